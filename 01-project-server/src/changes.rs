@@ -0,0 +1,216 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::database::AssetRecord;
+
+// Change Log
+// ----------
+//
+// Every change the project notices gets the next sequence number. Clients keep the number of the last change they saw as a
+// cursor, and a client that reconnects asks for everything after it. The last HISTORY_LEN changes are kept around for
+// that; a client further behind (or one whose cursor is from a history that was lost in a crash) has to start over by
+// listing the asset tree again.
+//
+// Only the next sequence number is saved to the Library folder, so numbers keep increasing across restarts: changes made
+// while the server was down are picked up by the startup scan and numbered after everything clients have already seen.
+//
+
+const CHANGE_LOG_FILE: &str = "change-log.json";
+const HISTORY_LEN: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+  Added,
+  // The asset's contents or meta file changed.
+  Modified,
+  Moved,
+  Removed,
+  // The asset's artifacts were written again, possibly because something it depends on changed.
+  Imported,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AssetChange {
+  pub seq: u64,
+  pub kind: ChangeKind,
+  pub guid: Uuid,
+  // Relative to the asset root.
+  pub path: PathBuf,
+  // Where a moved asset was before.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub from: Option<PathBuf>,
+  #[serde(rename = "type")]
+  pub asset_type: String,
+}
+
+// Which changes a client wants to hear about. Empty filters match everything.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChangeFilter {
+  // Only changes to assets inside this folder (either before or after a move).
+  #[serde(default)]
+  pub folder: Option<PathBuf>,
+  // Only changes to assets of these types ("texture", "folder", ...).
+  #[serde(default)]
+  pub types: Vec<String>,
+}
+
+impl ChangeFilter {
+  pub fn matches(&self, change: &AssetChange) -> bool {
+    let in_folder = |folder: &Path| {
+      change.path.starts_with(folder) || change.from.as_ref().is_some_and(|from| from.starts_with(folder))
+    };
+    self.folder.as_deref().is_none_or(in_folder) && (self.types.is_empty() || self.types.contains(&change.asset_type))
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChangeLogFile {
+  next_seq: u64,
+}
+
+type Listener = Box<dyn Fn(&AssetChange) + Send>;
+
+pub struct ChangeLog {
+  next_seq: u64,
+  history: VecDeque<AssetChange>,
+  listeners: Vec<Listener>,
+}
+
+impl ChangeLog {
+  pub fn load(library_path: &Path) -> ChangeLog {
+    let file: Option<ChangeLogFile> =
+      fs::read(library_path.join(CHANGE_LOG_FILE)).ok().and_then(|bytes| serde_json::from_slice(&bytes).ok());
+    ChangeLog { next_seq: file.map_or(1, |file| file.next_seq), history: VecDeque::new(), listeners: Vec::new() }
+  }
+
+  pub fn save(&self, library_path: &Path) -> io::Result<()> {
+    fs::create_dir_all(library_path)?;
+    let bytes = serde_json::to_vec(&ChangeLogFile { next_seq: self.next_seq })
+      .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    fs::write(library_path.join(CHANGE_LOG_FILE), bytes)
+  }
+
+  // The sequence number of the latest change, or the one before the first change this run if nothing changed yet.
+  pub fn cursor(&self) -> u64 {
+    self.next_seq - 1
+  }
+
+  // Calls `listener` with every change recorded from now on.
+  pub fn on_change<F>(&mut self, listener: F)
+  where
+    F: Fn(&AssetChange) + Send + 'static,
+  {
+    self.listeners.push(Box::new(listener));
+  }
+
+  pub fn record(&mut self, kind: ChangeKind, asset: &AssetRecord, from: Option<PathBuf>) {
+    let change = AssetChange {
+      seq: self.next_seq,
+      kind,
+      guid: asset.guid,
+      path: asset.path.clone(),
+      from,
+      asset_type: asset.asset_type.clone(),
+    };
+    self.next_seq += 1;
+    for listener in &self.listeners {
+      listener(&change);
+    }
+    if self.history.len() == HISTORY_LEN {
+      self.history.pop_front();
+    }
+    self.history.push_back(change);
+  }
+
+  // Everything recorded after `cursor`, or None if some of it is no longer known.
+  pub fn since(&self, cursor: u64) -> Option<Vec<AssetChange>> {
+    let oldest = self.history.front().map_or(self.next_seq, |change| change.seq);
+    if cursor + 1 < oldest || cursor > self.cursor() {
+      return None;
+    }
+    Some(self.history.iter().filter(|change| change.seq > cursor).cloned().collect())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::BTreeMap;
+  use std::time::SystemTime;
+  use super::*;
+
+  fn asset(path: &str) -> AssetRecord {
+    AssetRecord {
+      guid: Uuid::new_v4(),
+      path: PathBuf::from(path),
+      root: PathBuf::from("/project/Assets"),
+      asset_type: String::from("texture"),
+      is_folder: false,
+      size: 0,
+      modified: SystemTime::UNIX_EPOCH,
+      meta_modified: SystemTime::UNIX_EPOCH,
+      hash: String::new(),
+      settings_hash: String::new(),
+      labels: Vec::new(),
+      metadata: BTreeMap::new(),
+    }
+  }
+
+  fn seqs(changes: Option<Vec<AssetChange>>) -> Option<Vec<u64>> {
+    changes.map(|changes| changes.iter().map(|change| change.seq).collect())
+  }
+
+  #[test]
+  fn cursors_survive_a_restart() {
+    let library = tempfile::tempdir().unwrap();
+    let mut log = ChangeLog::load(library.path());
+    assert_eq!(log.cursor(), 0);
+    log.record(ChangeKind::Added, &asset("logo.png"), None);
+    log.record(ChangeKind::Imported, &asset("logo.png"), None);
+    let cursor = log.cursor();
+    assert_eq!(cursor, 2);
+    log.save(library.path()).unwrap();
+
+    // The history is gone, but numbering carries on after the last change clients saw.
+    let mut log = ChangeLog::load(library.path());
+    assert_eq!(log.cursor(), cursor);
+    assert_eq!(seqs(log.since(cursor)), Some(Vec::new()));
+    log.record(ChangeKind::Modified, &asset("logo.png"), None);
+    assert_eq!(seqs(log.since(cursor)), Some(vec![3]));
+    // Changes from before the restart can't be replayed.
+    assert_eq!(seqs(log.since(cursor - 1)), None);
+  }
+
+  #[test]
+  fn unreadable_logs_start_over() {
+    let library = tempfile::tempdir().unwrap();
+    fs::write(library.path().join(CHANGE_LOG_FILE), "not json").unwrap();
+    assert_eq!(ChangeLog::load(library.path()).cursor(), 0);
+  }
+
+  #[test]
+  fn cursors_from_the_future_are_refused() {
+    let mut log = ChangeLog::load(Path::new("/nonexistent"));
+    log.record(ChangeKind::Added, &asset("logo.png"), None);
+    assert_eq!(seqs(log.since(1)), Some(Vec::new()));
+    assert_eq!(seqs(log.since(2)), None);
+  }
+
+  #[test]
+  fn cursors_behind_the_history_expire() {
+    let mut log = ChangeLog::load(Path::new("/nonexistent"));
+    let logo = asset("logo.png");
+    for _ in 0..HISTORY_LEN + 5 {
+      log.record(ChangeKind::Modified, &logo, None);
+    }
+    // Changes 1 to 5 have been dropped, so only cursors from 5 on can be caught up.
+    assert_eq!(seqs(log.since(4)), None);
+    let caught_up = seqs(log.since(5)).unwrap();
+    assert_eq!(caught_up.len(), HISTORY_LEN);
+    assert_eq!((caught_up[0], caught_up[HISTORY_LEN - 1]), (6, HISTORY_LEN as u64 + 5));
+    assert_eq!(seqs(log.since(log.cursor() - 1)), Some(vec![log.cursor()]));
+  }
+}
@@ -0,0 +1,426 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use crate::error::ProjectError;
+use crate::ignore_rules::IgnoreRules;
+use crate::meta::{get_meta_file, is_meta_file, load_or_create_meta};
+
+// Asset Database
+// --------------
+//
+// An index of everything under the project's asset roots: GUID <-> path, what kind of asset it is, and enough about the
+// file (size, mtime, content hash) to tell whether it changed since the last scan. The index is saved to the project's
+// Library folder so a restart only has to look closely at files that were touched while the server was down.
+//
+// Asset paths are relative to the asset root they're in, since that's how assets refer to each other. If two roots hold
+// the same path, the root listed first wins and the other file is shadowed. Files matched by the project's ignore rules
+// are left out entirely.
+//
+
+pub const LIBRARY_FOLDER: &str = "Library";
+const DATABASE_FILE: &str = "asset-database.json";
+// Bump this whenever `AssetRecord` changes; an index with a different version is thrown away and rebuilt.
+const DATABASE_VERSION: u32 = 4;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AssetRecord {
+  pub guid: Uuid,
+  // Relative to the asset root.
+  pub path: PathBuf,
+  // Absolute path of the asset root the asset is in.
+  pub root: PathBuf,
+  // The name of the importer from the asset's meta file ("folder", "texture", ...).
+  pub asset_type: String,
+  pub is_folder: bool,
+  pub size: u64,
+  pub modified: SystemTime,
+  pub meta_modified: SystemTime,
+  // Hex SHA-256 of the file contents. Empty for folders.
+  pub hash: String,
+  // Hash of the importer settings in the meta file.
+  pub settings_hash: String,
+  // Labels and metadata from the meta file.
+  pub labels: Vec<String>,
+  pub metadata: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default)]
+pub struct ScanReport {
+  pub added: Vec<PathBuf>,
+  // Assets whose contents or meta file changed.
+  pub changed: Vec<PathBuf>,
+  pub removed: Vec<AssetRecord>,
+  pub unchanged: usize,
+  // Entries that couldn't be indexed. They're skipped; everything else is still scanned.
+  pub errors: Vec<ProjectError>,
+}
+
+// What a single refresh found out about an asset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Refresh {
+  Added,
+  Changed,
+  Unchanged,
+  // The path is matched by an ignore rule, so it isn't an asset.
+  Ignored,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DatabaseFile {
+  version: u32,
+  assets: Vec<AssetRecord>,
+}
+
+pub struct AssetDatabase {
+  roots: Vec<PathBuf>,
+  records: HashMap<PathBuf, AssetRecord>,
+  guids: HashMap<Uuid, PathBuf>,
+  ignore: IgnoreRules,
+}
+
+impl AssetDatabase {
+  // An empty index over the given asset roots, in priority order.
+  pub fn new(roots: &[PathBuf], ignore: IgnoreRules) -> AssetDatabase {
+    AssetDatabase { roots: roots.to_vec(), records: HashMap::new(), guids: HashMap::new(), ignore }
+  }
+
+  // Loads the saved index from the Library folder. A missing, unreadable or outdated index just means starting from
+  // scratch, since everything in it can be rebuilt from the asset roots.
+  pub fn load(roots: &[PathBuf], library_path: &Path, ignore: IgnoreRules) -> AssetDatabase {
+    let mut database = AssetDatabase::new(roots, ignore);
+    let database_path = library_path.join(DATABASE_FILE);
+    let file: DatabaseFile = match fs::read(&database_path).map(|bytes| serde_json::from_slice(&bytes)) {
+      Ok(Ok(file)) => file,
+      Ok(Err(err)) => {
+        eprintln!("Ignoring unreadable asset database {:?}: {}", database_path, err);
+        return database;
+      }
+      Err(_) => return database,
+    };
+    if file.version != DATABASE_VERSION {
+      eprintln!("Rebuilding asset database (saved with version {}, current version {}).", file.version, DATABASE_VERSION);
+      return database;
+    }
+    // Records from roots that were since removed from the manifest are dropped; the next scan re-adds anything still there.
+    for record in file.assets {
+      if database.roots.contains(&record.root) {
+        database.insert(record);
+      }
+    }
+    database
+  }
+
+  // Writes the index to the Library folder. The index is written to a temporary file first so a crash mid-write can't leave
+  // a truncated index behind.
+  pub fn save(&self, library_path: &Path) -> io::Result<()> {
+    fs::create_dir_all(library_path)?;
+    let mut assets: Vec<AssetRecord> = self.records.values().cloned().collect();
+    assets.sort_by(|a, b| a.path.cmp(&b.path));
+    let file = DatabaseFile { version: DATABASE_VERSION, assets };
+    let bytes = serde_json::to_vec(&file).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    let database_path = library_path.join(DATABASE_FILE);
+    let temp_path = database_path.with_extension("json.tmp");
+    fs::write(&temp_path, bytes)?;
+    fs::rename(&temp_path, &database_path)
+  }
+
+  // The asset roots, highest priority first.
+  pub fn roots(&self) -> &[PathBuf] {
+    &self.roots
+  }
+
+  pub fn len(&self) -> usize {
+    self.records.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.records.is_empty()
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &AssetRecord> {
+    self.records.values()
+  }
+
+  // Whether an absolute path is matched by the ignore rules, either itself or through a folder it's in. `is_dir` matters for
+  // folder-only patterns ("build/") and can't be looked up for paths that don't exist (yet).
+  pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+    self.ignore.is_ignored(path, is_dir)
+  }
+
+  // Picks up changes to the .theseusignore in `folder`. Rescan the folder afterwards to apply them.
+  pub fn reload_ignore_file(&mut self, folder: &Path) -> Option<ProjectError> {
+    self.ignore.load_folder(folder)
+  }
+
+  // Looks up an asset by its path, either absolute or relative to its asset root.
+  pub fn get(&self, path: &Path) -> Option<&AssetRecord> {
+    self.records.get(&self.relative(path))
+  }
+
+  pub fn get_by_guid(&self, guid: &Uuid) -> Option<&AssetRecord> {
+    self.guids.get(guid).and_then(|path| self.records.get(path))
+  }
+
+  pub fn path_for_guid(&self, guid: &Uuid) -> Option<&Path> {
+    self.guids.get(guid).map(|path| path.as_path())
+  }
+
+  pub fn guid_for_path(&self, path: &Path) -> Option<Uuid> {
+    self.get(path).map(|record| record.guid)
+  }
+
+  // The asset root an absolute path is in.
+  pub fn root_of(&self, path: &Path) -> Option<&Path> {
+    self.roots.iter().find(|root| path.starts_with(root)).map(|root| root.as_path())
+  }
+
+  // Absolute path of an asset given its path relative to an asset root. Paths that aren't indexed resolve against the first
+  // root they exist in, or the first root if they don't exist anywhere.
+  pub fn absolute(&self, relative_path: &Path) -> PathBuf {
+    if let Some(record) = self.records.get(relative_path) {
+      return record.root.join(relative_path);
+    }
+    self
+      .roots
+      .iter()
+      .map(|root| root.join(relative_path))
+      .find(|path| path.exists())
+      .unwrap_or_else(|| self.roots[0].join(relative_path))
+  }
+
+  // Path relative to its asset root. Paths that are already relative are returned as is.
+  pub fn relative(&self, path: &Path) -> PathBuf {
+    match self.root_of(path) {
+      Some(root) => path.strip_prefix(root).unwrap().to_path_buf(),
+      None => path.to_path_buf(),
+    }
+  }
+
+  // Brings the whole index up to date with the asset roots.
+  pub fn scan(&mut self) -> ScanReport {
+    let mut report = ScanReport::default();
+    let mut seen = HashSet::new();
+    for root in self.roots.clone() {
+      self.walk(&root, &mut seen, &mut report);
+    }
+    let gone: Vec<PathBuf> = self.records.keys().filter(|path| !seen.contains(*path)).cloned().collect();
+    report.removed.extend(gone.iter().filter_map(|path| self.forget(path)));
+    report
+  }
+
+  // Brings everything inside `folder` up to date: new files are added, touched files are re-read, and anything the index
+  // remembers that is no longer on disk is forgotten. Files whose size and mtime match the index are skipped without
+  // reading them.
+  pub fn scan_folder(&mut self, folder: &Path) -> ScanReport {
+    let mut report = ScanReport::default();
+    let mut seen = HashSet::new();
+    self.walk(folder, &mut seen, &mut report);
+
+    let root = self.root_of(folder).map(|root| root.to_path_buf());
+    let relative_folder = self.relative(folder);
+    let gone: Vec<PathBuf> = self
+      .records
+      .values()
+      .filter(|record| Some(&record.root) == root.as_ref() && record.path.starts_with(&relative_folder))
+      // The walk only sees what's inside the folder, not the folder itself.
+      .filter(|record| record.path != relative_folder && !seen.contains(&record.path))
+      .map(|record| record.path.clone())
+      .collect();
+    report.removed.extend(gone.iter().filter_map(|path| self.forget(path)));
+    report
+  }
+
+  fn walk(&mut self, dir: &Path, seen: &mut HashSet<PathBuf>, report: &mut ScanReport) {
+    if let Some(err) = self.ignore.load_folder(dir) {
+      report.errors.push(err);
+    }
+    let entries = match fs::read_dir(dir) {
+      Ok(entries) => entries,
+      Err(source) => {
+        report.errors.push(ProjectError::DirectoryUnreadable { path: dir.to_path_buf(), source });
+        return;
+      }
+    };
+    for entry in entries {
+      let path = match entry {
+        Ok(entry) => entry.path(),
+        Err(source) => {
+          report.errors.push(ProjectError::DirectoryUnreadable { path: dir.to_path_buf(), source });
+          continue;
+        }
+      };
+      if is_meta_file(&path) { continue; }
+      if path.to_str().is_none() {
+        report.errors.push(ProjectError::NonUtf8Path { path });
+        continue;
+      }
+      // Ignored folders aren't walked at all.
+      let is_dir = path.is_dir();
+      if self.ignore.is_ignored(&path, is_dir) { continue; }
+      let relative_path = self.relative(&path);
+      // A path already seen in a higher priority root shadows this one. A shadowed folder's contents can still add to it.
+      if seen.insert(relative_path.clone()) {
+        match self.refresh(&path) {
+          Ok(Refresh::Added) => report.added.push(relative_path),
+          Ok(Refresh::Changed) => report.changed.push(relative_path),
+          Ok(Refresh::Unchanged) => report.unchanged += 1,
+          Ok(Refresh::Ignored) => {}
+          Err(err) => report.errors.push(err),
+        }
+      }
+      if is_dir {
+        self.walk(&path, seen, report);
+      }
+    }
+  }
+
+  // Brings the index entry for a single asset up to date, creating its meta file if needed. `path` is either absolute or
+  // relative to an asset root.
+  pub fn refresh(&mut self, path: &Path) -> Result<Refresh, ProjectError> {
+    let path = if path.is_absolute() { path.to_path_buf() } else { self.absolute(path) };
+    let root = self.root_of(&path).ok_or_else(|| ProjectError::InvalidAssetPath { path: path.clone() })?.to_path_buf();
+    let relative_path = self.relative(&path);
+    if path.to_str().is_none() {
+      return Err(ProjectError::NonUtf8Path { path });
+    }
+    if self.is_ignored(&path, path.is_dir()) {
+      return Ok(Refresh::Ignored);
+    }
+    let previous = self.records.get(&relative_path);
+    if let Some(previous) = previous {
+      // Leave assets shadowed by a higher priority root alone.
+      let shadowed = previous.root != root && self.root_priority(&previous.root) < self.root_priority(&root);
+      if shadowed && previous.root.join(&relative_path).exists() {
+        return Ok(Refresh::Unchanged);
+      }
+    }
+
+    let unreadable = |source| ProjectError::FileUnreadable { path: path.clone(), source };
+    let metadata = fs::metadata(&path).map_err(unreadable)?;
+    let modified = metadata.modified().map_err(unreadable)?;
+    let size = if metadata.is_dir() { 0 } else { metadata.len() };
+    let meta_path = get_meta_file(&path)?;
+    let meta_modified = fs::metadata(&meta_path).and_then(|m| m.modified()).ok();
+
+    if let (Some(previous), Some(meta_modified)) = (previous, meta_modified) {
+      let same_file = previous.root == root && previous.size == size && previous.modified == modified;
+      if same_file && previous.meta_modified == meta_modified {
+        return Ok(Refresh::Unchanged);
+      }
+    }
+
+    let (meta, _) = load_or_create_meta(&path)?;
+    let hash = if metadata.is_dir() { String::new() } else { hash_file(&path).map_err(unreadable)? };
+    let meta_modified = fs::metadata(&meta_path)
+      .and_then(|m| m.modified())
+      .map_err(|source| ProjectError::MetaUnreadable { path: meta_path.clone(), source })?;
+    let record = AssetRecord {
+      guid: meta.guid,
+      path: relative_path,
+      root,
+      settings_hash: meta.importer.settings_hash(),
+      asset_type: meta.importer.name,
+      labels: meta.labels,
+      metadata: meta.metadata,
+      is_folder: metadata.is_dir(),
+      size,
+      modified,
+      meta_modified,
+      hash,
+    };
+
+    // Only a different hash or meta counts as a change; a touched but otherwise identical file just has its mtime updated.
+    let result = match previous {
+      None => Refresh::Added,
+      Some(previous)
+        if previous.hash != record.hash
+          || previous.guid != record.guid
+          || previous.root != record.root
+          || previous.asset_type != record.asset_type
+          || previous.settings_hash != record.settings_hash
+          || previous.labels != record.labels
+          || previous.metadata != record.metadata =>
+      {
+        Refresh::Changed
+      }
+      Some(_) => Refresh::Unchanged,
+    };
+    if let Some(previous) = previous {
+      if previous.guid != record.guid {
+        let previous_guid = previous.guid;
+        self.guids.remove(&previous_guid);
+      }
+    }
+    self.insert(record);
+    Ok(result)
+  }
+
+  fn root_priority(&self, root: &Path) -> usize {
+    self.roots.iter().position(|r| r == root).unwrap_or(usize::MAX)
+  }
+
+  // Moves an asset (and, for folders, everything inside it) to a new path, keeping GUIDs. Both paths are absolute.
+  pub fn rename(&mut self, from: &Path, to: &Path) {
+    let new_root = self.root_of(to).map(|root| root.to_path_buf());
+    let from = self.relative(from);
+    let to = self.relative(to);
+    let moved: Vec<PathBuf> = self.records.keys().filter(|path| path.starts_with(&from)).cloned().collect();
+    for old_path in moved {
+      let mut record = self.forget(&old_path).unwrap();
+      let suffix = old_path.strip_prefix(&from).unwrap();
+      record.path = if suffix.as_os_str().is_empty() { to.clone() } else { to.join(suffix) };
+      if let Some(new_root) = &new_root {
+        record.root = new_root.clone();
+      }
+      self.insert(record);
+    }
+  }
+
+  // Forgets an asset and, for folders, everything inside it. Returns the removed records.
+  pub fn remove(&mut self, path: &Path) -> Vec<AssetRecord> {
+    let root = self.root_of(path).map(|root| root.to_path_buf());
+    let path = self.relative(path);
+    let removed: Vec<PathBuf> = self
+      .records
+      .values()
+      .filter(|record| record.path.starts_with(&path) && root.as_ref().is_none_or(|root| *root == record.root))
+      .map(|record| record.path.clone())
+      .collect();
+    removed.iter().filter_map(|p| self.forget(p)).collect()
+  }
+
+  fn insert(&mut self, record: AssetRecord) {
+    if let Some(other_path) = self.guids.get(&record.guid) {
+      if *other_path != record.path {
+        eprintln!("Warning: {:?} has the same GUID as {:?} ({}).", record.path, other_path, record.guid);
+      }
+    }
+    self.guids.insert(record.guid, record.path.clone());
+    self.records.insert(record.path.clone(), record);
+  }
+
+  fn forget(&mut self, relative_path: &Path) -> Option<AssetRecord> {
+    let record = self.records.remove(relative_path)?;
+    if self.guids.get(&record.guid).map(|p| p.as_path()) == Some(relative_path) {
+      self.guids.remove(&record.guid);
+    }
+    Some(record)
+  }
+}
+
+pub fn hash_file(path: &Path) -> io::Result<String> {
+  let mut file = fs::File::open(path)?;
+  let mut hasher = Sha256::new();
+  let mut buffer = [0u8; 64 * 1024];
+  loop {
+    let read = file.read(&mut buffer)?;
+    if read == 0 { break; }
+    hasher.update(&buffer[..read]);
+  }
+  Ok(format!("{:x}", hasher.finalize()))
+}
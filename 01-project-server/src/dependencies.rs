@@ -0,0 +1,197 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use crate::database::AssetDatabase;
+use crate::importer::ImportPipeline;
+
+// Dependency Graph
+// ----------------
+//
+// Which assets refer to which, e.g. "prefabs/level.ron" -> "textures/spritesheet.png". Edges come from the dependencies
+// importers report, and are keyed by path relative to Assets since that's how assets refer to each other. A referenced
+// path doesn't have to exist, so it's possible to ask what a missing asset breaks.
+//
+
+#[derive(Debug, Default)]
+pub struct DependencyGraph {
+  dependencies: HashMap<PathBuf, BTreeSet<PathBuf>>,
+  dependents: HashMap<PathBuf, BTreeSet<PathBuf>>,
+}
+
+impl DependencyGraph {
+  // Rebuilds the graph from what the import pipeline recorded on its last imports.
+  pub fn build(database: &AssetDatabase, pipeline: &ImportPipeline) -> DependencyGraph {
+    let mut graph = DependencyGraph::default();
+    for (guid, record) in pipeline.records() {
+      if let Some(path) = database.path_for_guid(guid) {
+        graph.set_dependencies(path, record.dependencies.iter().cloned());
+      }
+    }
+    graph
+  }
+
+  // Replaces what `asset` depends on.
+  pub fn set_dependencies<I: IntoIterator<Item = PathBuf>>(&mut self, asset: &Path, dependencies: I) {
+    self.remove(asset);
+    let dependencies: BTreeSet<PathBuf> = dependencies.into_iter().collect();
+    if dependencies.is_empty() {
+      return;
+    }
+    for dependency in &dependencies {
+      self.dependents.entry(dependency.clone()).or_default().insert(asset.to_path_buf());
+    }
+    self.dependencies.insert(asset.to_path_buf(), dependencies);
+  }
+
+  // Forgets what `asset` depends on. Assets that depend on it keep their edges; they're broken now, not gone.
+  pub fn remove(&mut self, asset: &Path) {
+    if let Some(dependencies) = self.dependencies.remove(asset) {
+      for dependency in dependencies {
+        if let Some(dependents) = self.dependents.get_mut(&dependency) {
+          dependents.remove(asset);
+          if dependents.is_empty() {
+            self.dependents.remove(&dependency);
+          }
+        }
+      }
+    }
+  }
+
+  // Moves the outgoing edges of an asset (or of everything in a folder) to its new path. Incoming edges stay on the old
+  // path, because the files referring to it still say the old path.
+  pub fn rename(&mut self, from: &Path, to: &Path) {
+    let moved: Vec<PathBuf> = self.dependencies.keys().filter(|path| path.starts_with(from)).cloned().collect();
+    for old_path in moved {
+      let dependencies = self.dependencies.get(&old_path).cloned().unwrap_or_default();
+      self.remove(&old_path);
+      let suffix = old_path.strip_prefix(from).unwrap();
+      let new_path = if suffix.as_os_str().is_empty() { to.to_path_buf() } else { to.join(suffix) };
+      self.set_dependencies(&new_path, dependencies);
+    }
+  }
+
+  // What `asset` refers to directly.
+  pub fn dependencies_of(&self, asset: &Path) -> Vec<PathBuf> {
+    self.dependencies.get(asset).map(|paths| paths.iter().cloned().collect()).unwrap_or_default()
+  }
+
+  // What refers to `asset` directly.
+  pub fn dependents_of(&self, asset: &Path) -> Vec<PathBuf> {
+    self.dependents.get(asset).map(|paths| paths.iter().cloned().collect()).unwrap_or_default()
+  }
+
+  // What refers directly to `path` or, for a folder, to anything inside it.
+  pub fn dependents_within(&self, path: &Path) -> BTreeSet<PathBuf> {
+    self
+      .dependents
+      .iter()
+      .filter(|(dependency, _)| dependency.starts_with(path))
+      .flat_map(|(_, dependents)| dependents.iter().cloned())
+      .collect()
+  }
+
+  // Everything that refers to `asset`, directly or through other assets, nearest first. This is what breaks if `asset`
+  // changes.
+  pub fn all_dependents_of(&self, asset: &Path) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    let mut visited = BTreeSet::new();
+    visited.insert(asset.to_path_buf());
+    let mut queue: VecDeque<PathBuf> = VecDeque::new();
+    queue.push_back(asset.to_path_buf());
+    while let Some(path) = queue.pop_front() {
+      for dependent in self.dependents.get(&path).into_iter().flatten() {
+        if visited.insert(dependent.clone()) {
+          found.push(dependent.clone());
+          queue.push_back(dependent.clone());
+        }
+      }
+    }
+    found
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn paths(paths: &[&str]) -> Vec<PathBuf> {
+    paths.iter().map(PathBuf::from).collect()
+  }
+
+  fn depend(graph: &mut DependencyGraph, asset: &str, dependencies: &[&str]) {
+    graph.set_dependencies(Path::new(asset), paths(dependencies));
+  }
+
+  // level.ron -> ball.ron -> sprites/ball.png, with the level also using the sky.
+  fn graph() -> DependencyGraph {
+    let mut graph = DependencyGraph::default();
+    depend(&mut graph, "prefabs/level.ron", &["prefabs/ball.ron", "sprites/sky.png"]);
+    depend(&mut graph, "prefabs/ball.ron", &["sprites/ball.png"]);
+    graph
+  }
+
+  #[test]
+  fn dependents_are_found_directly_and_through_other_assets() {
+    let graph = graph();
+    assert_eq!(graph.dependents_of(Path::new("sprites/ball.png")), paths(&["prefabs/ball.ron"]));
+    let all = graph.all_dependents_of(Path::new("sprites/ball.png"));
+    assert_eq!(all, paths(&["prefabs/ball.ron", "prefabs/level.ron"]));
+    assert_eq!(graph.dependencies_of(Path::new("prefabs/level.ron")), paths(&["prefabs/ball.ron", "sprites/sky.png"]));
+    // Missing assets can be asked about too.
+    assert!(graph.dependents_of(Path::new("sprites/missing.png")).is_empty());
+  }
+
+  #[test]
+  fn cycles_end() {
+    let mut graph = graph();
+    depend(&mut graph, "sprites/ball.png", &["prefabs/level.ron"]);
+    let dependents = graph.all_dependents_of(Path::new("sprites/ball.png"));
+    assert_eq!(dependents, paths(&["prefabs/ball.ron", "prefabs/level.ron"]));
+  }
+
+  #[test]
+  fn dependents_within_a_folder() {
+    let mut graph = graph();
+    depend(&mut graph, "prefabs/menu.ron", &["sprites/ui/button.png"]);
+    let within = |path: &str| graph.dependents_within(Path::new(path)).into_iter().collect::<Vec<_>>();
+    assert_eq!(within("sprites"), paths(&["prefabs/ball.ron", "prefabs/level.ron", "prefabs/menu.ron"]));
+    assert_eq!(within("sprites/ui"), paths(&["prefabs/menu.ron"]));
+    assert_eq!(within("sprites/ball.png"), paths(&["prefabs/ball.ron"]));
+    // Whole path components only.
+    assert!(within("sprites/u").is_empty());
+  }
+
+  #[test]
+  fn reimports_replace_the_old_edges() {
+    let mut graph = graph();
+    depend(&mut graph, "prefabs/ball.ron", &["sprites/ball2.png"]);
+    assert!(graph.dependents_of(Path::new("sprites/ball.png")).is_empty());
+    assert!(graph.dependents_within(Path::new("sprites/ball.png")).is_empty());
+    assert_eq!(graph.dependents_of(Path::new("sprites/ball2.png")), paths(&["prefabs/ball.ron"]));
+
+    // An import without references leaves nothing behind.
+    depend(&mut graph, "prefabs/ball.ron", &[]);
+    assert!(graph.dependencies_of(Path::new("prefabs/ball.ron")).is_empty());
+    assert!(graph.dependents_of(Path::new("sprites/ball2.png")).is_empty());
+  }
+
+  #[test]
+  fn removed_assets_stay_depended_on() {
+    let mut graph = graph();
+    graph.remove(Path::new("prefabs/ball.ron"));
+    assert!(graph.dependencies_of(Path::new("prefabs/ball.ron")).is_empty());
+    assert!(graph.dependents_of(Path::new("sprites/ball.png")).is_empty());
+    // The level still refers to it, and is what breaks.
+    assert_eq!(graph.dependents_of(Path::new("prefabs/ball.ron")), paths(&["prefabs/level.ron"]));
+  }
+
+  #[test]
+  fn renames_move_the_outgoing_edges() {
+    let mut graph = graph();
+    graph.rename(Path::new("prefabs"), Path::new("things"));
+    assert_eq!(graph.dependencies_of(Path::new("things/ball.ron")), paths(&["sprites/ball.png"]));
+    assert!(graph.dependencies_of(Path::new("prefabs/ball.ron")).is_empty());
+    assert_eq!(graph.dependents_of(Path::new("sprites/ball.png")), paths(&["things/ball.ron"]));
+    // The level's file still says prefabs/ball.ron.
+    assert_eq!(graph.dependents_of(Path::new("prefabs/ball.ron")), paths(&["things/level.ron"]));
+  }
+}
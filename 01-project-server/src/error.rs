@@ -0,0 +1,149 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::lfs::LFS_FIX;
+use crate::lock::LockInfo;
+use crate::meta::META_FORMAT_VERSION;
+
+// Project Errors
+// --------------
+//
+// Everything that can go wrong opening a project or walking its assets. Each error carries the path it's about, so a bad
+// entry can be reported and skipped instead of taking the server down.
+//
+
+#[derive(Debug)]
+pub enum ProjectError {
+  // The folder has neither a theseus.toml nor an Assets folder.
+  NotAProject { path: PathBuf },
+  // A new project was to be created in a folder that already has files in it.
+  ProjectExists { path: PathBuf },
+  // The server was built from a checkout without Git LFS, so the template it would create a project from has pointer
+  // files in place of some of its assets.
+  TemplateIncomplete { path: PathBuf, template: String, pointers: Vec<String> },
+  // Another process holds the project's lock file; `holder` says who, when the lock file could be read.
+  ProjectLocked { path: PathBuf, holder: Option<LockInfo> },
+  ManifestUnreadable { path: PathBuf, source: io::Error },
+  ManifestInvalid { path: PathBuf, message: String },
+  ManifestUnwritable { path: PathBuf, source: io::Error },
+  // An asset root listed in the manifest doesn't exist or isn't a folder.
+  AssetRootMissing { path: PathBuf },
+  DirectoryUnreadable { path: PathBuf, source: io::Error },
+  FileUnreadable { path: PathBuf, source: io::Error },
+  FileUnwritable { path: PathBuf, source: io::Error },
+  // Asset paths end up in meta files, the index and the API, which all need them to be valid UTF-8.
+  NonUtf8Path { path: PathBuf },
+  // A path with no file name (such as "..") where an asset was expected.
+  InvalidAssetPath { path: PathBuf },
+  // Asks for an asset the project doesn't have.
+  AssetNotFound { path: PathBuf },
+  // Something is already at the path an asset was to be moved to.
+  AssetExists { path: PathBuf },
+  // Moving or deleting an asset on disk failed.
+  AssetUnwritable { path: PathBuf, source: io::Error },
+  // A file changed on disk while the server was in the middle of rewriting it.
+  FileChanged { path: PathBuf },
+  MetaUnreadable { path: PathBuf, source: io::Error },
+  MetaInvalid { path: PathBuf, message: String },
+  // A meta file written by a newer version of the server.
+  MetaVersionUnsupported { path: PathBuf, version: u32 },
+  MetaUnwritable { path: PathBuf, source: io::Error },
+  // A .theseusignore with lines that aren't valid patterns; the valid lines still apply.
+  IgnoreFileInvalid { path: PathBuf, message: String },
+}
+
+impl ProjectError {
+  // The file or folder the error is about.
+  pub fn path(&self) -> &Path {
+    match self {
+      ProjectError::NotAProject { path }
+      | ProjectError::ProjectExists { path }
+      | ProjectError::TemplateIncomplete { path, .. }
+      | ProjectError::ProjectLocked { path, .. }
+      | ProjectError::ManifestUnreadable { path, .. }
+      | ProjectError::ManifestInvalid { path, .. }
+      | ProjectError::ManifestUnwritable { path, .. }
+      | ProjectError::AssetRootMissing { path }
+      | ProjectError::DirectoryUnreadable { path, .. }
+      | ProjectError::FileUnreadable { path, .. }
+      | ProjectError::FileUnwritable { path, .. }
+      | ProjectError::NonUtf8Path { path }
+      | ProjectError::InvalidAssetPath { path }
+      | ProjectError::AssetNotFound { path }
+      | ProjectError::AssetExists { path }
+      | ProjectError::AssetUnwritable { path, .. }
+      | ProjectError::FileChanged { path }
+      | ProjectError::MetaUnreadable { path, .. }
+      | ProjectError::MetaInvalid { path, .. }
+      | ProjectError::MetaVersionUnsupported { path, .. }
+      | ProjectError::MetaUnwritable { path, .. }
+      | ProjectError::IgnoreFileInvalid { path, .. } => path,
+    }
+  }
+}
+
+impl fmt::Display for ProjectError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ProjectError::NotAProject { path } => {
+        write!(f, "{:?} is not a project: it has neither a theseus.toml nor an Assets folder", path)
+      }
+      ProjectError::ProjectExists { path } => write!(f, "{:?}: folder is not empty", path),
+      ProjectError::TemplateIncomplete { path, template, pointers } => write!(
+        f,
+        "{:?}: the {} template was built into the server from Git LFS pointers instead of the files they stand for \
+         ({}); {}, then rebuild the server",
+        path,
+        template,
+        pointers.join(", "),
+        LFS_FIX
+      ),
+      ProjectError::ProjectLocked { path, holder: Some(holder) } => {
+        write!(f, "{:?}: project is in use by `{}` (pid {})", path, holder.command, holder.pid)?;
+        if let Some(address) = holder.address {
+          write!(f, ", serving the API on {}", address)?;
+        }
+        Ok(())
+      }
+      ProjectError::ProjectLocked { path, holder: None } => write!(f, "{:?}: project is in use by another process", path),
+      ProjectError::ManifestUnreadable { path, source } => write!(f, "{:?}: can't read project manifest: {}", path, source),
+      ProjectError::ManifestInvalid { path, message } => write!(f, "{:?}: invalid project manifest: {}", path, message),
+      ProjectError::ManifestUnwritable { path, source } => write!(f, "{:?}: can't write project manifest: {}", path, source),
+      ProjectError::AssetRootMissing { path } => write!(f, "{:?}: asset root does not exist", path),
+      ProjectError::DirectoryUnreadable { path, source } => write!(f, "{:?}: can't read folder: {}", path, source),
+      ProjectError::FileUnreadable { path, source } => write!(f, "{:?}: can't read file: {}", path, source),
+      ProjectError::FileUnwritable { path, source } => write!(f, "{:?}: can't write file: {}", path, source),
+      ProjectError::NonUtf8Path { path } => write!(f, "{:?}: path is not valid UTF-8, skipping", path),
+      ProjectError::InvalidAssetPath { path } => write!(f, "{:?}: not a valid asset path", path),
+      ProjectError::AssetNotFound { path } => write!(f, "{:?}: no such asset", path),
+      ProjectError::AssetExists { path } => write!(f, "{:?}: already exists", path),
+      ProjectError::AssetUnwritable { path, source } => write!(f, "{:?}: can't change asset: {}", path, source),
+      ProjectError::FileChanged { path } => write!(f, "{:?}: changed on disk while being rewritten, try again", path),
+      ProjectError::MetaUnreadable { path, source } => write!(f, "{:?}: can't read meta file: {}", path, source),
+      ProjectError::MetaInvalid { path, message } => write!(f, "{:?}: invalid meta file: {}", path, message),
+      ProjectError::MetaVersionUnsupported { path, version } => write!(
+        f,
+        "{:?}: meta file format version {} is newer than supported version {}",
+        path, version, META_FORMAT_VERSION
+      ),
+      ProjectError::MetaUnwritable { path, source } => write!(f, "{:?}: can't write meta file: {}", path, source),
+      ProjectError::IgnoreFileInvalid { path, message } => write!(f, "{:?}: invalid ignore file: {}", path, message),
+    }
+  }
+}
+
+impl std::error::Error for ProjectError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      ProjectError::ManifestUnreadable { source, .. }
+      | ProjectError::ManifestUnwritable { source, .. }
+      | ProjectError::DirectoryUnreadable { source, .. }
+      | ProjectError::FileUnreadable { source, .. }
+      | ProjectError::FileUnwritable { source, .. }
+      | ProjectError::AssetUnwritable { source, .. }
+      | ProjectError::MetaUnreadable { source, .. }
+      | ProjectError::MetaUnwritable { source, .. } => Some(source),
+      _ => None,
+    }
+  }
+}
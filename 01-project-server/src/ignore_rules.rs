@@ -0,0 +1,183 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use crate::error::ProjectError;
+
+// Ignore Rules
+// ------------
+//
+// Which files under the asset roots aren't assets at all: editor swap files, OS clutter, VCS folders and anything listed
+// in a .theseusignore. .theseusignore files use .gitignore syntax and can sit at the project root or in any folder under
+// an asset root; rules in a deeper file win over shallower ones, and any file can re-include a default with "!pattern".
+// Ignored files get no meta file, aren't indexed and don't produce watcher events.
+//
+
+pub const IGNORE_FILE: &str = ".theseusignore";
+
+const DEFAULT_IGNORES: &[&str] = &[
+  // OS clutter.
+  ".DS_Store",
+  "._*",
+  "Thumbs.db",
+  "desktop.ini",
+  // Editor backup, swap and lock files.
+  "*~",
+  "*.swp",
+  "*.swo",
+  "*.swx",
+  ".#*",
+  "#*#",
+  "*.tmp",
+  "*.bak",
+  // Version control.
+  ".git/",
+  ".svn/",
+  ".hg/",
+  ".gitignore",
+  ".gitattributes",
+  ".gitkeep",
+  // The ignore files themselves.
+  IGNORE_FILE,
+];
+
+pub struct IgnoreRules {
+  defaults: Gitignore,
+  // Loaded .theseusignore files by the folder they're in.
+  files: BTreeMap<PathBuf, Gitignore>,
+}
+
+impl IgnoreRules {
+  // The built-in rules plus the .theseusignore at the project root, if there is one. Ignore files in asset folders are
+  // picked up with `load_folder` as the folders are walked.
+  pub fn load(project_path: &Path) -> (IgnoreRules, Vec<ProjectError>) {
+    let mut builder = GitignoreBuilder::new("");
+    for pattern in DEFAULT_IGNORES {
+      builder.add_line(None, pattern).expect("built-in ignore patterns are valid");
+    }
+    let defaults = builder.build().expect("built-in ignore patterns are valid");
+    let mut rules = IgnoreRules { defaults, files: BTreeMap::new() };
+    let project_path = project_path.canonicalize().unwrap_or_else(|_| project_path.to_path_buf());
+    let errors = rules.load_folder(&project_path).into_iter().collect();
+    (rules, errors)
+  }
+
+  // (Re)loads the .theseusignore in `folder`, or forgets it if it's gone.
+  pub fn load_folder(&mut self, folder: &Path) -> Option<ProjectError> {
+    let ignore_path = folder.join(IGNORE_FILE);
+    if !ignore_path.is_file() {
+      self.files.remove(folder);
+      return None;
+    }
+    // Invalid lines are reported, but the rest of the file still applies.
+    let (gitignore, err) = Gitignore::new(&ignore_path);
+    self.files.insert(folder.to_path_buf(), gitignore);
+    err.map(|err| ProjectError::IgnoreFileInvalid { path: ignore_path, message: err.to_string() })
+  }
+
+  // Whether `path` (absolute) or any folder it's in is ignored.
+  pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+    // Deeper folders sort after the folders they're in, so walking backwards visits the most specific rules first.
+    for (folder, gitignore) in self.files.iter().rev() {
+      if !path.starts_with(folder) || path == folder { continue; }
+      match gitignore.matched_path_or_any_parents(path, is_dir) {
+        Match::Ignore(_) => return true,
+        Match::Whitelist(_) => return false,
+        Match::None => {}
+      }
+    }
+    self.defaults.matched_path_or_any_parents(path, is_dir).is_ignore()
+  }
+}
+
+// Whether the path is a .theseusignore file.
+pub fn is_ignore_file(path: &Path) -> bool {
+  path.file_name().is_some_and(|name| name == IGNORE_FILE)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+  use tempfile::TempDir;
+  use super::*;
+
+  // A project folder with the given .theseusignore files, by the folder they're in ("" for the project root).
+  fn project(ignore_files: &[(&str, &str)]) -> (TempDir, PathBuf) {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().canonicalize().unwrap();
+    fs::create_dir_all(root.join("Assets")).unwrap();
+    for (folder, contents) in ignore_files {
+      fs::create_dir_all(root.join(folder)).unwrap();
+      fs::write(root.join(folder).join(IGNORE_FILE), contents).unwrap();
+    }
+    (dir, root)
+  }
+
+  // Loads the rules the way the database does, root first and then the asset folders.
+  fn load(root: &Path, folders: &[&str]) -> IgnoreRules {
+    let (mut rules, errors) = IgnoreRules::load(root);
+    assert!(errors.is_empty(), "{:?}", errors);
+    for folder in folders {
+      assert!(rules.load_folder(&root.join(folder)).is_none());
+    }
+    rules
+  }
+
+  fn ignored(rules: &IgnoreRules, root: &Path, path: &str) -> bool {
+    rules.is_ignored(&root.join(path.trim_end_matches('/')), path.ends_with('/'))
+  }
+
+  #[test]
+  fn clutter_is_ignored_by_default() {
+    let (_dir, root) = project(&[]);
+    let rules = load(&root, &[]);
+    for path in ["Assets/.DS_Store", "Assets/ui/Thumbs.db", "Assets/level.ron~", "Assets/.ball.ron.swp", "Assets/a.tmp"] {
+      assert!(ignored(&rules, &root, path), "{}", path);
+    }
+    // Everything inside a version control folder, and the ignore files themselves.
+    for path in ["Assets/.git/", "Assets/.git/config", "Assets/.gitkeep", "Assets/ui/.theseusignore"] {
+      assert!(ignored(&rules, &root, path), "{}", path);
+    }
+    for path in ["Assets/logo.png", "Assets/ui/", "Assets/swp.ron", "Assets/git/config"] {
+      assert!(!ignored(&rules, &root, path), "{}", path);
+    }
+  }
+
+  #[test]
+  fn deeper_ignore_files_win() {
+    let root_rules = "*.psd\nbuild/\n!keep.tmp\n";
+    let (_dir, root) = project(&[("", root_rules), ("Assets/art", "!*.psd\nsketches/\n")]);
+    let rules = load(&root, &["Assets", "Assets/art"]);
+    assert!(ignored(&rules, &root, "Assets/logo.psd"));
+    assert!(ignored(&rules, &root, "Assets/build/level.ron"));
+    // Re-included further down, and one of the defaults re-included at the root.
+    assert!(!ignored(&rules, &root, "Assets/art/logo.psd"));
+    assert!(!ignored(&rules, &root, "Assets/keep.tmp"));
+    assert!(ignored(&rules, &root, "Assets/other.tmp"));
+    // The folder's own rules only apply inside it.
+    assert!(ignored(&rules, &root, "Assets/art/sketches/"));
+    assert!(!ignored(&rules, &root, "Assets/sketches/"));
+  }
+
+  #[test]
+  fn removed_ignore_files_are_forgotten() {
+    let (_dir, root) = project(&[("Assets/art", "*.psd\n")]);
+    let mut rules = load(&root, &["Assets/art"]);
+    assert!(ignored(&rules, &root, "Assets/art/logo.psd"));
+    fs::remove_file(root.join("Assets/art").join(IGNORE_FILE)).unwrap();
+    assert!(rules.load_folder(&root.join("Assets/art")).is_none());
+    assert!(!ignored(&rules, &root, "Assets/art/logo.psd"));
+  }
+
+  #[test]
+  fn invalid_lines_are_reported_and_the_rest_applies() {
+    let (_dir, root) = project(&[("", "*.psd\n[z-a].ron\n")]);
+    let (rules, errors) = IgnoreRules::load(&root);
+    match &errors[..] {
+      [ProjectError::IgnoreFileInvalid { path, .. }] => assert_eq!(path, &root.join(IGNORE_FILE)),
+      other => panic!("{:?}", other),
+    }
+    assert!(ignored(&rules, &root, "Assets/logo.psd"));
+    assert!(!ignored(&rules, &root, "Assets/level.ron"));
+  }
+}
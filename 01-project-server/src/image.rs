@@ -0,0 +1,214 @@
+use std::fmt;
+
+// Images
+// ------
+//
+// Just enough image handling for the server to draw previews on the CPU, with no window or GPU around: decoding PNGs of
+// any color type into 8-bit RGBA, resizing, drawing boxes and numbers, and encoding the result as a PNG again.
+//
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+  pub width: u32,
+  pub height: u32,
+  // RGBA, row by row from the top.
+  pub pixels: Vec<[u8; 4]>,
+}
+
+#[derive(Debug)]
+pub enum ImageError {
+  Decode(png::DecodingError),
+  Encode(png::EncodingError),
+}
+
+impl fmt::Display for ImageError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ImageError::Decode(err) => write!(f, "can't decode PNG: {}", err),
+      ImageError::Encode(err) => write!(f, "can't encode PNG: {}", err),
+    }
+  }
+}
+
+impl std::error::Error for ImageError {}
+
+// 3x5 pixel digits, one row per byte with the leftmost pixel in the highest of three bits.
+const DIGITS: [[u8; 5]; 10] = [
+  [0b111, 0b101, 0b101, 0b101, 0b111],
+  [0b010, 0b110, 0b010, 0b010, 0b111],
+  [0b111, 0b001, 0b111, 0b100, 0b111],
+  [0b111, 0b001, 0b011, 0b001, 0b111],
+  [0b101, 0b101, 0b111, 0b001, 0b001],
+  [0b111, 0b100, 0b111, 0b001, 0b111],
+  [0b111, 0b100, 0b111, 0b101, 0b111],
+  [0b111, 0b001, 0b010, 0b010, 0b010],
+  [0b111, 0b101, 0b111, 0b101, 0b111],
+  [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+const DIGIT_WIDTH: u32 = 3;
+const DIGIT_HEIGHT: u32 = 5;
+
+impl Image {
+  pub fn new(width: u32, height: u32, color: [u8; 4]) -> Image {
+    Image { width, height, pixels: vec![color; width as usize * height as usize] }
+  }
+
+  pub fn decode_png(bytes: &[u8]) -> Result<Image, ImageError> {
+    let mut decoder = png::Decoder::new(bytes);
+    // Palettes, low bit depths and transparency chunks are expanded and 16-bit channels stripped, which leaves 8-bit
+    // gray, gray and alpha, RGB or RGBA.
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(ImageError::Decode)?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut buffer).map_err(ImageError::Decode)?;
+    let samples = &buffer[..frame.buffer_size()];
+    let pixels = match frame.color_type {
+      png::ColorType::Grayscale => samples.iter().map(|&v| [v, v, v, 255]).collect(),
+      png::ColorType::GrayscaleAlpha => samples.chunks_exact(2).map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+      png::ColorType::Rgb => samples.chunks_exact(3).map(|p| [p[0], p[1], p[2], 255]).collect(),
+      png::ColorType::Rgba => samples.chunks_exact(4).map(|p| [p[0], p[1], p[2], p[3]]).collect(),
+      png::ColorType::Indexed => unreachable!("palettes are expanded by the decoder"),
+    };
+    Ok(Image { width: frame.width, height: frame.height, pixels })
+  }
+
+  // The width and height of a PNG, without decoding it.
+  pub fn png_size(bytes: &[u8]) -> Result<(u32, u32), ImageError> {
+    let reader = png::Decoder::new(bytes).read_info().map_err(ImageError::Decode)?;
+    Ok((reader.info().width, reader.info().height))
+  }
+
+  pub fn encode_png(&self) -> Result<Vec<u8>, ImageError> {
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, self.width, self.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(ImageError::Encode)?;
+    writer.write_image_data(&self.pixels.concat()).map_err(ImageError::Encode)?;
+    writer.finish().map_err(ImageError::Encode)?;
+    Ok(bytes)
+  }
+
+  pub fn get(&self, x: u32, y: u32) -> [u8; 4] {
+    self.pixels[(y * self.width + x) as usize]
+  }
+
+  // Copies `image` into this one with its top left corner at (x, y), replacing what's there.
+  pub fn blit(&mut self, image: &Image, x: u32, y: u32) {
+    for row in 0..image.height.min(self.height.saturating_sub(y)) {
+      let width = image.width.min(self.width.saturating_sub(x)) as usize;
+      let from = (row * image.width) as usize;
+      let to = ((y + row) * self.width + x) as usize;
+      self.pixels[to..to + width].copy_from_slice(&image.pixels[from..from + width]);
+    }
+  }
+
+  // Draws `color` over the pixel, blending by the color's alpha. Pixels outside the image are ignored.
+  pub fn blend(&mut self, x: u32, y: u32, color: [u8; 4]) {
+    if x >= self.width || y >= self.height {
+      return;
+    }
+    let pixel = &mut self.pixels[(y * self.width + x) as usize];
+    // The pixel's own alpha is weighed in, so drawing on a transparent pixel doesn't darken the color.
+    let (alpha, below) = (color[3] as u32, pixel[3] as u32 * (255 - color[3] as u32) / 255);
+    let total = alpha + below;
+    if total == 0 {
+      return;
+    }
+    for c in 0..3 {
+      pixel[c] = ((color[c] as u32 * alpha + pixel[c] as u32 * below) / total) as u8;
+    }
+    pixel[3] = total as u8;
+  }
+
+  // A copy scaled to `width` by `height`. Shrinking averages the pixels each new pixel covers, weighing colors by their
+  // alpha so transparent pixels don't darken the edges; growing repeats pixels, which keeps pixel art sharp.
+  pub fn resize(&self, width: u32, height: u32) -> Image {
+    let mut resized = Image::new(width, height, [0, 0, 0, 0]);
+    let span = |i: u32, from: u32, to: u32| {
+      let start = (i as u64 * from as u64 / to as u64) as u32;
+      let end = (((i + 1) as u64 * from as u64 / to as u64) as u32).max(start + 1);
+      start..end
+    };
+    for y in 0..height {
+      let rows = span(y, self.height, height);
+      for x in 0..width {
+        let (mut color, mut alpha, mut count) = ([0u64; 3], 0u64, 0u64);
+        for sy in rows.clone() {
+          for sx in span(x, self.width, width) {
+            let pixel = self.get(sx, sy);
+            for c in 0..3 {
+              color[c] += pixel[c] as u64 * pixel[3] as u64;
+            }
+            alpha += pixel[3] as u64;
+            count += 1;
+          }
+        }
+        // Fully transparent stays transparent black.
+        if alpha == 0 {
+          continue;
+        }
+        let pixel = &mut resized.pixels[(y * width + x) as usize];
+        for c in 0..3 {
+          pixel[c] = (color[c] / alpha) as u8;
+        }
+        pixel[3] = (alpha / count) as u8;
+      }
+    }
+    resized
+  }
+
+  // A copy scaled down to fit within `size` by `size`, or up by a whole factor if it's smaller than that.
+  pub fn fit(&self, size: u32) -> Image {
+    let longest = self.width.max(self.height).max(1);
+    if longest <= size {
+      let factor = size / longest;
+      return self.resize(self.width * factor, self.height * factor);
+    }
+    let scale = |side: u32| ((side as u64 * size as u64 + longest as u64 / 2) / longest as u64).max(1) as u32;
+    self.resize(scale(self.width), scale(self.height))
+  }
+
+  pub fn fill_rect(&mut self, x: u32, y: u32, width: u32, height: u32, color: [u8; 4]) {
+    for py in y..y.saturating_add(height).min(self.height) {
+      for px in x..x.saturating_add(width).min(self.width) {
+        self.blend(px, py, color);
+      }
+    }
+  }
+
+  // A one pixel outline just inside the rectangle.
+  pub fn outline_rect(&mut self, x: u32, y: u32, width: u32, height: u32, color: [u8; 4]) {
+    if width == 0 || height == 0 {
+      return;
+    }
+    self.fill_rect(x, y, width, 1, color);
+    if height > 1 {
+      self.fill_rect(x, y + height - 1, width, 1, color);
+    }
+    self.fill_rect(x, y + 1, 1, height.saturating_sub(2), color);
+    if width > 1 {
+      self.fill_rect(x + width - 1, y + 1, 1, height.saturating_sub(2), color);
+    }
+  }
+
+  // Draws a number with its top left corner at (x, y), each font pixel `scale` pixels wide, on a backdrop so it reads on
+  // any image. Returns the width and height taken up.
+  pub fn draw_number(&mut self, x: u32, y: u32, number: usize, scale: u32, color: [u8; 4], backdrop: [u8; 4]) -> (u32, u32) {
+    let digits: Vec<usize> = number.to_string().bytes().map(|digit| (digit - b'0') as usize).collect();
+    let advance = (DIGIT_WIDTH + 1) * scale;
+    let (width, height) = (digits.len() as u32 * advance + scale, (DIGIT_HEIGHT + 2) * scale);
+    self.fill_rect(x, y, width, height, backdrop);
+    for (i, &digit) in digits.iter().enumerate() {
+      let left = x + scale + i as u32 * advance;
+      for (row, bits) in DIGITS[digit].iter().enumerate() {
+        for column in 0..DIGIT_WIDTH {
+          if bits & (1 << (DIGIT_WIDTH - 1 - column)) != 0 {
+            self.fill_rect(left + column * scale, y + (row as u32 + 1) * scale, scale, scale, color);
+          }
+        }
+      }
+    }
+    (width, height)
+  }
+}
@@ -0,0 +1,508 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::database::{AssetDatabase, AssetRecord};
+use crate::error::ProjectError;
+use crate::lfs::{self, LFS_FIX};
+use crate::meta::{get_meta_file, Meta};
+
+mod atlas;
+mod font;
+mod ron_file;
+mod texture;
+
+pub use atlas::AtlasImporter;
+pub use font::FontImporter;
+pub use ron_file::{find_file_references, find_values, FileReference, RonImporter};
+pub use texture::TextureImporter;
+
+// Importers
+// ---------
+//
+// An importer turns a source asset plus the settings in its meta file into processed artifacts under
+// Library/Artifacts/<guid>/. The pipeline remembers what each asset was last imported from, so an asset is only imported
+// again when its contents, its import settings or the importer itself changed.
+//
+
+pub const ARTIFACTS_FOLDER: &str = "Artifacts";
+const IMPORT_RECORDS_FILE: &str = "import-records.json";
+// How many leading bytes of a file importers get to look at when detecting its content type.
+const DETECT_HEADER_LEN: usize = 16;
+
+pub trait Importer: Send + Sync {
+  // The name meta files use to pick this importer.
+  fn name(&self) -> &'static str;
+
+  // Bump to have every asset this importer handles imported again.
+  fn version(&self) -> u32 {
+    1
+  }
+
+  // Lowercase file extensions (without the dot) this importer handles.
+  fn extensions(&self) -> &'static [&'static str] {
+    &[]
+  }
+
+  // Whether this importer recognizes a file from its first bytes, for files whose extension doesn't give them away.
+  fn detect(&self, _header: &[u8]) -> bool {
+    false
+  }
+
+  // Processes the source asset, writing artifacts through the context. Returns the names of the artifacts written.
+  fn import(&self, context: &ImportContext) -> Result<Vec<String>, ImportError>;
+
+  // Other assets the source refers to, as paths relative to Assets. When one of them changes the source is imported again.
+  fn dependencies(&self, _context: &ImportContext) -> Result<Vec<PathBuf>, ImportError> {
+    Ok(Vec::new())
+  }
+
+  // Artifacts that stand in for a file next to the asset (see Generated Files), as the artifact's name and the file's
+  // extension.
+  fn generated_files(&self) -> &'static [(&'static str, &'static str)] {
+    &[]
+  }
+}
+
+pub struct ImportContext<'a> {
+  // The asset root the source is in. Dependencies are relative to it.
+  pub assets_path: &'a Path,
+  pub source_path: &'a Path,
+  pub meta: &'a Meta,
+  pub output_path: &'a Path,
+  pub registry: &'a ImporterRegistry,
+}
+
+impl<'a> ImportContext<'a> {
+  pub fn read_source(&self) -> Result<Vec<u8>, ImportError> {
+    let bytes = fs::read(self.source_path)?;
+    if lfs::is_pointer(&bytes) {
+      return Err(ImportError::LfsPointer);
+    }
+    Ok(bytes)
+  }
+
+  // Writes an artifact to the asset's output folder and returns its name, for collecting into `import`'s return value.
+  pub fn write_artifact(&self, name: &str, contents: &[u8]) -> Result<String, ImportError> {
+    fs::write(self.output_path.join(name), contents)?;
+    Ok(String::from(name))
+  }
+
+  pub fn write_ron_artifact<T: Serialize>(&self, name: &str, value: &T) -> Result<String, ImportError> {
+    let pretty = ron::ser::PrettyConfig::new().with_indentor(String::from("  "));
+    let contents = ron::ser::to_string_pretty(value, pretty).map_err(|err| ImportError::Invalid(err.to_string()))?;
+    self.write_artifact(name, contents.as_bytes())
+  }
+}
+
+// Generated Files
+// ---------------
+//
+// Some artifacts stand in for a file next to their asset, so RON files have a path to refer to them by. A texture with
+// sprite settings generates its sprite sheet: "textures/pong.png" makes "textures/pong.ron". An atlas folder generates
+// its texture, sheet and name table the same way. The files only exist as artifacts, so importers never write into the
+// asset roots. A reference to one counts as a reference to the asset that generates it, and packs ship the artifact
+// under the file's path.
+//
+
+// Where a generated file with the given extension appears to be, next to the asset at `asset_path`.
+pub fn generated_file_path(asset_path: &Path, is_folder: bool, extension: &str) -> PathBuf {
+  if is_folder {
+    let mut name = asset_path.as_os_str().to_os_string();
+    name.push(format!(".{}", extension));
+    PathBuf::from(name)
+  } else {
+    asset_path.with_extension(extension)
+  }
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+  Io(io::Error),
+  // The source file isn't what the importer expected.
+  Invalid(String),
+  // The import settings in the meta file can't be used.
+  Settings(String),
+  // The source refers to assets that don't exist.
+  MissingDependencies(Vec<PathBuf>),
+  // The source is a Git LFS pointer, checked out without Git LFS.
+  LfsPointer,
+  // The source's meta file couldn't be read.
+  Project(ProjectError),
+}
+
+impl fmt::Display for ImportError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ImportError::Io(err) => write!(f, "{}", err),
+      ImportError::Invalid(message) => write!(f, "invalid source: {}", message),
+      ImportError::Settings(message) => write!(f, "invalid import settings: {}", message),
+      ImportError::MissingDependencies(paths) => write!(f, "missing dependencies: {:?}", paths),
+      ImportError::LfsPointer => write!(f, "a Git LFS pointer, not the file itself: {}", LFS_FIX),
+      ImportError::Project(err) => write!(f, "{}", err),
+    }
+  }
+}
+
+impl std::error::Error for ImportError {}
+
+impl From<io::Error> for ImportError {
+  fn from(err: io::Error) -> ImportError {
+    ImportError::Io(err)
+  }
+}
+
+impl From<ProjectError> for ImportError {
+  fn from(err: ProjectError) -> ImportError {
+    ImportError::Project(err)
+  }
+}
+
+// Importer Registry
+// -----------------
+//
+pub struct ImporterRegistry {
+  importers: Vec<Box<dyn Importer>>,
+}
+
+impl Default for ImporterRegistry {
+  // A registry with the built-in importers.
+  fn default() -> ImporterRegistry {
+    let mut registry = ImporterRegistry::new();
+    registry.register(Box::new(TextureImporter));
+    registry.register(Box::new(FontImporter));
+    registry.register(Box::new(RonImporter));
+    registry.register(Box::new(AtlasImporter));
+    registry
+  }
+}
+
+impl ImporterRegistry {
+  // An empty registry.
+  pub fn new() -> ImporterRegistry {
+    ImporterRegistry { importers: Vec::new() }
+  }
+
+  // Registers an importer. An importer registered later wins over earlier ones with the same name or extension.
+  pub fn register(&mut self, importer: Box<dyn Importer>) {
+    self.importers.insert(0, importer);
+  }
+
+  pub fn by_name(&self, name: &str) -> Option<&dyn Importer> {
+    self.importers.iter().find(|importer| importer.name() == name).map(|importer| importer.as_ref())
+  }
+
+  pub fn by_extension(&self, path: &Path) -> Option<&dyn Importer> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    self.importers.iter().find(|importer| importer.extensions().contains(&ext.as_str())).map(|importer| importer.as_ref())
+  }
+
+  pub fn by_content(&self, path: &Path) -> Option<&dyn Importer> {
+    let mut header = Vec::with_capacity(DETECT_HEADER_LEN);
+    fs::File::open(path).ok()?.take(DETECT_HEADER_LEN as u64).read_to_end(&mut header).ok()?;
+    self.importers.iter().find(|importer| importer.detect(&header)).map(|importer| importer.as_ref())
+  }
+
+  // The asset a RON file's reference to `path` (relative to the asset root) is a reference to: the file at the path if
+  // there is one, otherwise the asset that would generate it, if any.
+  pub fn referenced_asset(&self, assets_path: &Path, path: &Path) -> Option<PathBuf> {
+    if assets_path.join(path).exists() {
+      return Some(path.to_path_buf());
+    }
+    let name = path.file_name()?.to_str()?;
+    for importer in &self.importers {
+      for (_, extension) in importer.generated_files() {
+        let base = match name.strip_suffix(extension).and_then(|base| base.strip_suffix('.')) {
+          Some(base) if !base.is_empty() => base,
+          _ => continue,
+        };
+        // Importers without extensions of their own import folders.
+        let mut candidates: Vec<String> = importer.extensions().iter().map(|ext| format!("{}.{}", base, ext)).collect();
+        if candidates.is_empty() {
+          candidates.push(String::from(base));
+        }
+        for candidate in candidates {
+          let asset = path.with_file_name(candidate);
+          if assets_path.join(&asset).exists() {
+            return Some(asset);
+          }
+        }
+      }
+    }
+    None
+  }
+
+  // The importer for an asset: the one its meta file names, otherwise one registered for its extension, otherwise one that
+  // recognizes its contents.
+  pub fn for_asset(&self, path: &Path, importer_name: &str) -> Option<&dyn Importer> {
+    if let Some(importer) = self.by_name(importer_name) {
+      return Some(importer);
+    }
+    if path.is_dir() {
+      return None;
+    }
+    self.by_extension(path).or_else(|| self.by_content(path))
+  }
+}
+
+// Import Pipeline
+// ---------------
+//
+
+// What an asset was last imported from, and what came out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportRecord {
+  pub importer: String,
+  pub importer_version: u32,
+  pub source_hash: String,
+  pub settings_hash: String,
+  pub artifacts: Vec<String>,
+  // Assets the source refers to, relative to Assets. Kept even when they don't exist, so the asset is imported again once
+  // they show up.
+  #[serde(default)]
+  pub dependencies: Vec<PathBuf>,
+  // Set when the import failed. The asset isn't retried until something about it changes.
+  pub error: Option<String>,
+  // Set when something the asset depends on changed, so it has to be imported again even though it didn't change itself.
+  #[serde(default)]
+  pub dirty: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+  pub imported: Vec<PathBuf>,
+  pub failed: Vec<(PathBuf, ImportError)>,
+  pub up_to_date: usize,
+}
+
+// What `collect_garbage` found.
+#[derive(Debug, Default)]
+pub struct GcReport {
+  // Names of the artifact folders and GUIDs of the import records that belong to no asset.
+  pub orphans: Vec<String>,
+  // Size of the orphaned artifacts on disk.
+  pub bytes: u64,
+}
+
+pub struct ImportPipeline {
+  registry: ImporterRegistry,
+  library_path: PathBuf,
+  records: HashMap<Uuid, ImportRecord>,
+}
+
+impl ImportPipeline {
+  // Loads the import records saved in the Library folder. Like the asset database, a missing or unreadable file only means
+  // everything gets imported again.
+  pub fn load(library_path: &Path, registry: ImporterRegistry) -> ImportPipeline {
+    let records_path = library_path.join(IMPORT_RECORDS_FILE);
+    let records = match fs::read(&records_path).map(|bytes| serde_json::from_slice(&bytes)) {
+      Ok(Ok(records)) => records,
+      Ok(Err(err)) => {
+        eprintln!("Ignoring unreadable import records {:?}: {}", records_path, err);
+        HashMap::new()
+      }
+      Err(_) => HashMap::new(),
+    };
+    ImportPipeline { registry, library_path: library_path.to_path_buf(), records }
+  }
+
+  pub fn save(&self) -> io::Result<()> {
+    fs::create_dir_all(&self.library_path)?;
+    let bytes = serde_json::to_vec(&self.records).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    let records_path = self.library_path.join(IMPORT_RECORDS_FILE);
+    let temp_path = records_path.with_extension("json.tmp");
+    fs::write(&temp_path, bytes)?;
+    fs::rename(&temp_path, &records_path)
+  }
+
+  pub fn registry(&self) -> &ImporterRegistry {
+    &self.registry
+  }
+
+  pub fn has_importer(&self, name: &str) -> bool {
+    self.registry.by_name(name).is_some()
+  }
+
+  pub fn record(&self, guid: &Uuid) -> Option<&ImportRecord> {
+    self.records.get(guid)
+  }
+
+  // Where an asset's artifacts live.
+  pub fn artifacts_path(&self, guid: &Uuid) -> PathBuf {
+    self.library_path.join(ARTIFACTS_FOLDER).join(guid.to_string())
+  }
+
+  // Whether the asset has to be imported (again) to bring its artifacts up to date.
+  pub fn needs_import(&self, database: &AssetDatabase, asset: &AssetRecord) -> bool {
+    // Folders are only imported when their meta file asks for an importer, as atlases do.
+    if asset.is_folder && !self.has_importer(&asset.asset_type) {
+      return false;
+    }
+    let importer = match self.registry.for_asset(&database.absolute(&asset.path), &asset.asset_type) {
+      Some(importer) => importer,
+      None => return false,
+    };
+    match self.records.get(&asset.guid) {
+      Some(record) => {
+        record.dirty
+          || record.importer != importer.name()
+          || record.importer_version != importer.version()
+          || record.source_hash != asset.hash
+          || record.settings_hash != asset.settings_hash
+      }
+      None => true,
+    }
+  }
+
+  // Imports the asset if it's out of date. Returns whether an import happened.
+  pub fn import_if_needed(&mut self, database: &AssetDatabase, asset: &AssetRecord) -> Result<bool, ImportError> {
+    if !self.needs_import(database, asset) {
+      return Ok(false);
+    }
+    self.import(database, asset)?;
+    Ok(true)
+  }
+
+  // Imports the asset whether or not it's out of date. A failed import is remembered too, so it isn't retried until the
+  // asset changes.
+  pub fn import(&mut self, database: &AssetDatabase, asset: &AssetRecord) -> Result<(), ImportError> {
+    let source_path = database.absolute(&asset.path);
+    let importer = self
+      .registry
+      .for_asset(&source_path, &asset.asset_type)
+      .ok_or_else(|| ImportError::Settings(format!("no importer for {:?}", asset.path)))?;
+    let meta = Meta::read(&get_meta_file(&source_path)?)?;
+
+    // Start from an empty output folder so artifacts from an earlier import can't linger.
+    let output_path = self.artifacts_path(&asset.guid);
+    if output_path.exists() {
+      fs::remove_dir_all(&output_path)?;
+    }
+    fs::create_dir_all(&output_path)?;
+
+    let context = ImportContext {
+      assets_path: &asset.root,
+      source_path: &source_path,
+      meta: &meta,
+      output_path: &output_path,
+      registry: &self.registry,
+    };
+    let mut record = ImportRecord {
+      importer: String::from(importer.name()),
+      importer_version: importer.version(),
+      source_hash: asset.hash.clone(),
+      settings_hash: asset.settings_hash.clone(),
+      artifacts: Vec::new(),
+      dependencies: Vec::new(),
+      error: None,
+      dirty: false,
+    };
+    let result = importer.import(&context).and_then(|artifacts| {
+      record.dependencies = importer.dependencies(&context)?;
+      let missing: Vec<PathBuf> =
+        record.dependencies.iter().filter(|path| !database.absolute(path).exists()).cloned().collect();
+      if !missing.is_empty() {
+        return Err(ImportError::MissingDependencies(missing));
+      }
+      Ok(artifacts)
+    });
+    match result {
+      Ok(artifacts) => {
+        record.artifacts = artifacts;
+        self.records.insert(asset.guid, record);
+        Ok(())
+      }
+      Err(err) => {
+        record.error = Some(err.to_string());
+        self.records.insert(asset.guid, record);
+        Err(err)
+      }
+    }
+  }
+
+  // Imports every out-of-date asset in the database.
+  pub fn import_all(&mut self, database: &AssetDatabase) -> ImportReport {
+    let mut report = ImportReport::default();
+    let mut assets: Vec<&AssetRecord> = database.iter().collect();
+    assets.sort_by(|a, b| a.path.cmp(&b.path));
+    for asset in assets {
+      match self.import_if_needed(database, asset) {
+        Ok(true) => report.imported.push(asset.path.clone()),
+        Ok(false) => report.up_to_date += 1,
+        Err(err) => report.failed.push((asset.path.clone(), err)),
+      }
+    }
+    report
+  }
+
+  // Has the asset imported again, even if it didn't change itself.
+  pub fn mark_dirty(&mut self, guid: &Uuid) {
+    if let Some(record) = self.records.get_mut(guid) {
+      record.dirty = true;
+    }
+  }
+
+  pub fn records(&self) -> impl Iterator<Item = (&Uuid, &ImportRecord)> {
+    self.records.iter()
+  }
+
+  // Drops the artifacts and import record of an asset that no longer exists.
+  pub fn forget(&mut self, guid: &Uuid) -> io::Result<()> {
+    self.records.remove(guid);
+    let output_path = self.artifacts_path(guid);
+    if output_path.exists() {
+      fs::remove_dir_all(&output_path)?;
+    }
+    Ok(())
+  }
+
+  // Removes the artifacts and import records of assets the database doesn't know, which are left behind when the Library
+  // is saved without the assets' removal (e.g. after a crash). With `dry_run`, only reports them.
+  pub fn collect_garbage(&mut self, database: &AssetDatabase, dry_run: bool) -> io::Result<GcReport> {
+    let is_live = |name: &str| Uuid::parse_str(name).is_ok_and(|guid| database.get_by_guid(&guid).is_some());
+    let mut report = GcReport::default();
+    let mut orphans: BTreeSet<String> =
+      self.records.keys().map(|guid| guid.to_string()).filter(|name| !is_live(name)).collect();
+    let artifacts_root = self.library_path.join(ARTIFACTS_FOLDER);
+    if artifacts_root.is_dir() {
+      for entry in fs::read_dir(&artifacts_root)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !is_live(&name) {
+          report.bytes += disk_size(&entry.path())?;
+          orphans.insert(name);
+        }
+      }
+    }
+    if !dry_run {
+      for name in &orphans {
+        if let Ok(guid) = Uuid::parse_str(name) {
+          self.records.remove(&guid);
+        }
+        let path = artifacts_root.join(name);
+        if path.is_dir() {
+          fs::remove_dir_all(&path)?;
+        } else if path.exists() {
+          fs::remove_file(&path)?;
+        }
+      }
+    }
+    report.orphans = orphans.into_iter().collect();
+    Ok(report)
+  }
+}
+
+fn disk_size(path: &Path) -> io::Result<u64> {
+  let metadata = fs::symlink_metadata(path)?;
+  if !metadata.is_dir() {
+    return Ok(metadata.len());
+  }
+  let mut size = 0;
+  for entry in fs::read_dir(path)? {
+    size += disk_size(&entry?.path())?;
+  }
+  Ok(size)
+}
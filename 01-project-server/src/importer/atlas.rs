@@ -0,0 +1,171 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::image::Image;
+use crate::lfs::{self, LFS_FIX};
+use crate::meta::is_meta_file;
+use crate::sprites::{write_sprite_list, Sprite, GENERATED_HEADER};
+use super::texture::check_not_shadowed;
+use super::{generated_file_path, ImportContext, ImportError, Importer};
+
+// Texture atlases. A folder whose meta file names the "atlas" importer has the PNGs in it (and its subfolders) packed
+// into a single texture, with its sprite sheet and a table of sprite numbers by name. All three are generated files:
+// artifacts of the folder that RON files refer to as if they sat next to it.
+//
+//   sprites/ui/               importer: (name: "atlas", settings: {"padding": 1, "max_size": 4096})
+//     button.png
+//     icons/heart.png
+//   sprites/ui.png            The atlas texture. Prefabs referring to it (or to the sheet) can use sprite names in place
+//                             of sprite numbers.
+//   sprites/ui.ron            The sprite sheet, for Amethyst's SpriteSheetFormat.
+//   sprites/ui.names.ron      {"button": 0, "icons/heart": 1}
+//
+// A sprite's name is its path inside the folder without the extension, and sprites are numbered in name order. Adding
+// or removing an image changes the atlas like changing one does, since folders depend on what's in them.
+pub struct AtlasImporter;
+
+const ATLAS_ARTIFACT: &str = "atlas.png";
+const SHEET_ARTIFACT: &str = "sheet.ron";
+const NAMES_ARTIFACT: &str = "names.ron";
+const DEFAULT_PADDING: u32 = 1;
+const DEFAULT_MAX_SIZE: u32 = 4096;
+
+impl Importer for AtlasImporter {
+  fn name(&self) -> &'static str {
+    "atlas"
+  }
+
+  fn import(&self, context: &ImportContext) -> Result<Vec<String>, ImportError> {
+    let settings = &context.meta.importer;
+    let number = |key: &str, default: u32| match settings.settings.get(key) {
+      None => Ok(default),
+      Some(_) => match settings.get_i64(key) {
+        Some(value) if value >= 0 && value <= u32::MAX as i64 => Ok(value as u32),
+        _ => Err(ImportError::Settings(format!("{} must be a whole number of at least 0", key))),
+      },
+    };
+    let (padding, max_size) = (number("padding", DEFAULT_PADDING)?, number("max_size", DEFAULT_MAX_SIZE)?);
+
+    let (files, names) = find_sprites(context.source_path)?;
+    if files.is_empty() {
+      return Err(ImportError::Invalid(String::from("no PNG images in the folder")));
+    }
+    let mut images = Vec::new();
+    for file in &files {
+      let relative = file.strip_prefix(context.source_path).unwrap_or(file);
+      let bytes = fs::read(file)?;
+      if lfs::is_pointer(&bytes) {
+        let message = format!("{:?} is a Git LFS pointer, not the image itself: {}", relative, LFS_FIX);
+        return Err(ImportError::Invalid(message));
+      }
+      let image = Image::decode_png(&bytes).map_err(|err| ImportError::Invalid(format!("{:?}: {}", relative, err)))?;
+      images.push(image);
+    }
+
+    let sizes: Vec<(u32, u32)> = images.iter().map(|image| (image.width, image.height)).collect();
+    let Packing { width, height, positions } = pack(&sizes, padding, max_size).ok_or_else(|| {
+      ImportError::Settings(format!("the images don't fit in a {0}x{0} atlas; raise max_size", max_size))
+    })?;
+    let mut atlas = Image::new(width, height, [0, 0, 0, 0]);
+    let mut sprites = Vec::new();
+    for (image, &(x, y)) in images.iter().zip(&positions) {
+      atlas.blit(image, x, y);
+      sprites.push(Sprite { x, y, width: image.width, height: image.height });
+    }
+
+    for (_, extension) in self.generated_files() {
+      check_not_shadowed(&generated_file_path(context.source_path, true, extension))?;
+    }
+    let folder_name = context.source_path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    let source_name = format!("{}/", folder_name);
+    let names: Vec<&str> = names.iter().map(String::as_str).collect();
+    let texture = atlas.encode_png().map_err(|err| ImportError::Invalid(err.to_string()))?;
+    let sheet = write_sprite_list(&source_name, width, height, &sprites, &names);
+    let table = write_name_table(&source_name, &names);
+    Ok(vec![
+      context.write_artifact(ATLAS_ARTIFACT, &texture)?,
+      context.write_artifact(SHEET_ARTIFACT, sheet.as_bytes())?,
+      context.write_artifact(NAMES_ARTIFACT, table.as_bytes())?,
+    ])
+  }
+
+  fn generated_files(&self) -> &'static [(&'static str, &'static str)] {
+    &[(ATLAS_ARTIFACT, "png"), (SHEET_ARTIFACT, "ron"), (NAMES_ARTIFACT, "names.ron")]
+  }
+}
+
+// The PNGs making up the atlas of `folder`, in sprite number order, with their sprite names.
+pub(super) fn find_sprites(folder: &Path) -> io::Result<(Vec<PathBuf>, Vec<String>)> {
+  let mut files = Vec::new();
+  find_images(folder, &mut files)?;
+  files.sort();
+  let names = files
+    .iter()
+    .map(|file| {
+      let relative = file.strip_prefix(folder).unwrap_or(file).with_extension("");
+      let parts: Vec<String> = relative.components().map(|part| part.as_os_str().to_string_lossy().into_owned()).collect();
+      parts.join("/")
+    })
+    .collect();
+  Ok((files, names))
+}
+
+// Every PNG in the folder and its subfolders.
+fn find_images(folder: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+  for entry in fs::read_dir(folder)? {
+    let path = entry?.path();
+    if path.is_dir() {
+      find_images(&path, files)?;
+    } else if !is_meta_file(&path) && path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png")) {
+      files.push(path);
+    }
+  }
+  Ok(())
+}
+
+struct Packing {
+  // Powers of two.
+  width: u32,
+  height: u32,
+  // Top left corners, in the order the sizes were given.
+  positions: Vec<(u32, u32)>,
+}
+
+// Packs rectangles of the given sizes onto shelves, tallest first, `padding` pixels apart. Tries power of two widths from
+// the smallest that could hold them up to `max_size`, and returns the first that fits.
+fn pack(sizes: &[(u32, u32)], padding: u32, max_size: u32) -> Option<Packing> {
+  let mut order: Vec<usize> = (0..sizes.len()).collect();
+  order.sort_by_key(|&i| (std::cmp::Reverse(sizes[i].1), std::cmp::Reverse(sizes[i].0), i));
+  let area: u64 = sizes.iter().map(|&(w, h)| (w + padding) as u64 * (h + padding) as u64).sum();
+  let widest = sizes.iter().map(|&(w, _)| w).max().unwrap_or(1);
+  let mut width = widest.max((area as f64).sqrt() as u32).max(1).next_power_of_two();
+  while width <= max_size {
+    let mut positions = vec![(0, 0); sizes.len()];
+    let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+    for &i in &order {
+      let (w, h) = sizes[i];
+      if x > 0 && x + w > width {
+        y += shelf_height + padding;
+        x = 0;
+        shelf_height = 0;
+      }
+      positions[i] = (x, y);
+      x += w + padding;
+      shelf_height = shelf_height.max(h);
+    }
+    let height = (y + shelf_height).max(1).next_power_of_two();
+    if height <= max_size {
+      return Some(Packing { width, height, positions });
+    }
+    width *= 2;
+  }
+  None
+}
+
+fn write_name_table(source_name: &str, names: &[&str]) -> String {
+  let mut text = format!("{}{} and its meta file. Changes here are overwritten.\n{{\n", GENERATED_HEADER, source_name);
+  for (index, name) in names.iter().enumerate() {
+    text += &format!("    {:?}: {},\n", name, index);
+  }
+  text + "}\n"
+}
@@ -0,0 +1,41 @@
+use serde::Serialize;
+use super::{ImportContext, ImportError, Importer};
+
+// TrueType fonts. The font's table directory is checked and the file copied as is.
+pub struct FontImporter;
+
+// sfnt versions for TrueType outlines: 1.0, and Apple's 'true'.
+const TRUETYPE_VERSIONS: [[u8; 4]; 2] = [[0x00, 0x01, 0x00, 0x00], *b"true"];
+
+#[derive(Debug, Serialize)]
+pub struct FontInfo {
+  pub num_tables: u16,
+}
+
+impl Importer for FontImporter {
+  fn name(&self) -> &'static str {
+    "font"
+  }
+
+  fn extensions(&self) -> &'static [&'static str] {
+    &["ttf"]
+  }
+
+  fn detect(&self, header: &[u8]) -> bool {
+    header.len() >= 4 && TRUETYPE_VERSIONS.iter().any(|version| header[..4] == version[..])
+  }
+
+  fn import(&self, context: &ImportContext) -> Result<Vec<String>, ImportError> {
+    let bytes = context.read_source()?;
+    if !self.detect(&bytes) || bytes.len() < 12 {
+      return Err(ImportError::Invalid(String::from("not a TrueType font")));
+    }
+    let num_tables = u16::from_be_bytes([bytes[4], bytes[5]]);
+    // Each table record is 16 bytes, following the 12 byte offset table.
+    if num_tables == 0 || bytes.len() < 12 + 16 * num_tables as usize {
+      return Err(ImportError::Invalid(String::from("truncated font table directory")));
+    }
+    let info = FontInfo { num_tables };
+    Ok(vec![context.write_artifact("font.ttf", &bytes)?, context.write_ron_artifact("font.ron", &info)?])
+  }
+}
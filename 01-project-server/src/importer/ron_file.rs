@@ -0,0 +1,259 @@
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use crate::meta::{get_meta_file, Meta};
+use super::atlas::find_sprites;
+use super::texture::sprite_names;
+use super::{ImportContext, ImportError, Importer};
+
+// RON data such as prefabs, UI layouts and sprite sheets. The file has to parse; beyond that its layout belongs to whatever
+// loads it, so it's copied as is.
+//
+// The one exception is sprite names. Where a prefab says `sprite_number: "paddle"` instead of a number, the name is looked
+// up in the meta files of the textures (and sprite sheets) the file refers to, or in the atlases it refers to, and the
+// artifact gets the number Amethyst expects.
+pub struct RonImporter;
+
+impl Importer for RonImporter {
+  fn name(&self) -> &'static str {
+    "ron"
+  }
+
+  fn extensions(&self) -> &'static [&'static str] {
+    &["ron"]
+  }
+
+  fn import(&self, context: &ImportContext) -> Result<Vec<String>, ImportError> {
+    let text = read_text(context)?;
+    ron::de::from_str::<ron::Value>(&text).map_err(|err| ImportError::Invalid(err.to_string()))?;
+    let text = resolve_sprite_names(context, &text)?;
+    Ok(vec![context.write_artifact("asset.ron", text.as_bytes())?])
+  }
+
+  fn dependencies(&self, context: &ImportContext) -> Result<Vec<PathBuf>, ImportError> {
+    let text = read_text(context)?;
+    // A generated file depends on the asset generating it; anything else missing is kept as it is, and reported.
+    let mut paths: Vec<PathBuf> = find_file_references(&text)
+      .into_iter()
+      .map(|reference| {
+        let path = PathBuf::from(reference.path);
+        context.registry.referenced_asset(context.assets_path, &path).unwrap_or(path)
+      })
+      .collect();
+    paths.sort();
+    paths.dedup();
+    Ok(paths)
+  }
+}
+
+fn read_text(context: &ImportContext) -> Result<String, ImportError> {
+  String::from_utf8(context.read_source()?).map_err(|_| ImportError::Invalid(String::from("not UTF-8 text")))
+}
+
+// Replaces sprite names given as sprite numbers with their numbers.
+fn resolve_sprite_names(context: &ImportContext, text: &str) -> Result<String, ImportError> {
+  let fields = find_string_fields(text, "sprite_number");
+  if fields.is_empty() {
+    return Ok(String::from(text));
+  }
+  // The names of every texture and atlas the file refers to, directly or through a sprite sheet.
+  let mut textures: Vec<(String, Vec<String>)> = Vec::new();
+  for reference in find_file_references(text) {
+    let path = Path::new(&reference.path);
+    let texture = match context.registry.referenced_asset(context.assets_path, path) {
+      Some(asset) if context.assets_path.join(&asset).is_dir() => asset,
+      // A hand-written sheet stands for the texture next to it.
+      Some(asset) if asset.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("ron")) => asset.with_extension("png"),
+      Some(asset) if asset.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png")) => asset,
+      _ => continue,
+    };
+    let texture_path = context.assets_path.join(&texture);
+    if textures.iter().any(|(name, _)| Path::new(name) == texture) || !texture_path.exists() {
+      continue;
+    }
+    let meta = Meta::read(&get_meta_file(&texture_path)?)?;
+    let names = if texture_path.is_dir() {
+      if meta.importer.name != "atlas" { continue; }
+      find_sprites(&texture_path)?.1
+    } else {
+      sprite_names(&meta.importer).into_iter().map(String::from).collect()
+    };
+    textures.push((texture.to_string_lossy().into_owned(), names));
+  }
+
+  let mut resolved = String::with_capacity(text.len());
+  let mut copied = 0;
+  for (span, name) in fields {
+    let mut found = textures.iter().filter_map(|(texture, names)| Some((texture, names.iter().position(|n| *n == name)?)));
+    let (texture, number) = found.next().ok_or_else(|| {
+      let textures: Vec<&str> = textures.iter().map(|(texture, _)| texture.as_str()).collect();
+      ImportError::Invalid(format!("no sprite named {:?} in {:?}", name, textures))
+    })?;
+    if let Some((other, other_number)) = found.find(|&(_, other_number)| other_number != number) {
+      let message =
+        format!("sprite name {:?} is ambiguous: {} in {:?}, {} in {:?}", name, number, texture, other_number, other);
+      return Err(ImportError::Invalid(message));
+    }
+    resolved += &text[copied..span.start];
+    resolved += &number.to_string();
+    copied = span.end;
+  }
+  resolved += &text[copied..];
+  Ok(resolved)
+}
+
+// A `File("path", ...)` reference to another asset in a RON file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileReference {
+  pub path: String,
+  // Byte range of the path inside the file, not including the quotes.
+  pub span: Range<usize>,
+}
+
+// Finds the asset paths a RON file references. Amethyst loads nested assets through `File(path, format)`, e.g.
+// `font: File("fonts/joystix.ttf", ("TTF", ()))`, so that's what is looked for. Strings and comments are skipped, so a
+// "File(" inside a label's text doesn't count.
+pub fn find_file_references(text: &str) -> Vec<FileReference> {
+  let bytes = text.as_bytes();
+  let mut references = Vec::new();
+  let mut i = 0;
+  while i < bytes.len() {
+    match bytes[i] {
+      b'"' => i = skip_string(bytes, i),
+      b'/' if matches!(bytes.get(i + 1), Some(b'/') | Some(b'*')) => i = skip_comment(bytes, i),
+      c if c.is_ascii_alphabetic() || c == b'_' => {
+        let start = i;
+        i = skip_identifier(bytes, i);
+        if &bytes[start..i] != b"File" { continue; }
+        let mut j = skip_whitespace(bytes, i);
+        if bytes.get(j) != Some(&b'(') { continue; }
+        j = skip_whitespace(bytes, j + 1);
+        if bytes.get(j) != Some(&b'"') { continue; }
+        let end = skip_string(bytes, j);
+        if bytes.get(end - 1) != Some(&b'"') || end < j + 2 { break; }
+        // Paths with escapes are rare enough that they're left alone rather than unescaped.
+        let span = j + 1..end - 1;
+        if !text[span.clone()].contains('\\') {
+          references.push(FileReference { path: String::from(&text[span.clone()]), span });
+        }
+        i = end;
+      }
+      _ => i += 1,
+    }
+  }
+  references
+}
+
+// Finds the `Name(...)` values in a RON file with one of the given names, such as the `Grid(...)` and `List(...)` sprite
+// lists in a sprite sheet. Each span runs from the name to the closing parenthesis, and values nested inside others are
+// found too, in the order they start.
+pub fn find_values(text: &str, names: &[&str]) -> Vec<Range<usize>> {
+  let bytes = text.as_bytes();
+  let mut values = Vec::new();
+  let mut i = 0;
+  while i < bytes.len() {
+    match bytes[i] {
+      b'"' => i = skip_string(bytes, i),
+      b'/' if matches!(bytes.get(i + 1), Some(b'/') | Some(b'*')) => i = skip_comment(bytes, i),
+      c if c.is_ascii_alphabetic() || c == b'_' => {
+        let start = i;
+        i = skip_identifier(bytes, i);
+        if !names.iter().any(|name| name.as_bytes() == &bytes[start..i]) { continue; }
+        let open = skip_whitespace(bytes, i);
+        if bytes.get(open) != Some(&b'(') { continue; }
+        if let Some(close) = find_closing(bytes, open) {
+          values.push(start..close + 1);
+        }
+      }
+      _ => i += 1,
+    }
+  }
+  values
+}
+
+// Finds the fields named `field` whose value is a string, such as `sprite_number: "paddle"`. Returns the span of each
+// value, quotes included, and the string. Strings with escapes are skipped.
+fn find_string_fields(text: &str, field: &str) -> Vec<(Range<usize>, String)> {
+  let bytes = text.as_bytes();
+  let mut fields = Vec::new();
+  let mut i = 0;
+  while i < bytes.len() {
+    match bytes[i] {
+      b'"' => i = skip_string(bytes, i),
+      b'/' if matches!(bytes.get(i + 1), Some(b'/') | Some(b'*')) => i = skip_comment(bytes, i),
+      c if c.is_ascii_alphabetic() || c == b'_' => {
+        let start = i;
+        i = skip_identifier(bytes, i);
+        if &bytes[start..i] != field.as_bytes() { continue; }
+        let mut j = skip_whitespace(bytes, i);
+        if bytes.get(j) != Some(&b':') { continue; }
+        j = skip_whitespace(bytes, j + 1);
+        if bytes.get(j) != Some(&b'"') { continue; }
+        let end = skip_string(bytes, j);
+        let value = &text[j + 1..end.saturating_sub(1).max(j + 1)];
+        if bytes.get(end - 1) == Some(&b'"') && end >= j + 2 && !value.contains('\\') {
+          fields.push((j..end, String::from(value)));
+        }
+        i = end;
+      }
+      _ => i += 1,
+    }
+  }
+  fields
+}
+
+// Given the index of an opening parenthesis or bracket, returns the index of the one closing it.
+fn find_closing(bytes: &[u8], open: usize) -> Option<usize> {
+  let mut depth = 0;
+  let mut i = open;
+  while i < bytes.len() {
+    match bytes[i] {
+      b'"' => i = skip_string(bytes, i),
+      b'/' if matches!(bytes.get(i + 1), Some(b'/') | Some(b'*')) => i = skip_comment(bytes, i),
+      b'(' | b'[' | b'{' => {
+        depth += 1;
+        i += 1;
+      }
+      b')' | b']' | b'}' => {
+        depth -= 1;
+        if depth == 0 { return Some(i); }
+        i += 1;
+      }
+      _ => i += 1,
+    }
+  }
+  None
+}
+
+fn skip_identifier(bytes: &[u8], mut i: usize) -> usize {
+  while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') { i += 1; }
+  i
+}
+
+// Given the index of the slash starting a // or /* comment, returns the index just past it.
+fn skip_comment(bytes: &[u8], mut i: usize) -> usize {
+  if bytes.get(i + 1) == Some(&b'/') {
+    while i < bytes.len() && bytes[i] != b'\n' { i += 1; }
+    return i;
+  }
+  i += 2;
+  while i + 1 < bytes.len() && !(bytes[i] == b'*' && bytes[i + 1] == b'/') { i += 1; }
+  (i + 2).min(bytes.len())
+}
+
+fn skip_whitespace(bytes: &[u8], mut i: usize) -> usize {
+  while i < bytes.len() && bytes[i].is_ascii_whitespace() { i += 1; }
+  i
+}
+
+// Given the index of an opening quote, returns the index just past the closing quote.
+fn skip_string(bytes: &[u8], start: usize) -> usize {
+  let mut i = start + 1;
+  while i < bytes.len() {
+    match bytes[i] {
+      b'\\' => i += 2,
+      b'"' => return i + 1,
+      _ => i += 1,
+    }
+  }
+  bytes.len()
+}
@@ -0,0 +1,171 @@
+use std::path::Path;
+use serde::Serialize;
+use crate::image::Image;
+use crate::meta::ImporterSettings;
+use crate::sprites::{detect_sprites, write_sprite_grid, write_sprite_list, SpriteGrid};
+use super::{ImportContext, ImportError, Importer};
+
+// PNG textures. The image is validated and copied as is; its dimensions and sampling settings are written next to it so
+// tools don't have to decode the PNG to find them.
+//
+// A texture can also make its own sprite sheet, saving anyone from working out pixel coordinates by hand. The meta file
+// says how the sprites are laid out (see sprites.rs), and the sheet is a generated file: an artifact RON files refer to
+// as if it sat next to the texture, as "textures/pong.ron" for "textures/pong.png".
+//
+//   settings: {
+//     "sprites": "grid",       // Cells of a grid: "columns", optionally "rows", "sprite_count", "cell_width",
+//                              // "cell_height", "x" and "y", as in Amethyst's Grid.
+//     "sprites": "auto",       // Found from the transparent gutters between them, with an optional "alpha_threshold"
+//                              // (0-255) below which a pixel counts as transparent.
+//     "names": ["paddle", "ball"],
+//   }
+//
+// Names are optional, and number the sprites from 0. Prefabs can use them in place of sprite numbers (see ron_file.rs).
+pub struct TextureImporter;
+
+const SHEET_ARTIFACT: &str = "sheet.ron";
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+#[derive(Debug, Serialize)]
+pub struct TextureInfo {
+  pub width: u32,
+  pub height: u32,
+  pub bit_depth: u8,
+  pub color_type: u8,
+  pub filter: String,
+}
+
+// Width, height, bit depth and color type from a PNG's IHDR chunk, which the PNG spec requires to come first.
+pub fn read_png_header(bytes: &[u8]) -> Result<(u32, u32, u8, u8), ImportError> {
+  if bytes.len() < 26 || bytes[..8] != PNG_SIGNATURE || &bytes[12..16] != b"IHDR" {
+    return Err(ImportError::Invalid(String::from("not a PNG file")));
+  }
+  let width = u32::from_be_bytes([bytes[16], bytes[17], bytes[18], bytes[19]]);
+  let height = u32::from_be_bytes([bytes[20], bytes[21], bytes[22], bytes[23]]);
+  Ok((width, height, bytes[24], bytes[25]))
+}
+
+impl Importer for TextureImporter {
+  fn name(&self) -> &'static str {
+    "texture"
+  }
+
+  fn extensions(&self) -> &'static [&'static str] {
+    &["png"]
+  }
+
+  fn detect(&self, header: &[u8]) -> bool {
+    header.starts_with(&PNG_SIGNATURE)
+  }
+
+  fn import(&self, context: &ImportContext) -> Result<Vec<String>, ImportError> {
+    let bytes = context.read_source()?;
+    let (width, height, bit_depth, color_type) = read_png_header(&bytes)?;
+    let filter = context.meta.importer.get_str("filter").unwrap_or("nearest");
+    if filter != "nearest" && filter != "linear" {
+      return Err(ImportError::Settings(format!("filter must be \"nearest\" or \"linear\", not {:?}", filter)));
+    }
+    let info = TextureInfo { width, height, bit_depth, color_type, filter: String::from(filter) };
+    let mut artifacts =
+      vec![context.write_artifact("texture.png", &bytes)?, context.write_ron_artifact("texture.ron", &info)?];
+    if let Some(sheet) = generate_sprite_sheet(context, &bytes, width, height)? {
+      check_not_shadowed(&context.source_path.with_extension("ron"))?;
+      artifacts.push(context.write_artifact(SHEET_ARTIFACT, sheet.as_bytes())?);
+    }
+    Ok(artifacts)
+  }
+
+  fn generated_files(&self) -> &'static [(&'static str, &'static str)] {
+    &[(SHEET_ARTIFACT, "ron")]
+  }
+}
+
+// The sprite names in a texture's import settings.
+pub fn sprite_names(settings: &ImporterSettings) -> Vec<&str> {
+  settings.get_str_list("names").unwrap_or_default()
+}
+
+// The sprite sheet the meta file asks for, if any.
+fn generate_sprite_sheet(
+  context: &ImportContext,
+  bytes: &[u8],
+  width: u32,
+  height: u32,
+) -> Result<Option<String>, ImportError> {
+  let settings = &context.meta.importer;
+  let source_name = context.source_path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+  let number = |key: &str| -> Result<Option<u32>, ImportError> {
+    match settings.settings.get(key) {
+      None => Ok(None),
+      Some(_) => match settings.get_i64(key) {
+        Some(value) if value >= 0 && value <= u32::MAX as i64 => Ok(Some(value as u32)),
+        _ => Err(ImportError::Settings(format!("{} must be a whole number of at least 0", key))),
+      },
+    }
+  };
+  if settings.settings.contains_key("names") && settings.get_str_list("names").is_none() {
+    return Err(ImportError::Settings(String::from("names must be a list of strings")));
+  }
+  let names = sprite_names(settings);
+
+  let (sheet, count) = match settings.get_str("sprites") {
+    None if settings.settings.contains_key("sprites") => {
+      return Err(ImportError::Settings(String::from("sprites must be \"grid\" or \"auto\"")))
+    }
+    None => return Ok(None),
+    Some("grid") => {
+      let columns = number("columns")?.filter(|&columns| columns > 0);
+      let columns = columns.ok_or_else(|| ImportError::Settings(String::from("a grid needs at least 1 column")))?;
+      let cell_size = match (number("cell_width")?, number("cell_height")?) {
+        (Some(cell_width), Some(cell_height)) => Some((cell_width, cell_height)),
+        (None, None) => None,
+        _ => return Err(ImportError::Settings(String::from("cell_width and cell_height go together"))),
+      };
+      let position = match (number("x")?, number("y")?) {
+        (None, None) => None,
+        (x, y) => Some((x.unwrap_or(0), y.unwrap_or(0))),
+      };
+      let grid = SpriteGrid {
+        texture_width: width,
+        texture_height: height,
+        columns,
+        rows: number("rows")?,
+        sprite_count: number("sprite_count")?,
+        cell_size,
+        position,
+      };
+      let sprites = grid.sprites();
+      if let Some(outside) = sprites.iter().position(|s| s.x + s.width > width || s.y + s.height > height) {
+        let message = format!("sprite {} of the grid lies outside the {}x{} texture", outside, width, height);
+        return Err(ImportError::Settings(message));
+      }
+      (write_sprite_grid(source_name, &grid, &names), sprites.len())
+    }
+    Some("auto") => {
+      let alpha_threshold = match number("alpha_threshold")? {
+        Some(threshold) if threshold > 255 => {
+          return Err(ImportError::Settings(String::from("alpha_threshold must be between 0 and 255")))
+        }
+        threshold => threshold.unwrap_or(0) as u8,
+      };
+      let image = Image::decode_png(bytes).map_err(|err| ImportError::Invalid(err.to_string()))?;
+      let sprites = detect_sprites(&image, alpha_threshold);
+      (write_sprite_list(source_name, width, height, &sprites, &names), sprites.len())
+    }
+    Some(other) => return Err(ImportError::Settings(format!("sprites must be \"grid\" or \"auto\", not {:?}", other))),
+  };
+  if names.len() > count {
+    return Err(ImportError::Settings(format!("{} names for {} sprites", names.len(), count)));
+  }
+  Ok(Some(sheet))
+}
+
+// Fails if a file of the asset's own is where a generated file would appear, since references would find that instead.
+pub(super) fn check_not_shadowed(path: &Path) -> Result<(), ImportError> {
+  if path.exists() {
+    let name = path.file_name().unwrap_or_default();
+    let message = format!("{:?} is in the way of the generated file of the same name; rename or delete it", name);
+    return Err(ImportError::Settings(message));
+  }
+  Ok(())
+}
@@ -0,0 +1,42 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+// Git LFS
+// -------
+//
+// Projects usually keep their textures and fonts in Git LFS. A checkout made without Git LFS installed has small text
+// pointer files where those should be, which otherwise only show up as PNGs and fonts that fail to decode:
+//
+//   version https://git-lfs.github.com/spec/v1
+//   oid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393
+//   size 12345
+//
+// The server recognizes them, so importers and verify can say what's wrong and how to get the real files.
+//
+
+const POINTER_PREFIX: &[u8] = b"version https://git-lfs.github.com/spec/";
+// Pointer files are a few short lines; anything bigger is the real thing.
+const MAX_POINTER_SIZE: u64 = 1024;
+
+// How to replace pointer files with the files they stand for.
+pub const LFS_FIX: &str = "install Git LFS (https://git-lfs.github.com/), then run `git lfs install` and `git lfs pull`";
+
+pub fn is_pointer(bytes: &[u8]) -> bool {
+  bytes.len() as u64 <= MAX_POINTER_SIZE && bytes.starts_with(POINTER_PREFIX)
+}
+
+// Whether the file is a pointer, reading no more of it than a pointer could be.
+pub fn is_pointer_file(path: &Path) -> bool {
+  let mut bytes = Vec::new();
+  match File::open(path) {
+    Ok(file) => file.take(MAX_POINTER_SIZE + 1).read_to_end(&mut bytes).is_ok() && is_pointer(&bytes),
+    Err(_) => false,
+  }
+}
+
+// The size of the file a pointer stands for, from its "size" line.
+pub fn pointer_size(bytes: &[u8]) -> Option<u64> {
+  let text = std::str::from_utf8(bytes).ok()?;
+  text.lines().find_map(|line| line.strip_prefix("size ")).and_then(|size| size.trim().parse().ok())
+}
@@ -0,0 +1,28 @@
+// Theseus Project Server
+// ----------------------
+//
+// The pieces of the project server that other tools can reuse: the project manifest, meta files, the asset database,
+// importers, the dependency graph, ignore rules, the asset watcher, the change log, asset search, the project lock, asset
+// packs, project templates, meta verification, Git LFS pointer detection, and sprite sheet and thumbnail previews. The
+// server itself, which keeps a project up to date and serves it to editors, is proj-server-async.
+//
+pub mod changes;
+pub mod database;
+pub mod dependencies;
+pub mod error;
+pub mod ignore_rules;
+pub mod image;
+pub mod importer;
+pub mod lfs;
+pub mod lock;
+pub mod manifest;
+pub mod meta;
+pub mod pack;
+pub mod preview;
+pub mod project;
+pub mod references;
+pub mod search;
+pub mod sprites;
+pub mod templates;
+pub mod verify;
+pub mod watcher;
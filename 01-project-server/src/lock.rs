@@ -0,0 +1,146 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process;
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use crate::error::ProjectError;
+
+// Project Lock
+// ------------
+//
+// Only one process may work on a project at a time: two servers (or a server and a one-shot `scan`) would race on meta
+// files and the Library. The process working on a project holds an exclusive lock on Library/server.lock, and writes its
+// PID and the address it listens on into it, so a second instance can say who's in the way and clients (`stop` among
+// them) know where to find the server. The server listens on any free port unless told otherwise, so one machine can
+// serve several projects at once.
+//
+// The operating system releases the lock when the process exits, even if it crashes, so a lock file left behind never
+// blocks anyone. That's also why the file is emptied rather than deleted on exit: deleting it would let a process that
+// already opened it lock a file no one else can see.
+//
+
+pub const LOCK_FILE: &str = "server.lock";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LockInfo {
+  pub pid: u32,
+  // The command holding the lock ("serve", "scan", ...).
+  pub command: String,
+  // Where the server listens for clients, once it does.
+  #[serde(default)]
+  pub address: Option<SocketAddr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LockStatus {
+  Unlocked,
+  // The info is None when the lock file can't be read, which is the case on platforms where a lock keeps others from
+  // reading.
+  Locked(Option<LockInfo>),
+}
+
+pub struct ProjectLock {
+  path: PathBuf,
+  file: File,
+  info: LockInfo,
+}
+
+impl ProjectLock {
+  pub fn acquire(library_path: &Path, command: &str) -> Result<ProjectLock, ProjectError> {
+    let path = library_path.join(LOCK_FILE);
+    let unwritable = |source| ProjectError::FileUnwritable { path: path.clone(), source };
+    fs::create_dir_all(library_path).map_err(unwritable)?;
+    let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).map_err(unwritable)?;
+    if file.try_lock_exclusive().is_err() {
+      return Err(ProjectError::ProjectLocked { holder: read_info(file), path });
+    }
+    let info = LockInfo { pid: process::id(), command: String::from(command), address: None };
+    let mut lock = ProjectLock { path: path.clone(), file, info };
+    lock.write()?;
+    Ok(lock)
+  }
+
+  // Whether another process is working on the project, and who.
+  pub fn status(library_path: &Path) -> LockStatus {
+    let file = match File::open(library_path.join(LOCK_FILE)) {
+      Ok(file) => file,
+      Err(_) => return LockStatus::Unlocked,
+    };
+    if file.try_lock_shared().is_ok() {
+      let _ = file.unlock();
+      return LockStatus::Unlocked;
+    }
+    LockStatus::Locked(read_info(file))
+  }
+
+  // Where the server working on the project listens, if one is.
+  pub fn server_address(library_path: &Path) -> Option<SocketAddr> {
+    match ProjectLock::status(library_path) {
+      LockStatus::Locked(Some(info)) => info.address,
+      _ => None,
+    }
+  }
+
+  pub fn set_address(&mut self, address: SocketAddr) -> Result<(), ProjectError> {
+    self.info.address = Some(address);
+    self.write()
+  }
+
+  fn write(&mut self) -> Result<(), ProjectError> {
+    let bytes = serde_json::to_vec(&self.info).map_err(io::Error::from);
+    bytes
+      .and_then(|bytes| {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&bytes)?;
+        self.file.sync_data()
+      })
+      .map_err(|source| ProjectError::FileUnwritable { path: self.path.clone(), source })
+  }
+}
+
+impl Drop for ProjectLock {
+  fn drop(&mut self) {
+    let _ = self.file.set_len(0);
+    let _ = self.file.unlock();
+  }
+}
+
+fn read_info(mut file: File) -> Option<LockInfo> {
+  let mut contents = String::new();
+  file.read_to_string(&mut contents).ok()?;
+  serde_json::from_str(&contents).ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn projects_can_only_be_locked_once() {
+    let library = tempfile::tempdir().unwrap();
+    let mut lock = ProjectLock::acquire(library.path(), "serve").unwrap();
+    match ProjectLock::acquire(library.path(), "scan") {
+      Err(ProjectError::ProjectLocked { holder, .. }) => {
+        let holder = holder.unwrap();
+        assert_eq!((holder.pid, holder.command.as_str(), holder.address), (process::id(), "serve", None));
+      }
+      other => panic!("{:?}", other.map(|_| ())),
+    }
+
+    let address: SocketAddr = "127.0.0.1:4530".parse().unwrap();
+    lock.set_address(address).unwrap();
+    let info = LockInfo { pid: process::id(), command: String::from("serve"), address: Some(address) };
+    assert_eq!(ProjectLock::status(library.path()), LockStatus::Locked(Some(info)));
+    assert_eq!(ProjectLock::server_address(library.path()), Some(address));
+
+    drop(lock);
+    assert_eq!(ProjectLock::status(library.path()), LockStatus::Unlocked);
+    assert_eq!(ProjectLock::server_address(library.path()), None);
+    // The lock file is left behind, empty, and doesn't keep anyone out.
+    assert!(library.path().join(LOCK_FILE).is_file());
+    ProjectLock::acquire(library.path(), "scan").unwrap();
+  }
+}
@@ -1,10 +1,24 @@
-use std::fs::DirEntry;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::thread;
-use std::time::Duration;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use serde::Serialize;
+use serde_json::{json, Value};
+use structopt::clap::AppSettings;
 use structopt::StructOpt;
+use project_server::database::ScanReport;
+use project_server::importer::{ImportReport, ImporterRegistry};
+use project_server::lock::{LockStatus, ProjectLock};
+use project_server::pack::{self, PackError, PackReport};
+use project_server::preview::{self, PreviewError};
+use project_server::project::Project;
+use project_server::search::{search, Query};
+use project_server::templates::{self, Template};
+use project_server::verify;
 
 // Constants
 // ---------
@@ -14,12 +28,107 @@ const VERBOSE: bool = true;
 // Command Line Interface
 // ----------------------
 //
+// Exit codes: EXIT_ERROR when a command couldn't run at all (no project, unwritable Library, ...) and EXIT_PROBLEMS when
+// it ran but found something wrong, such as import failures or inconsistent meta files, so CI scripts can tell the two
+// apart.
+//
+const EXIT_OK: i32 = 0;
+const EXIT_ERROR: i32 = 1;
+const EXIT_PROBLEMS: i32 = 2;
+
 #[derive(Debug, StructOpt)]
 struct Cli {
   // The path of the project folder to open.
   #[structopt(parse(from_os_str))]
-  project_path: std::path::PathBuf
-} 
+  project_path: PathBuf,
+  // Output of one-shot commands: text or json. The server always logs text.
+  #[structopt(long, global = true, default_value = "text")]
+  format: Format,
+  // What to do with the project.
+  #[structopt(subcommand)]
+  command: Command,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+  Text,
+  Json,
+}
+
+impl FromStr for Format {
+  type Err = String;
+
+  fn from_str(name: &str) -> Result<Format, String> {
+    match name {
+      "text" => Ok(Format::Text),
+      "json" => Ok(Format::Json),
+      _ => Err(format!("unknown format {:?} (expected text or json)", name)),
+    }
+  }
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+  // Runs proj-server-async on the project: the server that keeps it up to date while it's edited and answers the
+  // editor and other clients. It's looked for next to this program, then on the PATH.
+  Serve {
+    // Where to listen for clients. By default any free port on localhost; clients find it in the project's lock file.
+    #[structopt(long)]
+    address: Option<SocketAddr>,
+  },
+  // Brings the asset index and imports up to date, then exits.
+  Scan,
+  // Checks that every asset has a valid meta file, every meta file has an asset and no GUID is used twice. Changes
+  // nothing.
+  Verify,
+  // Lists the problems verify finds with how to fix them, and with --fix applies the fixes.
+  Doctor {
+    // Fix what can be fixed automatically.
+    #[structopt(long)]
+    fix: bool,
+  },
+  // Removes artifacts and import records left behind by assets that no longer exist.
+  Gc {
+    // Only report what would be removed.
+    #[structopt(long)]
+    dry_run: bool,
+  },
+  // Prints the project's settings and what's in its asset index, without scanning.
+  Info,
+  // Lists the indexed assets matching a query such as `type:texture label:ui name:*menu*`, without scanning.
+  #[structopt(setting = AppSettings::AllowLeadingHyphen)]
+  Search {
+    // The query terms, all of which an asset has to match.
+    query: Vec<String>,
+  },
+  // Draws thumbnails of textures and previews of sprite sheets into the Library's cache, without scanning.
+  Preview {
+    // Textures and RON files to preview, relative to the asset root. Every indexed texture and sprite sheet if none are
+    // given.
+    #[structopt(parse(from_os_str))]
+    paths: Vec<PathBuf>,
+  },
+  // Asks the server running on the project to save and shut down, and waits until it has.
+  Stop,
+  // Creates a new project in the (missing or empty) project folder from one of the sample games.
+  New {
+    // empty-2d, pong or arkanoid.
+    #[structopt(short, long, default_value = "empty-2d")]
+    template: Template,
+    // The project's name. Defaults to the name of the project folder.
+    #[structopt(short, long)]
+    name: Option<String>,
+  },
+  // Packs the imported assets into a single archive for shipping.
+  Pack {
+    // Where to write the archive.
+    #[structopt(short, long, parse(from_os_str), default_value = "assets.pack")]
+    output: PathBuf,
+    // Assets or folders to pack, relative to the asset root. Everything is packed if none are given.
+    #[structopt(parse(from_os_str))]
+    paths: Vec<PathBuf>,
+  },
+}
 
 // Main
 // ----
@@ -27,83 +136,492 @@ struct Cli {
 fn main() {
   // Project from path.
   let args = Cli::from_args();
-  println!("{:?}", args);
+  let format = args.format;
+  if format == Format::Text {
+    println!("{:?}", args);
+  }
   let project_path = Path::new(&args.project_path);
-  let project_assets_path = project_path.join(Path::new("Assets"));
-  if VERBOSE {
+  if let Command::Serve { address } = &args.command {
+    serve_command(project_path, *address, format);
+  }
+  if let Command::New { template, name } = &args.command {
+    if format == Format::Text {
+      println!("Creating {} project in {:?}...", template, project_path);
+    }
+    if let Err(err) = templates::create_project(project_path, name.as_deref(), *template) {
+      fail(format, "Error creating project", &err);
+    }
+  }
+  let mut project = match Project::open(project_path, ImporterRegistry::default()) {
+    Ok(project) => project,
+    Err(err) => fail(format, "Error opening project", &err),
+  };
+  if VERBOSE && format == Format::Text {
+    println!("Project: {} (engine version {})", project.manifest.project.name, project.manifest.project.engine_version);
     println!("Project path: {:?}", args.project_path);
-    println!("Project path exists? {:?}", project_path.exists());
-    println!("Project assets path (theoretical): {:?}", project_assets_path);
-    println!("Project assets path exists? {:?}", project_assets_path.exists());
+    println!("Asset roots: {:?}", project.asset_roots);
+  }
+
+  let command = args.command;
+  // Everything that may write meta files or the Library keeps other instances out while it runs.
+  let lock_name = match command {
+    Command::Scan => Some("scan"),
+    Command::Gc { .. } => Some("gc"),
+    Command::Doctor { fix: true } => Some("doctor"),
+    Command::New { .. } => Some("new"),
+    Command::Pack { .. } => Some("pack"),
+    Command::Verify
+    | Command::Doctor { fix: false }
+    | Command::Info
+    | Command::Search { .. }
+    | Command::Preview { .. }
+    | Command::Stop
+    | Command::Serve { .. } => None,
+  };
+  let lock = lock_name.map(|name| match ProjectLock::acquire(&project.library_path, name) {
+    Ok(lock) => lock,
+    Err(err) => fail(format, "Error opening project", &err),
+  });
+
+  let code = match command {
+    Command::Scan => scan_command(&mut project, format),
+    Command::Verify => verify_command(&project, format),
+    Command::Doctor { fix } => doctor_command(&project, fix, format),
+    Command::Gc { dry_run } => gc_command(&mut project, dry_run, format),
+    Command::Info => info_command(&project, format),
+    Command::Search { query } => search_command(&project, &query.join(" "), format),
+    Command::Preview { paths } => preview_command(&project, paths, format),
+    Command::Stop => stop_command(&project, format),
+    Command::Serve { .. } => unreachable!("serve runs proj-server-async instead"),
+    Command::New { .. } => {
+      let (scan, _) = update_project(&mut project, format);
+      // A template that doesn't import cleanly makes a project that won't run.
+      let failed = failed_imports(&project);
+      let ok = scan.errors.is_empty() && failed.is_empty();
+      match format {
+        Format::Text if ok => {
+          println!("\nProject created. Run proj-server-async on {:?} to start working on it.", project_path)
+        }
+        Format::Text => println!("\nProject created, but some of its assets failed to import (see above)."),
+        Format::Json => {
+          let failed: Vec<_> = failed.iter().map(|(path, err)| json!({ "path": path, "error": err })).collect();
+          print_json(&json!({ "path": project.path, "name": project.manifest.project.name, "failed": failed }))
+        }
+      }
+      if ok { EXIT_OK } else { EXIT_PROBLEMS }
+    }
+    Command::Pack { output, paths } => {
+      update_project(&mut project, format);
+      if format == Format::Text {
+        println!("\nPacking assets into {:?}...", output);
+      }
+      match pack::pack(&project, &output, &paths) {
+        Ok(report) => {
+          print_pack_report(&report, format);
+          EXIT_OK
+        }
+        // Assets that failed to import are a problem with the project, not with packing.
+        Err(err @ PackError::NotImported(_)) => {
+          print_error(format, "Error packing assets", &err);
+          EXIT_PROBLEMS
+        }
+        Err(err) => fail(format, "Error packing assets", &err),
+      }
+    }
+  };
+  // process::exit skips destructors.
+  drop(lock);
+  process::exit(code);
+}
+
+// Asset database and imports. Only what changed since the last run gets re-read and reimported.
+fn update_project(project: &mut Project, format: Format) -> (ScanReport, ImportReport) {
+  if format == Format::Text {
+    println!("\nScanning project assets ({} indexed)...", project.database.len());
   }
-  if !project_assets_path.exists() {
-    panic!("Project path \"{:?}\" is not a project because it does not contain an Assets folder.", project_path);
+  let scan = project.scan();
+  let import = project.import_all();
+  if format == Format::Text {
+    print_scan_report(&scan);
+    print_import_report(&import);
   }
+  save_project(project);
+  (scan, import)
+}
 
-  // Server life.
-  let live = Arc::new(AtomicBool::from(true));
-  
-  // Ctrl+C handler.
-  let live_for_ctrlc_handler = live.clone();
-  ctrlc::set_handler(move || {
-    println!("Received Ctrl+C.");
-    live_for_ctrlc_handler.store(false, Ordering::SeqCst);
-  })
-  .expect("Error setting Ctrl+C handler.");
-  
-  // Meta files.
-  println!("\nListing project directory contents...");
-  walk_dir_files(project_path);
+fn scan_command(project: &mut Project, format: Format) -> i32 {
+  let (scan, import) = update_project(project, format);
+  let failed = failed_imports(project);
+  if format == Format::Text {
+    for (path, err) in failed.iter().filter(|(path, _)| !import.failed.iter().any(|(failed, _)| failed == path)) {
+      println!("Error importing {:?} (failed before, unchanged since): {}", path, err);
+    }
+  }
+  if format == Format::Json {
+    let failed: Vec<_> = failed.iter().map(|(path, err)| json!({ "path": path, "error": err })).collect();
+    print_json(&json!({
+      "scan": {
+        "added": scan.added,
+        "changed": scan.changed,
+        "removed": scan.removed.iter().map(|record| &record.path).collect::<Vec<_>>(),
+        "unchanged": scan.unchanged,
+        "errors": scan.errors.iter().map(|err| err.to_string()).collect::<Vec<_>>(),
+      },
+      "import": { "imported": import.imported, "failed": failed, "up_to_date": import.up_to_date },
+    }));
+  }
+  if scan.errors.is_empty() && failed.is_empty() { EXIT_OK } else { EXIT_PROBLEMS }
+}
 
-  println!();
-  println!("Theseus project server started.\nProject path: {:?}\nCtrl+C to stop the server.\n", project_assets_path.canonicalize().unwrap());
-  loop {
-    if live.load(Ordering::SeqCst) == false { break; }
+// Every asset whose last import failed. Failed imports aren't retried until something about the asset changes, so the
+// ones failing since an earlier run don't show up in this run's import report.
+fn failed_imports(project: &Project) -> Vec<(PathBuf, String)> {
+  let mut failed: Vec<(PathBuf, String)> = project
+    .pipeline
+    .records()
+    .filter_map(|(guid, record)| {
+      let error = record.error.as_ref()?;
+      Some((project.database.get_by_guid(guid)?.path.clone(), error.clone()))
+    })
+    .collect();
+  failed.sort();
+  failed
+}
 
-    thread::sleep(Duration::from_millis(8));
+fn verify_command(project: &Project, format: Format) -> i32 {
+  let report = verify::verify(project);
+  match format {
+    Format::Text => {
+      println!();
+      for problem in &report.problems { println!("{:?}: {}", problem.path, problem.message); }
+      println!("Verify complete: {} assets checked, {} problem(s).", report.assets, report.problems.len());
+    }
+    Format::Json => print_json(&report),
   }
+  if report.is_ok() { EXIT_OK } else { EXIT_PROBLEMS }
 }
 
-use std::io;
-use std::fs;
-// Recursively walk directories, call callback
-// fn visit_dirs(dir: &Path, cb: &dyn Fn(&DirEntry)) -> io::Result<()> {
-fn walk_dir_files(dir: &Path) -> io::Result<()> {
-  if dir.is_dir() {
-    for entry in fs::read_dir(dir)? {
-      let entry = entry?;
-      let path = entry.path();
-      if path.is_dir() {
-        // visit_dirs(&path, cb)?;
-        println!("DIR: {:?}", entry);
-        
-        let meta_path = get_meta_file(path.as_path()).unwrap();
-        println!("Meta path would be: {:?}", meta_path);
-
-        walk_dir_files(&path)?;
-      } else {
-        // cb(&entry);
-        println!("{:?}", entry);
-        
-        let meta_path = get_meta_file(path.as_path());
-        println!("Meta path would be: {:?}", meta_path);
+fn doctor_command(project: &Project, fix: bool, format: Format) -> i32 {
+  let report = verify::verify(project);
+  let mut fixed = Vec::new();
+  let mut failed = Vec::new();
+  if format == Format::Text {
+    println!();
+  }
+  for problem in &report.problems {
+    let fix_result = match problem.fix {
+      Some(_) if fix => Some(verify::apply_fix(project, problem)),
+      _ => None,
+    };
+    if format == Format::Text {
+      match (problem.fix, &fix_result) {
+        (Some(_), Some(Ok(()))) => println!("Fixed {:?}: {}", problem.path, problem.message),
+        (Some(_), Some(Err(err))) => println!("Error fixing {:?}: {}", problem.path, err),
+        (Some(fix), None) => println!("{:?}: {} (fix: {})", problem.path, problem.message, fix),
+        (None, _) => println!("{:?}: {} (no automatic fix)", problem.path, problem.message),
       }
     }
+    match fix_result {
+      Some(Ok(())) => fixed.push(problem),
+      Some(Err(err)) => failed.push(json!({ "path": problem.path, "error": err.to_string() })),
+      None => {}
+    }
+  }
+  let left = report.problems.len() - fixed.len();
+  match format {
+    Format::Text => println!(
+      "Doctor complete: {} assets checked, {} problem(s), {} fixed, {} left.",
+      report.assets, report.problems.len(), fixed.len(), left
+    ),
+    Format::Json => print_json(&json!({
+      "assets": report.assets,
+      "problems": report.problems,
+      "fixed": fixed.iter().map(|problem| &problem.path).collect::<Vec<_>>(),
+      "failed": failed,
+    })),
   }
-  Ok(())
+  if left == 0 { EXIT_OK } else { EXIT_PROBLEMS }
 }
 
-fn get_meta_file(path: &Path) -> io::Result<PathBuf> {
-  let meta_path;
-  if path.is_dir() {
-    // Dir + ".meta"
-    meta_path = path.with_extension("meta");
+fn gc_command(project: &mut Project, dry_run: bool, format: Format) -> i32 {
+  // Only what's gone from the assets is garbage, so the index has to be current first.
+  let scan = project.scan();
+  if format == Format::Text {
+    print_scan_report(&scan);
+  }
+  let report = match project.pipeline.collect_garbage(&project.database, dry_run) {
+    Ok(report) => report,
+    Err(err) => fail(format, "Error collecting garbage", &err),
+  };
+  save_project(project);
+  match format {
+    Format::Text => {
+      if VERBOSE {
+        for name in &report.orphans { println!("Orphaned: {}", name); }
+      }
+      let verb = if dry_run { "would be removed" } else { "removed" };
+      println!("GC complete: {} orphaned artifact(s) ({} bytes) {}.", report.orphans.len(), report.bytes, verb);
+    }
+    Format::Json => print_json(&json!({ "dry_run": dry_run, "orphans": report.orphans, "bytes": report.bytes })),
+  }
+  EXIT_OK
+}
+
+fn search_command(project: &Project, query: &str, format: Format) -> i32 {
+  let query = match Query::parse(query) {
+    Ok(query) => query,
+    Err(err) => fail(format, "Error searching", &err),
+  };
+  let found = search(&project.database, &query);
+  match format {
+    Format::Text => {
+      println!();
+      for asset in &found { println!("{:?} ({})", asset.path, asset.asset_type); }
+      println!("Search complete: {} asset(s) found.", found.len());
+    }
+    Format::Json => {
+      let found: Vec<Value> = found
+        .into_iter()
+        .map(|asset| {
+          json!({
+            "guid": asset.guid,
+            "path": asset.path,
+            "type": asset.asset_type,
+            "is_folder": asset.is_folder,
+            "dependencies": project.dependencies.dependencies_of(&asset.path),
+            "dependents": project.dependencies.dependents_of(&asset.path),
+            "labels": asset.labels,
+            "metadata": asset.metadata,
+          })
+        })
+        .collect();
+      print_json(&found);
+    }
+  }
+  EXIT_OK
+}
+
+fn preview_command(project: &Project, paths: Vec<PathBuf>, format: Format) -> i32 {
+  let listed = paths.is_empty();
+  let paths = if listed {
+    let mut paths: Vec<PathBuf> = project
+      .database
+      .iter()
+      .filter(|asset| asset.asset_type == "texture" || asset.asset_type == "ron")
+      .map(|asset| asset.path.clone())
+      .collect();
+    paths.sort();
+    paths
   } else {
-    // File + extension + ".meta"
-    let ext = path.extension().unwrap().to_str().unwrap();
-    let mut ext = String::from(ext);
-    ext.push_str(".meta");
-    meta_path = path.with_extension(ext);
+    paths
+  };
+  let (mut thumbnails, mut sheets, mut errors) = (Vec::new(), Vec::new(), Vec::new());
+  for path in paths {
+    if path.extension().is_some_and(|extension| extension == "ron") {
+      match preview::sprite_sheet_previews(project, &path) {
+        Ok(previews) => sheets.push((path, previews)),
+        // Most RON files in a project aren't sprite sheets, which only matters when one was asked for.
+        Err(PreviewError::NoSpriteSheets(_)) if listed => {}
+        Err(err) => errors.push(err),
+      }
+    } else {
+      match preview::thumbnail(project, &path) {
+        Ok(thumbnail) => thumbnails.push((path, thumbnail)),
+        Err(err) => errors.push(err),
+      }
+    }
+  }
+  match format {
+    Format::Text => {
+      println!();
+      for (path, thumbnail) in &thumbnails { println!("Thumbnail: {:?} -> {:?}", path, thumbnail.file); }
+      for (path, previews) in &sheets {
+        for preview in previews {
+          println!("Sprite sheet: {:?} ({} sprites) -> {:?}", path, preview.sprites.len(), preview.file);
+        }
+      }
+      for err in &errors { println!("Error: {}", err); }
+      println!(
+        "Preview complete: {} thumbnail(s), {} sprite sheet file(s), {} error(s).",
+        thumbnails.len(), sheets.len(), errors.len()
+      );
+    }
+    Format::Json => {
+      let thumbnails: Vec<Value> =
+        thumbnails.iter().map(|(path, thumbnail)| json!({ "path": path, "thumbnail": thumbnail })).collect();
+      let sheets: Vec<Value> = sheets.iter().map(|(path, previews)| json!({ "path": path, "previews": previews })).collect();
+      let errors: Vec<Value> = errors.iter().map(|err| json!({ "path": err.path(), "error": err.to_string() })).collect();
+      print_json(&json!({ "thumbnails": thumbnails, "sprite_sheets": sheets, "errors": errors }));
+    }
+  }
+  if errors.is_empty() { EXIT_OK } else { EXIT_PROBLEMS }
+}
+
+// Hands the process over to proj-server-async, which opens the project itself.
+fn serve_command(project_path: &Path, address: Option<SocketAddr>, format: Format) -> ! {
+  let mut command = server_command();
+  command.arg(project_path);
+  if let Some(address) = address {
+    command.arg("--address").arg(address.to_string());
+  }
+  if format == Format::Text {
+    println!("Starting proj-server-async on {:?}...", project_path);
+  }
+  fail_to_start(format, run_in_place(command))
+}
+
+fn fail_to_start(format: Format, err: io::Error) -> ! {
+  if err.kind() == io::ErrorKind::NotFound {
+    fail(format, "Error starting proj-server-async", &"not found next to project-server or on the PATH");
+  }
+  fail(format, "Error starting proj-server-async", &err)
+}
+
+// proj-server-async, from next to this program if it's there (as when they're installed together), otherwise from the
+// PATH.
+fn server_command() -> process::Command {
+  let name = format!("proj-server-async{}", env::consts::EXE_SUFFIX);
+  let beside = env::current_exe().ok().and_then(|exe| Some(exe.parent()?.join(&name))).filter(|path| path.is_file());
+  process::Command::new(beside.unwrap_or_else(|| PathBuf::from(name)))
+}
+
+// Runs `command` in place of this process, so Ctrl+C and the termination signal go straight to it. Only returns if it
+// can't be started.
+#[cfg(unix)]
+fn run_in_place(mut command: process::Command) -> io::Error {
+  use std::os::unix::process::CommandExt;
+  command.exec()
+}
+
+#[cfg(not(unix))]
+fn run_in_place(mut command: process::Command) -> io::Error {
+  match command.status() {
+    Ok(status) => process::exit(status.code().unwrap_or(EXIT_ERROR)),
+    Err(err) => err,
+  }
+}
+
+// Has proj-server-async stop the server (see its stop.rs), so only the server's own crate speaks its protocol.
+fn stop_command(project: &Project, format: Format) -> i32 {
+  let pid = match ProjectLock::status(&project.library_path) {
+    LockStatus::Unlocked => {
+      match format {
+        Format::Text => println!("\nNo server is running on this project."),
+        Format::Json => print_json(&json!({ "stopped": false })),
+      }
+      return EXIT_OK;
+    }
+    LockStatus::Locked(holder) => holder.map(|info| info.pid),
+  };
+  let mut command = server_command();
+  command.arg(&project.path).arg("--stop");
+  if format == Format::Text {
+    println!();
+    return match command.status() {
+      Ok(status) => status.code().unwrap_or(EXIT_ERROR),
+      Err(err) => fail_to_start(format, err),
+    };
+  }
+  let output = command.output().unwrap_or_else(|err| fail_to_start(format, err));
+  if !output.status.success() {
+    let printed = String::from_utf8_lossy(&output.stdout);
+    let reason = printed.lines().last().unwrap_or_default().trim_start_matches("Error stopping server: ");
+    fail(format, "Error stopping server", &reason);
+  }
+  print_json(&json!({ "stopped": true, "pid": pid }));
+  EXIT_OK
+}
+
+fn info_command(project: &Project, format: Format) -> i32 {
+  let mut types: BTreeMap<&str, usize> = BTreeMap::new();
+  for asset in project.database.iter() {
+    *types.entry(&asset.asset_type).or_default() += 1;
+  }
+  let import_errors = project.pipeline.records().filter(|(_, record)| record.error.is_some()).count();
+  match format {
+    Format::Text => {
+      println!();
+      println!("Library path: {:?}", project.library_path);
+      println!("Indexed assets: {}", project.database.len());
+      for (asset_type, count) in &types { println!("  {}: {}", asset_type, count); }
+      println!("Import errors: {}", import_errors);
+      println!("Change cursor: {}", project.changes.cursor());
+    }
+    Format::Json => print_json(&json!({
+      "name": project.manifest.project.name,
+      "engine_version": project.manifest.project.engine_version,
+      "path": project.path,
+      "asset_roots": project.asset_roots,
+      "library_path": project.library_path,
+      "assets": project.database.len(),
+      "types": types,
+      "import_errors": import_errors,
+      "change_cursor": project.changes.cursor(),
+    })),
+  }
+  EXIT_OK
+}
+
+fn save_project(project: &Project) {
+  if let Err(err) = project.save() {
+    println!("Error saving project library {:?}: {}", project.library_path, err);
+  }
+}
+
+fn print_scan_report(report: &ScanReport) {
+  if VERBOSE {
+    for path in &report.added { println!("Added: {:?}", path); }
+    for path in &report.changed { println!("Changed: {:?}", path); }
+    for record in &report.removed { println!("Removed: {:?}", record.path); }
+  }
+  for err in &report.errors { println!("Error: {}", err); }
+  println!(
+    "Scan complete: {} added, {} changed, {} removed, {} unchanged.",
+    report.added.len(), report.changed.len(), report.removed.len(), report.unchanged
+  );
+}
+
+fn print_import_report(report: &ImportReport) {
+  if VERBOSE {
+    for path in &report.imported { println!("Imported: {:?}", path); }
   }
-  return Ok(meta_path);
+  for (path, err) in &report.failed { println!("Error importing {:?}: {}", path, err); }
+  println!(
+    "Import complete: {} imported, {} failed, {} up to date.",
+    report.imported.len(), report.failed.len(), report.up_to_date
+  );
+}
+
+fn print_pack_report(report: &PackReport, format: Format) {
+  if format == Format::Json {
+    return print_json(&json!({ "packed": report.packed, "skipped": report.skipped, "bytes": report.bytes }));
+  }
+  if VERBOSE {
+    for path in &report.packed { println!("Packed: {:?}", path); }
+    for path in &report.skipped { println!("Skipped (source only): {:?}", path); }
+  }
+  println!(
+    "Pack complete: {} packed ({} bytes), {} skipped.",
+    report.packed.len(), report.bytes, report.skipped.len()
+  );
+}
+
+fn print_json<T: Serialize>(value: &T) {
+  match serde_json::to_string_pretty(value) {
+    Ok(json) => println!("{}", json),
+    Err(err) => println!("{{\"error\": {:?}}}", err.to_string()),
+  }
+}
+
+fn print_error(format: Format, context: &str, err: &dyn fmt::Display) {
+  match format {
+    Format::Text => println!("{}: {}", context, err),
+    Format::Json => print_json(&json!({ "error": format!("{}: {}", context, err) })),
+  }
+}
+
+// Reports an error that keeps the command from running and exits.
+fn fail(format: Format, context: &str, err: &dyn fmt::Display) -> ! {
+  print_error(format, context, err);
+  process::exit(EXIT_ERROR);
 }
@@ -0,0 +1,161 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::error::ProjectError;
+
+// Project Manifest
+// ----------------
+//
+// theseus.toml at the root of a project folder:
+//
+//   [project]
+//   name = "Arkanoid"
+//   engine_version = "0.1"
+//   asset_roots = ["Assets"]
+//
+//   [game]
+//   path = "."
+//   package = "arkanoid"
+//   cargo_args = ["--features", "vulkan"]
+//
+// Asset roots are relative to the project folder. When several roots hold the same relative path, the first one wins, the
+// same way a game searches its asset sources.
+//
+// The optional [game] section names the Cargo crate or workspace the assets are for, relative to the project folder, so
+// the server can build and run it. Without one, a project folder with its own Cargo.toml is taken to be the game.
+//
+
+pub const MANIFEST_FILE: &str = "theseus.toml";
+pub const DEFAULT_ASSETS_FOLDER: &str = "Assets";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+  pub project: ProjectSection,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub game: Option<GameSection>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProjectSection {
+  pub name: String,
+  pub engine_version: String,
+  #[serde(default = "default_asset_roots")]
+  pub asset_roots: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GameSection {
+  // The folder with the game's Cargo.toml.
+  #[serde(default = "default_game_path")]
+  pub path: PathBuf,
+  // Which package to build and run, for a workspace with several.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub package: Option<String>,
+  // Passed to both cargo build and cargo run, e.g. features.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub cargo_args: Vec<String>,
+}
+
+fn default_asset_roots() -> Vec<PathBuf> {
+  vec![PathBuf::from(DEFAULT_ASSETS_FOLDER)]
+}
+
+fn default_game_path() -> PathBuf {
+  PathBuf::from(".")
+}
+
+impl Manifest {
+  pub fn new(name: &str) -> Manifest {
+    Manifest {
+      project: ProjectSection {
+        name: String::from(name),
+        engine_version: String::from(env!("CARGO_PKG_VERSION")),
+        asset_roots: default_asset_roots(),
+      },
+      game: None,
+    }
+  }
+
+  // Loads the manifest of the project at `project_path`. A folder without a manifest that still has an Assets folder is
+  // treated as a project with default settings, so projects from before manifests keep opening.
+  pub fn load(project_path: &Path) -> Result<Manifest, ProjectError> {
+    let manifest_path = project_path.join(MANIFEST_FILE);
+    if !manifest_path.exists() {
+      if project_path.join(DEFAULT_ASSETS_FOLDER).is_dir() {
+        eprintln!("{:?} has no {}; using default project settings.", project_path, MANIFEST_FILE);
+        return Ok(Manifest::new(&default_name(project_path)));
+      }
+      return Err(ProjectError::NotAProject { path: project_path.to_path_buf() });
+    }
+
+    let contents = fs::read_to_string(&manifest_path)
+      .map_err(|source| ProjectError::ManifestUnreadable { path: manifest_path.clone(), source })?;
+    let manifest: Manifest = toml::from_str(&contents)
+      .map_err(|err| ProjectError::ManifestInvalid { path: manifest_path.clone(), message: err.to_string() })?;
+    manifest.validate(&manifest_path)?;
+    Ok(manifest)
+  }
+
+  pub fn save(&self, project_path: &Path) -> Result<(), ProjectError> {
+    let manifest_path = project_path.join(MANIFEST_FILE);
+    let contents = toml::to_string_pretty(self)
+      .map_err(|err| ProjectError::ManifestInvalid { path: manifest_path.clone(), message: err.to_string() })?;
+    fs::write(&manifest_path, contents).map_err(|source| ProjectError::ManifestUnwritable { path: manifest_path, source })
+  }
+
+  fn validate(&self, manifest_path: &Path) -> Result<(), ProjectError> {
+    let invalid =
+      |message: &str| ProjectError::ManifestInvalid { path: manifest_path.to_path_buf(), message: String::from(message) };
+    if self.project.name.trim().is_empty() {
+      return Err(invalid("project name is empty"));
+    }
+    if self.project.asset_roots.is_empty() {
+      return Err(invalid("no asset roots"));
+    }
+    if self.project.asset_roots.iter().any(|root| root.is_absolute() || root.components().any(|c| c.as_os_str() == "..")) {
+      return Err(invalid("asset roots must be inside the project folder"));
+    }
+    if let Some(game) = &self.game {
+      if game.path.is_absolute() {
+        return Err(invalid("the game path must be relative to the project folder"));
+      }
+      if game.package.as_ref().is_some_and(|package| package.trim().is_empty()) {
+        return Err(invalid("game package is empty"));
+      }
+    }
+    Ok(())
+  }
+
+  // Absolute paths of the asset roots, checked to exist.
+  pub fn asset_root_paths(&self, project_path: &Path) -> Result<Vec<PathBuf>, ProjectError> {
+    self
+      .project
+      .asset_roots
+      .iter()
+      .map(|root| {
+        let path = project_path.join(root);
+        if !path.is_dir() {
+          return Err(ProjectError::AssetRootMissing { path });
+        }
+        path.canonicalize().map_err(|source| ProjectError::DirectoryUnreadable { path, source })
+      })
+      .collect()
+  }
+
+  // The folder of the game's Cargo.toml, if the project has a game.
+  pub fn game_path(&self, project_path: &Path) -> Option<PathBuf> {
+    match &self.game {
+      Some(game) => Some(project_path.join(&game.path)),
+      None if project_path.join("Cargo.toml").is_file() => Some(project_path.to_path_buf()),
+      None => None,
+    }
+  }
+}
+
+pub(crate) fn default_name(project_path: &Path) -> String {
+  project_path
+    .canonicalize()
+    .ok()
+    .and_then(|path| path.file_name().map(|name| name.to_string_lossy().into_owned()))
+    .unwrap_or_else(|| String::from("Untitled"))
+}
@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use crate::error::ProjectError;
+
+// Meta Files
+// ----------
+//
+// Every asset and folder under Assets has a sibling ".meta" file. The meta file gives the asset an identity (its GUID) that
+// survives renames and moves, and carries the settings its importer should use. Meta files are plain RON so they can be
+// read, diffed and merged by hand.
+//
+// Users can also give an asset labels and free-form metadata, which the server indexes for searching:
+//
+//   (
+//     format_version: 1,
+//     guid: "...",
+//     importer: (name: "texture", settings: {}),
+//     labels: ["ui", "menu"],
+//     metadata: {"author": "nick"},
+//   )
+//
+
+// Bump this whenever the layout of `Meta` changes in a way older servers can't read.
+pub const META_FORMAT_VERSION: u32 = 1;
+
+pub const META_EXTENSION: &str = "meta";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Meta {
+  pub format_version: u32,
+  pub guid: Uuid,
+  pub importer: ImporterSettings,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub labels: Vec<String>,
+  #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+  pub metadata: BTreeMap<String, String>,
+}
+
+// Which importer processes the asset, and the per-asset settings handed to it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImporterSettings {
+  pub name: String,
+  #[serde(default)]
+  pub settings: BTreeMap<String, ron::Value>,
+}
+
+impl ImporterSettings {
+  pub fn named(name: &str) -> ImporterSettings {
+    ImporterSettings { name: String::from(name), settings: BTreeMap::new() }
+  }
+
+  // The importer a freshly discovered asset starts out with, picked from its extension.
+  pub fn default_for(asset_path: &Path) -> ImporterSettings {
+    if asset_path.is_dir() {
+      return ImporterSettings::named("folder");
+    }
+    let ext = asset_path.extension().and_then(|ext| ext.to_str()).map(|ext| ext.to_ascii_lowercase());
+    let name = match ext.as_deref() {
+      Some("png") => "texture",
+      Some("ttf") => "font",
+      Some("ron") => "ron",
+      _ => "default",
+    };
+    ImporterSettings::named(name)
+  }
+
+  pub fn get_str(&self, key: &str) -> Option<&str> {
+    match self.settings.get(key) {
+      Some(ron::Value::String(value)) => Some(value),
+      _ => None,
+    }
+  }
+
+  pub fn get_bool(&self, key: &str) -> Option<bool> {
+    match self.settings.get(key) {
+      Some(ron::Value::Bool(value)) => Some(*value),
+      _ => None,
+    }
+  }
+
+  pub fn get_i64(&self, key: &str) -> Option<i64> {
+    match self.settings.get(key) {
+      Some(ron::Value::Number(value)) => value.as_i64(),
+      _ => None,
+    }
+  }
+
+  // A list of strings, or None if the setting is missing or anything in it isn't a string.
+  pub fn get_str_list(&self, key: &str) -> Option<Vec<&str>> {
+    match self.settings.get(key) {
+      Some(ron::Value::Seq(values)) => values
+        .iter()
+        .map(|value| match value {
+          ron::Value::String(value) => Some(value.as_str()),
+          _ => None,
+        })
+        .collect(),
+      _ => None,
+    }
+  }
+
+  // Hex SHA-256 of the settings, so changes to them can be noticed without keeping the settings themselves around.
+  pub fn settings_hash(&self) -> String {
+    let serialized = ron::ser::to_string(self).unwrap_or_default();
+    format!("{:x}", Sha256::digest(serialized.as_bytes()))
+  }
+}
+
+impl Meta {
+  // A new meta with a freshly generated GUID.
+  pub fn new(importer: ImporterSettings) -> Meta {
+    Meta {
+      format_version: META_FORMAT_VERSION,
+      guid: Uuid::new_v4(),
+      importer,
+      labels: Vec::new(),
+      metadata: BTreeMap::new(),
+    }
+  }
+
+  pub fn read(meta_path: &Path) -> Result<Meta, ProjectError> {
+    let contents = fs::read_to_string(meta_path)
+      .map_err(|source| ProjectError::MetaUnreadable { path: meta_path.to_path_buf(), source })?;
+    let meta: Meta = ron::de::from_str(&contents)
+      .map_err(|err| ProjectError::MetaInvalid { path: meta_path.to_path_buf(), message: err.to_string() })?;
+    if meta.format_version > META_FORMAT_VERSION {
+      return Err(ProjectError::MetaVersionUnsupported { path: meta_path.to_path_buf(), version: meta.format_version });
+    }
+    Ok(meta)
+  }
+
+  pub fn write(&self, meta_path: &Path) -> Result<(), ProjectError> {
+    let pretty = ron::ser::PrettyConfig::new().with_indentor(String::from("  "));
+    let contents = ron::ser::to_string_pretty(self, pretty)
+      .map_err(|err| ProjectError::MetaInvalid { path: meta_path.to_path_buf(), message: err.to_string() })?;
+    fs::write(meta_path, contents + "\n")
+      .map_err(|source| ProjectError::MetaUnwritable { path: meta_path.to_path_buf(), source })
+  }
+}
+
+// Whether the path is itself a meta file (and so never gets a meta file of its own).
+pub fn is_meta_file(path: &Path) -> bool {
+  path.extension().is_some_and(|ext| ext == META_EXTENSION)
+}
+
+// The meta file sits next to its asset and is named after it: "logo.png" -> "logo.png.meta", "Scripts" -> "Scripts.meta".
+// Only the name is looked at, so this also works for assets that were just moved or deleted.
+pub fn get_meta_file(path: &Path) -> Result<PathBuf, ProjectError> {
+  let file_name = path.file_name().ok_or_else(|| ProjectError::InvalidAssetPath { path: path.to_path_buf() })?;
+  let mut meta_name = file_name.to_os_string();
+  meta_name.push(".");
+  meta_name.push(META_EXTENSION);
+  Ok(path.with_file_name(meta_name))
+}
+
+// Reads the asset's meta file, creating it with a new GUID if it doesn't exist yet. Also returns whether the meta file was
+// created.
+pub fn load_or_create_meta(asset_path: &Path) -> Result<(Meta, bool), ProjectError> {
+  let meta_path = get_meta_file(asset_path)?;
+  if meta_path.exists() {
+    return Ok((Meta::read(&meta_path)?, false));
+  }
+  let meta = Meta::new(ImporterSettings::default_for(asset_path));
+  meta.write(&meta_path)?;
+  Ok((meta, true))
+}
+
+// The asset a meta file belongs to: "logo.png.meta" -> "logo.png". None if the path isn't a meta file.
+pub fn get_asset_for_meta(meta_path: &Path) -> Option<PathBuf> {
+  if !is_meta_file(meta_path) {
+    return None;
+  }
+  meta_path.file_stem().map(|stem| meta_path.with_file_name(stem))
+}
+
+// Carries an asset's meta file along when the asset is moved, so it keeps its GUID. Returns whether a meta file was moved.
+// A meta file already waiting at the destination (e.g. because it was moved together with the asset) is left alone.
+pub fn move_meta(from: &Path, to: &Path) -> Result<bool, ProjectError> {
+  let old_meta_path = get_meta_file(from)?;
+  let new_meta_path = get_meta_file(to)?;
+  if !old_meta_path.exists() || new_meta_path.exists() {
+    return Ok(false);
+  }
+  fs::rename(&old_meta_path, &new_meta_path).map_err(|source| ProjectError::MetaUnwritable { path: new_meta_path, source })?;
+  Ok(true)
+}
+
+// Deletes the meta file of an asset that no longer exists. Returns whether a meta file was removed.
+pub fn remove_meta(asset_path: &Path) -> Result<bool, ProjectError> {
+  let meta_path = get_meta_file(asset_path)?;
+  if asset_path.exists() || !meta_path.exists() {
+    return Ok(false);
+  }
+  fs::remove_file(&meta_path).map_err(|source| ProjectError::MetaUnwritable { path: meta_path, source })?;
+  Ok(true)
+}
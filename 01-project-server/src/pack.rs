@@ -0,0 +1,211 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+use crate::database::AssetRecord;
+use crate::importer::generated_file_path;
+use crate::project::Project;
+use crate::references::reference_string;
+
+// Asset Packs
+// -----------
+//
+// A pack is a single archive of imported assets for shipping a game. It's a zip file with one deflated entry per asset,
+// named by the asset's path (as RON files refer to it) and holding the asset's main artifact, plus an index listing every
+// entry with its GUID, type, size and SHA-256 so a loader can tell when an entry was tampered with. Files generated
+// from an asset, such as a texture's sprite sheet, get entries of their own under the paths RON files refer to them by.
+//
+// Meta files, folders and source-only files (anything without an importer) are left out. Packing a subset of the assets
+// also packs everything they refer to, so the subset still loads on its own.
+//
+
+pub const PACK_INDEX: &str = "theseus-pack.json";
+pub const PACK_FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PackIndex {
+  pub format_version: u32,
+  pub project: String,
+  pub entries: Vec<PackEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PackEntry {
+  pub path: String,
+  pub guid: Uuid,
+  #[serde(rename = "type")]
+  pub asset_type: String,
+  pub size: u64,
+  // Hex SHA-256 of the entry's (uncompressed) contents.
+  pub hash: String,
+}
+
+#[derive(Debug, Default)]
+pub struct PackReport {
+  pub packed: Vec<PathBuf>,
+  // Source-only files that were left out.
+  pub skipped: Vec<PathBuf>,
+  // Total size of the packed entries before compression.
+  pub bytes: u64,
+}
+
+#[derive(Debug)]
+pub enum PackError {
+  Io(io::Error),
+  Zip(zip::result::ZipError),
+  // A path given to pack that isn't an asset.
+  AssetNotFound(PathBuf),
+  // Assets that would have to be packed but have no up-to-date artifacts, with the reason for each.
+  NotImported(Vec<(PathBuf, String)>),
+}
+
+impl fmt::Display for PackError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      PackError::Io(err) => write!(f, "{}", err),
+      PackError::Zip(err) => write!(f, "{}", err),
+      PackError::AssetNotFound(path) => write!(f, "{:?}: no such asset", path),
+      PackError::NotImported(assets) => {
+        write!(f, "{} asset(s) aren't imported:", assets.len())?;
+        for (path, reason) in assets {
+          write!(f, "\n  {:?}: {}", path, reason)?;
+        }
+        Ok(())
+      }
+    }
+  }
+}
+
+impl std::error::Error for PackError {}
+
+impl From<io::Error> for PackError {
+  fn from(err: io::Error) -> PackError {
+    PackError::Io(err)
+  }
+}
+
+impl From<zip::result::ZipError> for PackError {
+  fn from(err: zip::result::ZipError) -> PackError {
+    PackError::Zip(err)
+  }
+}
+
+// Packs the assets in `paths` (assets or folders relative to the asset root), or every asset if `paths` is empty, into
+// the archive at `output`. The project should have been scanned and imported first; nothing is imported here.
+pub fn pack(project: &Project, output: &Path, paths: &[PathBuf]) -> Result<PackReport, PackError> {
+  let selected = select_assets(project, paths)?;
+
+  let mut report = PackReport::default();
+  let mut entries = Vec::new();
+  let mut not_imported = Vec::new();
+  for path in selected {
+    let asset = match project.database.get(&path) {
+      Some(asset) => asset,
+      None => continue,
+    };
+    let source_path = project.database.absolute(&asset.path);
+    let importer = match project.pipeline.registry().for_asset(&source_path, &asset.asset_type) {
+      Some(importer) => importer,
+      None if asset.is_folder => continue,
+      None => {
+        report.skipped.push(asset.path.clone());
+        continue;
+      }
+    };
+    let record = match project.pipeline.record(&asset.guid) {
+      _ if project.pipeline.needs_import(&project.database, asset) => Err(String::from("out of date")),
+      Some(record) if record.error.is_some() => Err(record.error.clone().unwrap_or_default()),
+      Some(record) if !record.artifacts.is_empty() || asset.is_folder => Ok(record),
+      _ => Err(String::from("no artifacts")),
+    };
+    let record = match record {
+      Ok(record) => record,
+      Err(reason) => {
+        not_imported.push((asset.path.clone(), reason));
+        continue;
+      }
+    };
+    let artifacts_path = project.pipeline.artifacts_path(&asset.guid);
+    // Folders only ship the files they generate.
+    if !asset.is_folder {
+      entries.push((asset.path.clone(), asset, artifacts_path.join(&record.artifacts[0])));
+    }
+    for (artifact, extension) in importer.generated_files() {
+      if record.artifacts.iter().any(|name| name == artifact) {
+        let path = generated_file_path(&asset.path, asset.is_folder, extension);
+        entries.push((path, asset, artifacts_path.join(artifact)));
+      }
+    }
+  }
+  if !not_imported.is_empty() {
+    return Err(PackError::NotImported(not_imported));
+  }
+
+  // Written next to the output first, so a failed pack doesn't leave a broken archive where the last good one was.
+  let temp_path = output.with_extension("pack.tmp");
+  let written = write_pack(project, &temp_path, &entries, &mut report);
+  if written.is_err() {
+    let _ = fs::remove_file(&temp_path);
+  }
+  written?;
+  fs::rename(&temp_path, output)?;
+  Ok(report)
+}
+
+// The assets to pack: the given paths, everything inside the given folders and, recursively, everything they refer to.
+fn select_assets(project: &Project, paths: &[PathBuf]) -> Result<BTreeSet<PathBuf>, PackError> {
+  if paths.is_empty() {
+    return Ok(project.database.iter().map(|asset| asset.path.clone()).collect());
+  }
+  let mut selected = BTreeSet::new();
+  let mut queue = VecDeque::new();
+  for path in paths {
+    if project.database.get(path).is_none() {
+      return Err(PackError::AssetNotFound(path.clone()));
+    }
+    queue.extend(project.database.iter().filter(|asset| asset.path.starts_with(path)).map(|asset| asset.path.clone()));
+  }
+  while let Some(path) = queue.pop_front() {
+    if selected.insert(path.clone()) {
+      queue.extend(project.dependencies.dependencies_of(&path));
+    }
+  }
+  Ok(selected)
+}
+
+fn write_pack(
+  project: &Project,
+  pack_path: &Path,
+  entries: &[(PathBuf, &AssetRecord, PathBuf)],
+  report: &mut PackReport,
+) -> Result<(), PackError> {
+  let mut zip = ZipWriter::new(File::create(pack_path)?);
+  let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+  let mut index =
+    PackIndex { format_version: PACK_FORMAT_VERSION, project: project.manifest.project.name.clone(), entries: Vec::new() };
+  for (path, asset, artifact_path) in entries {
+    let name = reference_string(path).ok_or_else(|| PackError::AssetNotFound(path.clone()))?;
+    let contents = fs::read(artifact_path)?;
+    zip.start_file(name.as_str(), options)?;
+    zip.write_all(&contents)?;
+    index.entries.push(PackEntry {
+      path: name,
+      guid: asset.guid,
+      asset_type: asset.asset_type.clone(),
+      size: contents.len() as u64,
+      hash: format!("{:x}", Sha256::digest(&contents)),
+    });
+    report.packed.push(path.clone());
+    report.bytes += contents.len() as u64;
+  }
+  zip.start_file(PACK_INDEX, options)?;
+  serde_json::to_writer_pretty(&mut zip, &index).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+  zip.finish()?;
+  Ok(())
+}
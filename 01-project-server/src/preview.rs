@@ -0,0 +1,218 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use crate::error::ProjectError;
+use crate::image::{Image, ImageError};
+use crate::lfs::{self, LFS_FIX};
+use crate::project::Project;
+use crate::sprites::{find_sprite_sheets, standalone_texture, Sprite};
+
+// Asset Previews
+// --------------
+//
+// Thumbnails of textures and previews of sprite sheets for the editor's asset browser, drawn on the CPU so the server can
+// make them headless. A sprite sheet preview is its texture with every sprite outlined and numbered the way Amethyst
+// numbers them; textures too small for the numbers to fit are blown up first.
+//
+// Both are cached in Library/Thumbnails, named by a hash of the image contents (and sprite layout) they're drawn from.
+// An unchanged image is only ever drawn once, and a stale preview can't be handed out for a changed one. Nothing refers
+// to the cache but its own names, so the folder can be deleted at any time.
+//
+
+pub const THUMBNAILS_FOLDER: &str = "Thumbnails";
+// The longest side of a thumbnail.
+pub const THUMBNAIL_SIZE: u32 = 128;
+// Bump when changing how previews are drawn, so cached ones are drawn again.
+const PREVIEW_VERSION: u32 = 1;
+// Sprite sheet previews are scaled up by a whole factor until their longest side is at least this long.
+const PREVIEW_MIN_SIZE: u32 = 256;
+const PREVIEW_MAX_SCALE: u32 = 16;
+const OUTLINE_COLOR: [u8; 4] = [255, 0, 255, 255];
+const LABEL_COLOR: [u8; 4] = [255, 255, 255, 255];
+const LABEL_BACKDROP: [u8; 4] = [0, 0, 0, 176];
+const LABEL_SCALE: u32 = 2;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Thumbnail {
+  // The cached PNG.
+  pub file: PathBuf,
+  pub width: u32,
+  pub height: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SheetPreview {
+  // The cached PNG.
+  pub file: PathBuf,
+  pub width: u32,
+  pub height: u32,
+  // The sheet's texture, relative to its asset root.
+  pub texture: PathBuf,
+  // Preview pixels per texture pixel.
+  pub scale: u32,
+  // In texture pixels, in the order Amethyst numbers them from 0.
+  pub sprites: Vec<Sprite>,
+}
+
+#[derive(Debug)]
+pub enum PreviewError {
+  Project(ProjectError),
+  // An image that couldn't be decoded, or a preview that couldn't be encoded.
+  Image(PathBuf, ImageError),
+  // A RON file with sprite sheets that couldn't be read.
+  InvalidSheet(PathBuf, String),
+  // A RON file without any sprite sheets.
+  NoSpriteSheets(PathBuf),
+  // A Git LFS pointer in place of the file.
+  LfsPointer(PathBuf),
+}
+
+impl PreviewError {
+  pub fn path(&self) -> &Path {
+    match self {
+      PreviewError::Project(err) => err.path(),
+      PreviewError::Image(path, _)
+      | PreviewError::InvalidSheet(path, _)
+      | PreviewError::NoSpriteSheets(path)
+      | PreviewError::LfsPointer(path) => path,
+    }
+  }
+}
+
+impl fmt::Display for PreviewError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      PreviewError::Project(err) => write!(f, "{}", err),
+      PreviewError::Image(path, err) => write!(f, "{:?}: {}", path, err),
+      PreviewError::InvalidSheet(path, message) => write!(f, "{:?}: invalid sprite sheet: {}", path, message),
+      PreviewError::NoSpriteSheets(path) => write!(f, "{:?}: no sprite sheets in file", path),
+      PreviewError::LfsPointer(path) => write!(f, "{:?}: a Git LFS pointer, not the file itself: {}", path, LFS_FIX),
+    }
+  }
+}
+
+impl std::error::Error for PreviewError {}
+
+impl From<ProjectError> for PreviewError {
+  fn from(err: ProjectError) -> PreviewError {
+    PreviewError::Project(err)
+  }
+}
+
+// The thumbnail of the PNG at `path`, relative to its asset root, drawn unless it's cached.
+pub fn thumbnail(project: &Project, path: &Path) -> Result<Thumbnail, PreviewError> {
+  let bytes = read_asset(project, path)?;
+  let key = cache_key(&["thumbnail", &THUMBNAIL_SIZE.to_string(), &hash(&bytes)]);
+  let (file, width, height) = cached(project, &key, || {
+    let image = Image::decode_png(&bytes).map_err(|err| PreviewError::Image(path.to_path_buf(), err))?;
+    Ok(image.fit(THUMBNAIL_SIZE))
+  })?;
+  Ok(Thumbnail { file, width, height })
+}
+
+// Previews of the sprite sheets defined in the RON file at `path`, relative to its asset root, drawn unless they're
+// cached.
+pub fn sprite_sheet_previews(project: &Project, path: &Path) -> Result<Vec<SheetPreview>, PreviewError> {
+  let text = String::from_utf8(read_asset(project, path)?)
+    .map_err(|_| PreviewError::InvalidSheet(path.to_path_buf(), String::from("not UTF-8 text")))?;
+  let sheets = find_sprite_sheets(&text).map_err(|message| PreviewError::InvalidSheet(path.to_path_buf(), message))?;
+  if sheets.is_empty() {
+    return Err(PreviewError::NoSpriteSheets(path.to_path_buf()));
+  }
+
+  let mut previews = Vec::new();
+  for sheet in sheets {
+    let texture = sheet.texture.as_ref().map_or_else(|| standalone_texture(path), PathBuf::from);
+    let bytes = read_asset(project, &texture)?;
+    let layout = format!("{}x{} {:?}", sheet.texture_width, sheet.texture_height, sheet.sprites);
+    let key = cache_key(&["sheet", &hash(&bytes), &layout]);
+    let image_error = |err| PreviewError::Image(texture.clone(), err);
+    let (texture_width, texture_height) = Image::png_size(&bytes).map_err(image_error)?;
+    let scale = PREVIEW_MIN_SIZE.div_ceil(texture_width.max(texture_height).max(1)).clamp(1, PREVIEW_MAX_SCALE);
+    let (file, width, height) = cached(project, &key, || {
+      let image = Image::decode_png(&bytes).map_err(image_error)?;
+      Ok(draw_sheet(&image, scale, sheet.texture_width, sheet.texture_height, &sheet.sprites))
+    })?;
+    previews.push(SheetPreview { file, width, height, texture, scale, sprites: sheet.sprites });
+  }
+  Ok(previews)
+}
+
+// The texture scaled up, with every sprite outlined and numbered. Sprite coordinates are in the texture size the sheet
+// was written for, which Amethyst scales to the actual texture.
+fn draw_sheet(texture: &Image, scale: u32, texture_width: u32, texture_height: u32, sprites: &[Sprite]) -> Image {
+  let mut image = texture.resize(texture.width * scale, texture.height * scale);
+  let to_x = |x: u32| (x as u64 * image.width as u64 / texture_width.max(1) as u64) as u32;
+  let to_y = |y: u32| (y as u64 * image.height as u64 / texture_height.max(1) as u64) as u32;
+  let rects: Vec<(u32, u32, u32, u32)> = sprites
+    .iter()
+    .map(|sprite| {
+      let (x, y) = (to_x(sprite.x), to_y(sprite.y));
+      (x, y, to_x(sprite.x + sprite.width) - x, to_y(sprite.y + sprite.height) - y)
+    })
+    .collect();
+  // All outlines go first, so a neighbour's outline never covers a number.
+  for &(x, y, width, height) in &rects {
+    image.outline_rect(x, y, width, height, OUTLINE_COLOR);
+  }
+  for (index, &(x, y, _, _)) in rects.iter().enumerate() {
+    image.draw_number(x + 1, y + 1, index, LABEL_SCALE, LABEL_COLOR, LABEL_BACKDROP);
+  }
+  image
+}
+
+// The cached PNG named `key`, drawn and written first if it isn't there yet, with its width and height.
+fn cached<F>(project: &Project, key: &str, draw: F) -> Result<(PathBuf, u32, u32), PreviewError>
+where
+  F: FnOnce() -> Result<Image, PreviewError>,
+{
+  let unwritable = |path: &Path| {
+    let path = path.to_path_buf();
+    move |source| ProjectError::FileUnwritable { path, source }
+  };
+  let folder = project.library_path.join(THUMBNAILS_FOLDER);
+  fs::create_dir_all(&folder).map_err(unwritable(&folder))?;
+  // Clients get the file's absolute path, whatever folder the server was started from.
+  let folder = folder.canonicalize().map_err(|source| ProjectError::DirectoryUnreadable { path: folder, source })?;
+  let file = folder.join(format!("{}.png", key));
+  if let Ok(bytes) = fs::read(&file) {
+    if let Ok(image) = Image::decode_png(&bytes) {
+      return Ok((file, image.width, image.height));
+    }
+  }
+  let image = draw()?;
+  let bytes = image.encode_png().map_err(|err| PreviewError::Image(file.clone(), err))?;
+  // Written next to its final name and renamed, so a client never reads a half-written file.
+  let temp = folder.join(format!("{}.png.tmp", key));
+  fs::write(&temp, bytes).map_err(unwritable(&temp))?;
+  fs::rename(&temp, &file).map_err(unwritable(&file))?;
+  Ok((file, image.width, image.height))
+}
+
+fn read_asset(project: &Project, path: &Path) -> Result<Vec<u8>, PreviewError> {
+  let file = project.database.absolute(path);
+  if !file.is_file() {
+    return Err(ProjectError::AssetNotFound { path: path.to_path_buf() }.into());
+  }
+  let bytes = fs::read(&file).map_err(|source| ProjectError::FileUnreadable { path: file, source })?;
+  if lfs::is_pointer(&bytes) {
+    return Err(PreviewError::LfsPointer(path.to_path_buf()));
+  }
+  Ok(bytes)
+}
+
+fn hash(bytes: &[u8]) -> String {
+  format!("{:x}", Sha256::digest(bytes))
+}
+
+fn cache_key(parts: &[&str]) -> String {
+  let mut hasher = Sha256::new();
+  hasher.update(PREVIEW_VERSION.to_le_bytes());
+  for part in parts {
+    hasher.update((part.len() as u64).to_le_bytes());
+    hasher.update(part.as_bytes());
+  }
+  format!("{:x}", hasher.finalize())
+}
@@ -0,0 +1,507 @@
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use crate::changes::{ChangeKind, ChangeLog};
+use crate::database::{AssetDatabase, AssetRecord, Refresh, ScanReport, LIBRARY_FOLDER};
+use crate::dependencies::DependencyGraph;
+use crate::error::ProjectError;
+use crate::ignore_rules::{is_ignore_file, IgnoreRules};
+use crate::importer::{generated_file_path, ImportPipeline, ImportReport, ImporterRegistry};
+use crate::manifest::Manifest;
+use crate::meta::{get_asset_for_meta, get_meta_file, is_meta_file, move_meta, remove_meta};
+use crate::references::{apply_rewrites, FileRewrite, ReferenceEdit};
+use crate::watcher::AssetEvent;
+
+// Project
+// -------
+//
+// An open project: its manifest, the asset database indexing its asset roots, the import pipeline processing them into
+// the Library folder, the graph of which assets refer to which, and the log of changes clients are told about.
+//
+
+pub struct Project {
+  pub path: PathBuf,
+  pub manifest: Manifest,
+  // Absolute paths of the asset roots, highest priority first.
+  pub asset_roots: Vec<PathBuf>,
+  pub library_path: PathBuf,
+  pub database: AssetDatabase,
+  pub pipeline: ImportPipeline,
+  pub dependencies: DependencyGraph,
+  pub changes: ChangeLog,
+}
+
+impl Project {
+  // Opens the project at `path`, loading whatever the Library folder remembers from the last run. Nothing is scanned yet.
+  pub fn open(path: &Path, registry: ImporterRegistry) -> Result<Project, ProjectError> {
+    let manifest = Manifest::load(path)?;
+    let asset_roots = manifest.asset_root_paths(path)?;
+    let library_path = path.join(LIBRARY_FOLDER);
+    let (ignore, errors) = IgnoreRules::load(path);
+    for err in &errors {
+      eprintln!("Error: {}", err);
+    }
+    let database = AssetDatabase::load(&asset_roots, &library_path, ignore);
+    let pipeline = ImportPipeline::load(&library_path, registry);
+    let dependencies = DependencyGraph::build(&database, &pipeline);
+    let changes = ChangeLog::load(&library_path);
+    Ok(Project {
+      path: path.to_path_buf(),
+      manifest,
+      asset_roots,
+      library_path,
+      database,
+      pipeline,
+      dependencies,
+      changes,
+    })
+  }
+
+  // Brings the asset database up to date. Assets referring to anything that appeared or disappeared are marked dirty, so
+  // the next `import_all` imports them again.
+  pub fn scan(&mut self) -> ScanReport {
+    let report = self.database.scan();
+    self.record_scan_changes(&report);
+    for record in &report.removed {
+      self.forget_artifacts(record);
+    }
+    let mut appeared_or_gone = report.added.clone();
+    appeared_or_gone.extend(report.removed.iter().map(|record| record.path.clone()));
+    self.mark_dependents_dirty(&appeared_or_gone, &[]);
+    report
+  }
+
+  // Imports every out-of-date asset, then everything depending on what was imported.
+  pub fn import_all(&mut self) -> ImportReport {
+    let mut report = self.pipeline.import_all(&self.database);
+    let touched: Vec<PathBuf> = report.imported.iter().chain(report.failed.iter().map(|(path, _)| path)).cloned().collect();
+    for path in &touched {
+      self.update_dependencies(path);
+    }
+    if self.mark_dependents_dirty(&touched, &touched) > 0 {
+      let dependents = self.pipeline.import_all(&self.database);
+      for path in dependents.imported.iter().chain(dependents.failed.iter().map(|(path, _)| path)) {
+        self.update_dependencies(path);
+      }
+      report.imported.extend(dependents.imported);
+      report.failed.extend(dependents.failed);
+      report.up_to_date = dependents.up_to_date;
+    }
+    for path in &report.imported {
+      self.record_change(ChangeKind::Imported, path);
+    }
+    report
+  }
+
+  // Everything that refers to the asset, directly or indirectly: what breaks if it changes.
+  // Folders with an importer of their own, such as atlases, count as depending on everything in them.
+  pub fn dependents_of(&self, path: &Path) -> Vec<PathBuf> {
+    let relative_path = self.database.relative(path);
+    let mut dependents = self.dependencies.all_dependents_of(&relative_path);
+    let folders = relative_path.ancestors().skip(1).filter(|folder| {
+      self.database.get(folder).is_some_and(|record| record.is_folder && self.pipeline.has_importer(&record.asset_type))
+    });
+    for folder in folders {
+      for dependent in std::iter::once(folder.to_path_buf()).chain(self.dependencies.all_dependents_of(folder)) {
+        if !dependents.contains(&dependent) {
+          dependents.push(dependent);
+        }
+      }
+    }
+    dependents
+  }
+
+  pub fn save(&self) -> io::Result<()> {
+    self.database.save(&self.library_path)?;
+    self.changes.save(&self.library_path)?;
+    self.pipeline.save()
+  }
+
+  // Moves or renames an asset (a folder with everything in it), carrying its meta file along so it keeps its GUID and
+  // rewriting the RON files that refer to it. Both paths are relative to the asset root, and the asset stays in the root
+  // it's in. Returns the edits made to references.
+  pub fn move_asset(&mut self, from: &Path, to: &Path) -> Result<Vec<ReferenceEdit>, ProjectError> {
+    let (from_path, to_path, rewrites) = self.plan_move(from, to)?;
+    apply_rewrites(&rewrites, || {
+      fs::rename(&from_path, &to_path).map_err(|source| ProjectError::AssetUnwritable { path: from_path.clone(), source })
+    })?;
+
+    // Applied right away rather than when the watcher catches up, so the move is visible as soon as this returns. The
+    // rewritten files are indexed before anything is imported, so whatever refers to both them and the moved asset is
+    // only imported once.
+    println!("Moved: {:?} -> {:?}", from_path, to_path);
+    let moved = self.apply_rename(&from_path, &to_path);
+    let mut changed = self.reindex_changed(&to_path);
+    for rewrite in &rewrites {
+      // Files that moved along with the asset can only be found once the move is known.
+      let path = match rewrite.path.strip_prefix(&from_path) {
+        Ok(suffix) => to_path.join(suffix),
+        Err(_) => rewrite.path.clone(),
+      };
+      self.reindex(&path);
+      let path = self.database.relative(&path);
+      if !changed.contains(&path) {
+        changed.push(path);
+      }
+    }
+    self.reimport_with_dependents(&changed, &moved);
+    Ok(rewrites.into_iter().flat_map(|rewrite| rewrite.edits).collect())
+  }
+
+  // The reference edits moving an asset would make, without changing anything.
+  pub fn preview_move(&self, from: &Path, to: &Path) -> Result<Vec<ReferenceEdit>, ProjectError> {
+    let (_, _, rewrites) = self.plan_move(from, to)?;
+    Ok(rewrites.into_iter().flat_map(|rewrite| rewrite.edits).collect())
+  }
+
+  // Checks that an asset can be moved, and works out the absolute paths involved and the files to rewrite.
+  fn plan_move(&self, from: &Path, to: &Path) -> Result<(PathBuf, PathBuf, Vec<FileRewrite>), ProjectError> {
+    let record = self.database.get(from).ok_or_else(|| ProjectError::AssetNotFound { path: from.to_path_buf() })?;
+    let from_path = record.root.join(&record.path);
+    let to_path = record.root.join(to);
+    let invalid = || ProjectError::InvalidAssetPath { path: to.to_path_buf() };
+    if !is_plain_relative(to) || is_meta_file(to) || to_path.starts_with(&from_path) {
+      return Err(invalid());
+    }
+    // Moving an asset somewhere it would be ignored would make it disappear from the project.
+    if self.database.is_ignored(&to_path, record.is_folder) {
+      return Err(invalid());
+    }
+    if to_path.exists() || self.database.get(to).is_some() {
+      return Err(ProjectError::AssetExists { path: to.to_path_buf() });
+    }
+    if !to_path.parent().is_some_and(|parent| parent.is_dir()) {
+      return Err(ProjectError::AssetNotFound { path: to.parent().unwrap_or(to).to_path_buf() });
+    }
+
+    // References to the files the asset generates move with it too.
+    let mut moves = vec![(record.path.clone(), to.to_path_buf())];
+    if let Some(importer) = self.pipeline.registry().for_asset(&from_path, &record.asset_type) {
+      for (_, extension) in importer.generated_files() {
+        moves.push((
+          generated_file_path(&record.path, record.is_folder, extension),
+          generated_file_path(to, record.is_folder, extension),
+        ));
+      }
+    }
+    let mut rewrites = Vec::new();
+    for file in self.dependencies.dependents_within(&record.path) {
+      let path = self.database.absolute(&file);
+      let text = fs::read_to_string(&path).map_err(|source| ProjectError::FileUnreadable { path: path.clone(), source })?;
+      rewrites.extend(FileRewrite::plan_all(&path, &file, text, &moves));
+    }
+    Ok((from_path, to_path, rewrites))
+  }
+
+  // Deletes an asset (a folder with everything in it) along with its meta file. The path is relative to the asset root.
+  pub fn delete_asset(&mut self, path: &Path) -> Result<(), ProjectError> {
+    let record = self.database.get(path).ok_or_else(|| ProjectError::AssetNotFound { path: path.to_path_buf() })?;
+    let asset_path = record.root.join(&record.path);
+    let removed = if record.is_folder { fs::remove_dir_all(&asset_path) } else { fs::remove_file(&asset_path) };
+    removed.map_err(|source| ProjectError::AssetUnwritable { path: asset_path.clone(), source })?;
+    self.handle_asset_event(AssetEvent::Deleted(asset_path));
+    Ok(())
+  }
+
+  // Keeps meta files, the asset database and imported artifacts in step with changes made to the Assets folder behind the
+  // server's back.
+  pub fn handle_asset_event(&mut self, event: AssetEvent) {
+    let event = match self.filter_ignored(event) {
+      Some(event) => event,
+      None => return,
+    };
+    match event {
+      AssetEvent::Created(path) | AssetEvent::Modified(path) | AssetEvent::Deleted(path) if is_ignore_file(&path) => {
+        self.handle_ignore_file_changed(&path)
+      }
+      AssetEvent::Renamed { from, to } if is_ignore_file(&from) || is_ignore_file(&to) => {
+        for path in [from, to].iter().filter(|path| is_ignore_file(path)) {
+          self.handle_ignore_file_changed(path);
+        }
+      }
+      AssetEvent::Created(path) if is_meta_file(&path) => self.handle_meta_saved(&path),
+      // A file written elsewhere and moved over an asset can arrive as a creation once the watcher folds the events.
+      AssetEvent::Created(path) if !path.is_dir() && self.database.get(&path).is_some() => {
+        println!("Modified: {:?}", path);
+        self.refresh(&path);
+      }
+      AssetEvent::Created(path) => {
+        println!("Created: {:?}", path);
+        self.refresh(&path);
+        self.reimport_dependents(&path);
+        if path.is_dir() {
+          // A folder that's been moved in arrives as a single event; its contents need indexing too.
+          self.rescan_folder(&path);
+        }
+      }
+      AssetEvent::Modified(path) if is_meta_file(&path) => self.handle_meta_saved(&path),
+      AssetEvent::Modified(path) => {
+        println!("Modified: {:?}", path);
+        self.refresh(&path);
+      }
+      // Editors often save by writing a temporary file and renaming it over the original.
+      AssetEvent::Renamed { to, .. } if is_meta_file(&to) => self.handle_meta_saved(&to),
+      AssetEvent::Renamed { from, to } => {
+        if is_meta_file(&from) { return; }
+        // Already applied (e.g. the asset was moved through the API), or overtaken by later changes.
+        if self.database.get(&from).is_none() && (self.database.get(&to).is_some() || !to.exists()) { return; }
+        println!("Renamed: {:?} -> {:?}", from, to);
+        let moved = self.apply_rename(&from, &to);
+        let changed = self.reindex_changed(&to);
+        self.reimport_with_dependents(&changed, &moved);
+      }
+      AssetEvent::Deleted(path) => {
+        if is_meta_file(&path) { return; }
+        let has_meta = get_meta_file(&path).is_ok_and(|meta_path| meta_path.exists());
+        if self.database.get(&path).is_none() && !has_meta { return; }
+        println!("Deleted: {:?}", path);
+        if let Err(err) = remove_meta(&path) {
+          println!("Error: {}", err);
+        }
+        for record in self.database.remove(&path) {
+          self.changes.record(ChangeKind::Removed, &record, None);
+          self.forget_artifacts(&record);
+          self.reimport_dependents(&record.path);
+        }
+      }
+      AssetEvent::Rescan => {
+        println!("Rescanning {:?}", self.asset_roots);
+        for err in &self.scan().errors {
+          println!("Error: {}", err);
+        }
+        for (path, err) in &self.import_all().failed {
+          println!("Error importing {:?}: {}", path, err);
+        }
+      }
+    }
+  }
+
+  // Drops events about ignored paths. Moving a file across the line between ignored and not ignored makes it appear or
+  // disappear as far as the project is concerned. Ignore files themselves are passed through.
+  fn filter_ignored(&self, event: AssetEvent) -> Option<AssetEvent> {
+    // A deleted path can't be checked for being a folder, so anything still indexed counts as not ignored.
+    let was_ignored = |path: &Path| self.database.get(path).is_none() && self.database.is_ignored(path, false);
+    match event {
+      AssetEvent::Created(path) | AssetEvent::Modified(path)
+        if !is_ignore_file(&path) && self.database.is_ignored(&path, path.is_dir()) =>
+      {
+        None
+      }
+      AssetEvent::Deleted(path) if !is_ignore_file(&path) && was_ignored(&path) => None,
+      AssetEvent::Renamed { from, to } if !is_ignore_file(&from) && !is_ignore_file(&to) => {
+        match (was_ignored(&from), self.database.is_ignored(&to, to.is_dir())) {
+          (true, true) => None,
+          // Saving through a temporary file replaces the asset rather than creating it.
+          (true, false) if self.database.get(&to).is_some() => Some(AssetEvent::Modified(to)),
+          (true, false) => Some(AssetEvent::Created(to)),
+          (false, true) => Some(AssetEvent::Deleted(from)),
+          (false, false) => Some(AssetEvent::Renamed { from, to }),
+        }
+      }
+      event => Some(event),
+    }
+  }
+
+  // A .theseusignore was added, edited or removed. Rescanning its folder reloads it, forgets assets that are now ignored
+  // and picks up ones that no longer are.
+  fn handle_ignore_file_changed(&mut self, ignore_path: &Path) {
+    if let Some(folder) = ignore_path.parent() {
+      println!("Ignore rules changed: {:?}", ignore_path);
+      self.rescan_folder(folder);
+    }
+  }
+
+  // Re-indexes everything in a folder, importing what's new or changed and dropping what's gone.
+  fn rescan_folder(&mut self, folder: &Path) {
+    let report = self.database.scan_folder(folder);
+    self.record_scan_changes(&report);
+    for err in &report.errors {
+      println!("Error: {}", err);
+    }
+    for record in &report.removed {
+      println!("Removed: {:?}", record.path);
+      self.forget_artifacts(record);
+      self.reimport_dependents(&record.path);
+    }
+    for path in report.added.iter().chain(&report.changed) {
+      self.import(path);
+      self.reimport_dependents(path);
+    }
+  }
+
+  // Editing a meta file changes its asset's import settings (or even its GUID).
+  fn handle_meta_saved(&mut self, meta_path: &Path) {
+    if let Some(path) = get_asset_for_meta(meta_path) {
+      if path.exists() {
+        println!("Meta modified: {:?}", path);
+        self.refresh(&path);
+      }
+    }
+  }
+
+  // Carries an asset's meta file, index entry and dependency edges over to where it's been moved, and records the move.
+  // Returns the old and new paths of everything that moved: whatever referred to the old paths is broken now, and
+  // whatever was missing the new paths isn't anymore.
+  fn apply_rename(&mut self, from: &Path, to: &Path) -> Vec<PathBuf> {
+    if let Err(err) = move_meta(from, to) {
+      println!("Error: {}", err);
+    }
+    self.database.rename(from, to);
+    let (from, to) = (self.database.relative(from), self.database.relative(to));
+    self.dependencies.rename(&from, &to);
+    let moved: Vec<AssetRecord> = self.database.iter().filter(|record| record.path.starts_with(&to)).cloned().collect();
+    let mut paths = Vec::new();
+    for record in moved {
+      let suffix = record.path.strip_prefix(&to).unwrap();
+      let old_path = if suffix.as_os_str().is_empty() { from.clone() } else { from.join(suffix) };
+      self.changes.record(ChangeKind::Moved, &record, Some(old_path.clone()));
+      paths.push(old_path);
+      paths.push(record.path);
+    }
+    paths
+  }
+
+  // Re-indexes a single asset and reimports it if needed.
+  fn refresh(&mut self, path: &Path) {
+    match self.reindex(path) {
+      Some(Refresh::Added) => self.import(path),
+      Some(Refresh::Changed) => {
+        self.import(path);
+        self.reimport_dependents(path);
+      }
+      _ => {}
+    }
+  }
+
+  // Re-indexes a single asset and records what changed, without importing anything.
+  fn reindex(&mut self, path: &Path) -> Option<Refresh> {
+    match self.database.refresh(path) {
+      Ok(refresh) => {
+        match refresh {
+          Refresh::Added => self.record_change(ChangeKind::Added, path),
+          Refresh::Changed => self.record_change(ChangeKind::Modified, path),
+          Refresh::Unchanged | Refresh::Ignored => {}
+        }
+        Some(refresh)
+      }
+      Err(err) => {
+        println!("Error: {}", err);
+        None
+      }
+    }
+  }
+
+  // Re-indexes an asset, returning its relative path if it needs importing. Picks up a new meta file after a move if
+  // there was nothing to carry along.
+  fn reindex_changed(&mut self, path: &Path) -> Vec<PathBuf> {
+    match self.reindex(path) {
+      Some(Refresh::Added) | Some(Refresh::Changed) => vec![self.database.relative(path)],
+      _ => Vec::new(),
+    }
+  }
+
+  // Imports everything that refers to `path` again, directly or indirectly.
+  fn reimport_dependents(&mut self, path: &Path) {
+    self.reimport_with_dependents(&[], &[self.database.relative(path)]);
+  }
+
+  // Imports the `changed` assets again, then everything referring to them or to the `moved` paths, directly or
+  // indirectly. Each asset is imported once, however many of the paths it depends on.
+  fn reimport_with_dependents(&mut self, changed: &[PathBuf], moved: &[PathBuf]) {
+    let mut assets = changed.to_vec();
+    for path in changed.iter().chain(moved) {
+      for dependent in self.dependents_of(path) {
+        if !assets.contains(&dependent) {
+          assets.push(dependent);
+        }
+      }
+    }
+    let dependents = assets.len() - changed.len();
+    if dependents > 0 {
+      let paths: Vec<&PathBuf> = changed.iter().chain(moved).collect();
+      println!("Reimporting {} dependent(s) of {:?}", dependents, paths);
+    }
+    for asset in &assets {
+      if let Some(guid) = self.database.guid_for_path(asset) {
+        self.pipeline.mark_dirty(&guid);
+      }
+    }
+    for asset in assets {
+      self.import(&asset);
+    }
+  }
+
+  // Marks everything depending on `paths` dirty, except the assets in `skip`. Returns how many assets were marked.
+  fn mark_dependents_dirty(&mut self, paths: &[PathBuf], skip: &[PathBuf]) -> usize {
+    let mut marked = 0;
+    for path in paths {
+      for dependent in self.dependents_of(path) {
+        if skip.contains(&dependent) { continue; }
+        if let Some(guid) = self.database.guid_for_path(&dependent) {
+          self.pipeline.mark_dirty(&guid);
+          marked += 1;
+        }
+      }
+    }
+    marked
+  }
+
+  // Refreshes the graph's edges for an asset from its latest import.
+  fn update_dependencies(&mut self, path: &Path) {
+    let relative_path = self.database.relative(path);
+    let dependencies = self
+      .database
+      .guid_for_path(&relative_path)
+      .and_then(|guid| self.pipeline.record(&guid))
+      .map(|record| record.dependencies.clone())
+      .unwrap_or_default();
+    self.dependencies.set_dependencies(&relative_path, dependencies);
+  }
+
+  fn import(&mut self, path: &Path) {
+    let asset = match self.database.get(path) {
+      Some(asset) => asset.clone(),
+      None => return,
+    };
+    match self.pipeline.import_if_needed(&self.database, &asset) {
+      Ok(true) => {
+        println!("Imported: {:?}", asset.path);
+        self.changes.record(ChangeKind::Imported, &asset, None);
+      }
+      Ok(false) => return,
+      Err(err) => println!("Error importing {:?}: {}", asset.path, err),
+    }
+    self.update_dependencies(&asset.path);
+  }
+
+  // Records a change to the indexed asset at `path`.
+  fn record_change(&mut self, kind: ChangeKind, path: &Path) {
+    if let Some(record) = self.database.get(path) {
+      self.changes.record(kind, record, None);
+    }
+  }
+
+  fn record_scan_changes(&mut self, report: &ScanReport) {
+    for path in &report.added {
+      self.record_change(ChangeKind::Added, path);
+    }
+    for path in &report.changed {
+      self.record_change(ChangeKind::Modified, path);
+    }
+    for record in &report.removed {
+      self.changes.record(ChangeKind::Removed, record, None);
+    }
+  }
+
+  fn forget_artifacts(&mut self, record: &AssetRecord) {
+    self.dependencies.remove(&record.path);
+    if let Err(err) = self.pipeline.forget(&record.guid) {
+      println!("Error removing artifacts of {:?}: {}", record.path, err);
+    }
+  }
+}
+
+// Whether the path is relative and made of plain names only: no "..", "." or root.
+fn is_plain_relative(path: &Path) -> bool {
+  path.components().next().is_some() && path.components().all(|component| matches!(component, Component::Normal(_)))
+}
@@ -0,0 +1,157 @@
+use std::fs;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::error::ProjectError;
+use crate::importer::find_file_references;
+
+// Reference Rewriting
+// -------------------
+//
+// Moving an asset breaks every `File("...")` in a RON file that names it, and nothing notices until the game fails to load.
+// So a move through the server first plans the edits to the files referring to the asset (or to anything inside a moved
+// folder), then applies them together with the move.
+//
+// All new file contents are written next to the originals before anything is replaced, so a failure while writing leaves
+// the project untouched. If replacing a file or moving the asset fails, the files already replaced are written back.
+//
+
+// Suffix of the files new contents are written to before they replace the originals. The default ignore rules skip *.tmp,
+// so the watcher doesn't see them.
+const REWRITE_SUFFIX: &str = ".rewrite.tmp";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReferenceEdit {
+  // The file with the reference, relative to its asset root.
+  pub file: PathBuf,
+  // 1-based line of the reference.
+  pub line: usize,
+  pub old_path: String,
+  pub new_path: String,
+  // Byte range of the path in the file, not including the quotes.
+  #[serde(skip)]
+  pub span: Range<usize>,
+}
+
+// New contents for one file referring to a moved asset.
+#[derive(Debug, Clone)]
+pub struct FileRewrite {
+  // Absolute path of the file.
+  pub path: PathBuf,
+  pub original: String,
+  pub edits: Vec<ReferenceEdit>,
+}
+
+impl FileRewrite {
+  // Finds the references in `text` that point at `from` or anything inside it, and what they become once it's at `to`.
+  // `file` is the file's path relative to its asset root, as reported in the edits.
+  pub fn plan(path: &Path, file: &Path, text: String, from: &Path, to: &Path) -> Option<FileRewrite> {
+    FileRewrite::plan_all(path, file, text, &[(from.to_path_buf(), to.to_path_buf())])
+  }
+
+  // Like `plan`, for several paths moving at once: an asset and the files it generates.
+  pub fn plan_all(path: &Path, file: &Path, text: String, moves: &[(PathBuf, PathBuf)]) -> Option<FileRewrite> {
+    let moves: Vec<(String, String)> =
+      moves.iter().filter_map(|(from, to)| Some((reference_string(from)?, reference_string(to)?))).collect();
+    let edits: Vec<ReferenceEdit> = find_file_references(&text)
+      .into_iter()
+      .filter_map(|reference| {
+        let (suffix, to) = moves.iter().find_map(|(from, to)| {
+          let suffix = reference.path.strip_prefix(from.as_str())?;
+          (suffix.is_empty() || suffix.starts_with('/')).then(|| (suffix.to_string(), to))
+        })?;
+        Some(ReferenceEdit {
+          file: file.to_path_buf(),
+          line: text[..reference.span.start].matches('\n').count() + 1,
+          new_path: format!("{}{}", to, suffix),
+          old_path: reference.path,
+          span: reference.span,
+        })
+      })
+      .collect();
+    if edits.is_empty() {
+      return None;
+    }
+    Some(FileRewrite { path: path.to_path_buf(), original: text, edits })
+  }
+
+  pub fn rewritten(&self) -> String {
+    let mut text = String::with_capacity(self.original.len());
+    let mut last = 0;
+    for edit in &self.edits {
+      text.push_str(&self.original[last..edit.span.start]);
+      text.push_str(&edit.new_path);
+      last = edit.span.end;
+    }
+    text.push_str(&self.original[last..]);
+    text
+  }
+}
+
+// Rewrites the files, then calls `and_then` (which moves the asset). Either everything happens or, as far as the file
+// system allows, nothing does.
+pub fn apply_rewrites<F>(rewrites: &[FileRewrite], and_then: F) -> Result<(), ProjectError>
+where
+  F: FnOnce() -> Result<(), ProjectError>,
+{
+  let temp_path = |path: &Path| {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(REWRITE_SUFFIX);
+    path.with_file_name(name)
+  };
+  let remove_temps = || {
+    for rewrite in rewrites {
+      let _ = fs::remove_file(temp_path(&rewrite.path));
+    }
+  };
+  let restore = |replaced: &[FileRewrite]| {
+    for rewrite in replaced {
+      if let Err(err) = fs::write(&rewrite.path, &rewrite.original) {
+        println!("Error restoring {:?} after a failed move: {}", rewrite.path, err);
+      }
+    }
+  };
+
+  for rewrite in rewrites {
+    // Don't overwrite edits made since the move was planned.
+    let current = fs::read_to_string(&rewrite.path)
+      .map_err(|source| ProjectError::FileUnreadable { path: rewrite.path.clone(), source });
+    let written = current.and_then(|current| {
+      if current != rewrite.original {
+        return Err(ProjectError::FileChanged { path: rewrite.path.clone() });
+      }
+      let path = temp_path(&rewrite.path);
+      fs::write(&path, rewrite.rewritten()).map_err(|source| ProjectError::AssetUnwritable { path, source })
+    });
+    if let Err(err) = written {
+      remove_temps();
+      return Err(err);
+    }
+  }
+
+  for (i, rewrite) in rewrites.iter().enumerate() {
+    if let Err(source) = fs::rename(temp_path(&rewrite.path), &rewrite.path) {
+      restore(&rewrites[..i]);
+      remove_temps();
+      return Err(ProjectError::AssetUnwritable { path: rewrite.path.clone(), source });
+    }
+  }
+
+  if let Err(err) = and_then() {
+    restore(rewrites);
+    return Err(err);
+  }
+  Ok(())
+}
+
+// The way RON files spell an asset path: relative to the asset root, with forward slashes.
+pub fn reference_string(path: &Path) -> Option<String> {
+  let names: Option<Vec<&str>> = path
+    .components()
+    .map(|component| match component {
+      Component::Normal(name) => name.to_str(),
+      _ => None,
+    })
+    .collect();
+  Some(names?.join("/"))
+}
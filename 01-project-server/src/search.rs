@@ -0,0 +1,165 @@
+use std::fmt;
+use crate::database::{AssetDatabase, AssetRecord};
+use crate::references::reference_string;
+
+// Asset Search
+// ------------
+//
+// Queries against the asset index. A query is a list of terms, all of which an asset has to match:
+//
+//   type:texture label:ui name:*menu*
+//
+//   type:<pattern>    The asset's importer ("texture", "folder", ...).
+//   label:<pattern>   One of the asset's labels.
+//   name:<pattern>    The file or folder name.
+//   path:<pattern>    The path relative to the asset root, with forward slashes.
+//   <key>:<pattern>   The metadata value under any other key, e.g. author:nick.
+//   <word>            Short for name:*<word>*.
+//
+// Patterns match the whole value, case-insensitively, with * standing for any run of characters and ? for any one
+// character. A term starting with - excludes what it matches, and values with spaces can be quoted: name:"Inner Folder".
+//
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+  terms: Vec<Term>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Term {
+  field: Field,
+  // Lowercase.
+  pattern: String,
+  negated: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Field {
+  Type,
+  Label,
+  Name,
+  Path,
+  Metadata(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryError {
+  pub message: String,
+}
+
+impl fmt::Display for QueryError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "invalid query: {}", self.message)
+  }
+}
+
+impl std::error::Error for QueryError {}
+
+impl Query {
+  pub fn parse(query: &str) -> Result<Query, QueryError> {
+    let mut terms = Vec::new();
+    for token in tokenize(query)? {
+      let (negated, token) = match token.strip_prefix('-') {
+        Some(rest) => (true, rest.to_string()),
+        None => (false, token),
+      };
+      let (field, pattern) = match token.split_once(':') {
+        Some((key, pattern)) => {
+          let field = match key {
+            "type" => Field::Type,
+            "label" => Field::Label,
+            "name" => Field::Name,
+            "path" => Field::Path,
+            "" => return Err(QueryError { message: format!("missing field name before ':{}'", pattern) }),
+            key => Field::Metadata(String::from(key)),
+          };
+          (field, String::from(pattern))
+        }
+        None => (Field::Name, format!("*{}*", token)),
+      };
+      if pattern.is_empty() {
+        return Err(QueryError { message: format!("missing pattern after '{}'", token) });
+      }
+      terms.push(Term { field, pattern: pattern.to_lowercase(), negated });
+    }
+    Ok(Query { terms })
+  }
+
+  pub fn matches(&self, asset: &AssetRecord) -> bool {
+    self.terms.iter().all(|term| term.matches(asset) != term.negated)
+  }
+}
+
+impl Term {
+  fn matches(&self, asset: &AssetRecord) -> bool {
+    let matches = |value: &str| wildcard_match(&self.pattern, &value.to_lowercase());
+    match &self.field {
+      Field::Type => matches(&asset.asset_type),
+      Field::Label => asset.labels.iter().any(|label| matches(label)),
+      Field::Name => asset.path.file_name().and_then(|name| name.to_str()).is_some_and(matches),
+      Field::Path => reference_string(&asset.path).is_some_and(|path| matches(&path)),
+      Field::Metadata(key) => asset.metadata.get(key).is_some_and(|value| matches(value)),
+    }
+  }
+}
+
+// The assets matching the query, by path.
+pub fn search<'a>(database: &'a AssetDatabase, query: &Query) -> Vec<&'a AssetRecord> {
+  let mut found: Vec<&AssetRecord> = database.iter().filter(|asset| query.matches(asset)).collect();
+  found.sort_by(|a, b| a.path.cmp(&b.path));
+  found
+}
+
+// Splits the query at spaces outside of double quotes, dropping the quotes.
+fn tokenize(query: &str) -> Result<Vec<String>, QueryError> {
+  let mut tokens = Vec::new();
+  let mut token = String::new();
+  let mut in_quotes = false;
+  for c in query.chars() {
+    match c {
+      '"' => in_quotes = !in_quotes,
+      c if c.is_whitespace() && !in_quotes => {
+        if !token.is_empty() {
+          tokens.push(std::mem::take(&mut token));
+        }
+      }
+      c => token.push(c),
+    }
+  }
+  if in_quotes {
+    return Err(QueryError { message: String::from("unterminated quote") });
+  }
+  if !token.is_empty() {
+    tokens.push(token);
+  }
+  Ok(tokens)
+}
+
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+  let pattern: Vec<char> = pattern.chars().collect();
+  let value: Vec<char> = value.chars().collect();
+  // Where to resume after the last *, trying it against one more character each time.
+  let (mut p, mut v) = (0, 0);
+  let mut star: Option<(usize, usize)> = None;
+  while v < value.len() {
+    match pattern.get(p) {
+      Some('*') => {
+        star = Some((p, v));
+        p += 1;
+      }
+      Some(&c) if c == '?' || c == value[v] => {
+        p += 1;
+        v += 1;
+      }
+      _ => match star {
+        Some((star_p, star_v)) => {
+          p = star_p + 1;
+          v = star_v + 1;
+          star = Some((star_p, star_v + 1));
+        }
+        None => return false,
+      },
+    }
+  }
+  pattern[p..].iter().all(|&c| c == '*')
+}
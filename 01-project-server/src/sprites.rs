@@ -0,0 +1,297 @@
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::image::Image;
+use crate::importer::{find_file_references, find_values};
+
+// Sprite Sheets
+// -------------
+//
+// Reads the sprite sheet definitions Amethyst loads, to find where each sprite sits on its texture. They come two ways:
+//
+// A standalone sprite sheet file, for the texture with the same name next to it:
+//
+//   List((texture_width: 8, texture_height: 16, sprites: [(x: 0, y: 0, width: 4, height: 16), ...]))
+//
+// Or a Sheet inside a prefab, naming its texture and listing sprites in any number of grids and lists, numbered on from
+// one to the next:
+//
+//   sheet: Sheet(
+//     texture: File("textures/spritesheet.png", ...),
+//     sprites: [Grid((texture_width: 384, texture_height: 120, columns: 6, rows: 3, cell_size: (64, 32))), List(...)],
+//   )
+//
+// Only the layout is read; offsets, flips and names are left to Amethyst.
+//
+// Sheets can also be generated from a texture's meta file, laid out as a grid or found in the image from the
+// transparent gutters between sprites (see the texture importer). A generated sheet is an artifact of the texture in the
+// shape of a standalone sprite sheet file, with the sprites' names as comments, made again whenever the texture or its
+// settings change:
+//
+//   // Generated from pong_spritesheet.png and its meta file, which are what to edit. Changes here are overwritten.
+//   List((
+//       texture_width: 8,
+//       texture_height: 16,
+//       sprites: [
+//           (x: 0, y: 0, width: 4, height: 16), // 0: paddle
+//           (x: 4, y: 0, width: 4, height: 4), // 1: ball
+//       ],
+//   ))
+//
+
+// The first line of every generated sprite sheet, so they're never mistaken for hand-written ones.
+pub const GENERATED_HEADER: &str = "// Generated from ";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpriteSheet {
+  // The texture as the file refers to it, or None for a standalone sprite sheet file.
+  pub texture: Option<String>,
+  // The texture size the first sprite list was written for.
+  pub texture_width: u32,
+  pub texture_height: u32,
+  pub sprites: Vec<Sprite>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Sprite {
+  pub x: u32,
+  pub y: u32,
+  pub width: u32,
+  pub height: u32,
+}
+
+// The way Amethyst's SpriteList lays out sprites.
+#[derive(Deserialize)]
+enum SpriteList {
+  Grid(SpriteGrid),
+  List(SpriteListPositions),
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SpriteGrid {
+  pub texture_width: u32,
+  pub texture_height: u32,
+  pub columns: u32,
+  #[serde(default)]
+  pub rows: Option<u32>,
+  #[serde(default)]
+  pub sprite_count: Option<u32>,
+  #[serde(default)]
+  pub cell_size: Option<(u32, u32)>,
+  #[serde(default)]
+  pub position: Option<(u32, u32)>,
+}
+
+#[derive(Deserialize)]
+struct SpriteListPositions {
+  texture_width: u32,
+  texture_height: u32,
+  sprites: Vec<SpritePosition>,
+}
+
+#[derive(Deserialize)]
+struct SpritePosition {
+  x: u32,
+  y: u32,
+  width: u32,
+  height: u32,
+}
+
+impl SpriteGrid {
+  // Fills in what was left out the way Amethyst does: rows from the sprite count or cell height, the cell size from
+  // splitting the texture evenly.
+  pub fn sprites(&self) -> Vec<Sprite> {
+    let columns = self.columns.max(1);
+    let (left, top) = self.position.unwrap_or((0, 0));
+    let rows = match (self.rows, self.sprite_count, self.cell_size) {
+      (Some(rows), _, _) => rows,
+      (None, Some(count), _) => count.div_ceil(columns),
+      (None, None, Some((_, cell_height))) => self.texture_height.saturating_sub(top) / cell_height.max(1),
+      (None, None, None) => 1,
+    }
+    .max(1);
+    let (width, height) = self.cell_size.unwrap_or((
+      self.texture_width.saturating_sub(left) / columns,
+      self.texture_height.saturating_sub(top) / rows,
+    ));
+    let count = self.sprite_count.unwrap_or(columns * rows);
+    (0..count).map(|i| Sprite { x: left + i % columns * width, y: top + i / columns * height, width, height }).collect()
+  }
+}
+
+impl SpriteList {
+  fn texture_size(&self) -> (u32, u32) {
+    match self {
+      SpriteList::Grid(grid) => (grid.texture_width, grid.texture_height),
+      SpriteList::List(list) => (list.texture_width, list.texture_height),
+    }
+  }
+
+  fn sprites(&self) -> Vec<Sprite> {
+    match self {
+      SpriteList::Grid(grid) => grid.sprites(),
+      SpriteList::List(list) => list
+        .sprites
+        .iter()
+        .map(|sprite| Sprite { x: sprite.x, y: sprite.y, width: sprite.width, height: sprite.height })
+        .collect(),
+    }
+  }
+}
+
+// The sprite sheets defined in a RON file, in order: every Sheet, or the file's own sprite list if it has no Sheet. A
+// file with neither has none. An error means something looked like a sprite sheet but couldn't be read.
+pub fn find_sprite_sheets(text: &str) -> Result<Vec<SpriteSheet>, String> {
+  // Sprite lists are parsed on their own, so they need the file's extensions, such as implicit_some in prefabs.
+  let extensions: Vec<&str> = text.lines().take_while(|line| line.trim_start().starts_with("#!")).collect();
+  let extensions = extensions.join("\n");
+  let parse_list = |span: &str| {
+    let source = format!("{}\n{}", extensions, span);
+    ron::de::from_str::<SpriteList>(&source).map_err(|err| format!("{}: {}", excerpt(span), err))
+  };
+  let sheet = |texture: Option<String>, lists: Vec<SpriteList>| -> Result<SpriteSheet, String> {
+    let first = lists.first().ok_or_else(|| String::from("sprite sheet without sprites"))?;
+    let (texture_width, texture_height) = first.texture_size();
+    let sprites = lists.iter().flat_map(SpriteList::sprites).collect();
+    Ok(SpriteSheet { texture, texture_width, texture_height, sprites })
+  };
+
+  let sheets = find_values(text, &["Sheet"]);
+  let lists = find_values(text, &["Grid", "List"]);
+  if sheets.is_empty() {
+    // A standalone sprite sheet is a single sprite list making up the whole file.
+    let is_standalone = |start: usize| {
+      let is_preamble = |line: &str| line.is_empty() || line.starts_with("#!") || line.starts_with("//");
+      text[..start].lines().map(str::trim).all(is_preamble)
+    };
+    return match lists.first() {
+      Some(span) if is_standalone(span.start) => Ok(vec![sheet(None, vec![parse_list(&text[span.clone()])?])?]),
+      _ => Ok(Vec::new()),
+    };
+  }
+  sheets
+    .iter()
+    .map(|span| {
+      let body = &text[span.clone()];
+      let texture = find_file_references(body).into_iter().next().map(|reference| reference.path);
+      let lists = lists
+        .iter()
+        .filter(|list| list.start >= span.start && list.end <= span.end)
+        .map(|list| parse_list(&text[list.clone()]))
+        .collect::<Result<Vec<SpriteList>, String>>()?;
+      sheet(texture, lists)
+    })
+    .collect()
+}
+
+// The texture a standalone sprite sheet file is for: the PNG with the same name next to it.
+pub fn standalone_texture(sheet_path: &Path) -> PathBuf {
+  sheet_path.with_extension("png")
+}
+
+// Generating Sheets
+// -----------------
+//
+
+// Finds the sprites in an image with transparent gutters between them: the image is cut along every row of pixels no
+// more opaque than `alpha_threshold`, each band along such columns, and so on until nothing divides further, and each
+// piece is trimmed to its opaque pixels. Sprites are numbered top to bottom, then left to right.
+pub fn detect_sprites(image: &Image, alpha_threshold: u8) -> Vec<Sprite> {
+  let mut sprites = Vec::new();
+  let whole = Sprite { x: 0, y: 0, width: image.width, height: image.height };
+  split(image, alpha_threshold, whole, true, false, &mut sprites);
+  sprites
+}
+
+// Cuts `area` along its empty rows (or columns), then cuts each piece the other way. An area neither way cuts is a
+// sprite.
+fn split(image: &Image, alpha_threshold: u8, area: Sprite, rows: bool, cut_other_way: bool, sprites: &mut Vec<Sprite>) {
+  let is_empty = |line: u32| {
+    if rows {
+      (area.x..area.x + area.width).all(|x| image.get(x, line)[3] <= alpha_threshold)
+    } else {
+      (area.y..area.y + area.height).all(|y| image.get(line, y)[3] <= alpha_threshold)
+    }
+  };
+  let (start, len) = if rows { (area.y, area.height) } else { (area.x, area.width) };
+  let mut pieces = Vec::new();
+  let mut piece_start = None;
+  for line in start..start + len {
+    match (is_empty(line), piece_start) {
+      (false, None) => piece_start = Some(line),
+      (true, Some(from)) => {
+        pieces.push(from..line);
+        piece_start = None;
+      }
+      _ => {}
+    }
+  }
+  if let Some(from) = piece_start {
+    pieces.push(from..start + len);
+  }
+  let piece_area = |piece: &std::ops::Range<u32>| {
+    if rows {
+      Sprite { x: area.x, y: piece.start, width: area.width, height: piece.end - piece.start }
+    } else {
+      Sprite { x: piece.start, y: area.y, width: piece.end - piece.start, height: area.height }
+    }
+  };
+  match pieces.as_slice() {
+    [] => {}
+    // Not cut this way; once neither way cuts it, it's trimmed and done.
+    [piece] if cut_other_way => sprites.push(piece_area(piece)),
+    [piece] => split(image, alpha_threshold, piece_area(piece), !rows, true, sprites),
+    pieces => {
+      for piece in pieces {
+        split(image, alpha_threshold, piece_area(piece), !rows, false, sprites);
+      }
+    }
+  }
+}
+
+// A generated standalone sprite sheet listing every sprite.
+pub fn write_sprite_list(source_name: &str, width: u32, height: u32, sprites: &[Sprite], names: &[&str]) -> String {
+  let mut text = generated_header(source_name);
+  text += &format!("List((\n    texture_width: {},\n    texture_height: {},\n    sprites: [\n", width, height);
+  for (index, sprite) in sprites.iter().enumerate() {
+    text += &format!("        (x: {}, y: {}, width: {}, height: {}),", sprite.x, sprite.y, sprite.width, sprite.height);
+    match names.get(index) {
+      Some(name) => text += &format!(" // {}: {}\n", index, name),
+      None => text += &format!(" // {}\n", index),
+    }
+  }
+  text + "    ],\n))\n"
+}
+
+// A generated standalone sprite sheet laying the sprites out as a grid.
+pub fn write_sprite_grid(source_name: &str, grid: &SpriteGrid, names: &[&str]) -> String {
+  let mut text = generated_header(source_name);
+  for (index, name) in names.iter().enumerate() {
+    text += &format!("// {}: {}\n", index, name);
+  }
+  text += &format!("Grid((\n    texture_width: {},\n", grid.texture_width);
+  text += &format!("    texture_height: {},\n    columns: {},\n", grid.texture_height, grid.columns);
+  if let Some(rows) = grid.rows {
+    text += &format!("    rows: Some({}),\n", rows);
+  }
+  if let Some(count) = grid.sprite_count {
+    text += &format!("    sprite_count: Some({}),\n", count);
+  }
+  if let Some((width, height)) = grid.cell_size {
+    text += &format!("    cell_size: Some(({}, {})),\n", width, height);
+  }
+  if let Some((x, y)) = grid.position {
+    text += &format!("    position: Some(({}, {})),\n", x, y);
+  }
+  text + "))\n"
+}
+
+fn generated_header(source_name: &str) -> String {
+  let note = "and its meta file, which are what to edit. Changes here are overwritten.";
+  format!("{}{} {}\n", GENERATED_HEADER, source_name, note)
+}
+
+// The start of a RON value, for error messages.
+fn excerpt(span: &str) -> String {
+  let line = span.lines().next().unwrap_or("");
+  if line.len() < span.len() { format!("{}...", line.trim_end()) } else { String::from(line) }
+}
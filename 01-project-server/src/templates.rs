@@ -0,0 +1,182 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use crate::database::LIBRARY_FOLDER;
+use crate::error::ProjectError;
+use crate::lfs;
+use crate::manifest::{default_name, Manifest, DEFAULT_ASSETS_FOLDER};
+
+// Project Templates
+// -----------------
+//
+// New projects start from one of the sample games in this repository. A template's files are built into the server, so
+// creating a project doesn't depend on where the repository is checked out. Assets go into the new project's Assets
+// folder and config files into config; the meta files are written by the first scan, like for any other new asset.
+//
+// Anything in a config file quoting the sample's window title is renamed to the new project's name.
+//
+// A server built from a checkout without Git LFS has pointer files built in where the samples' textures and fonts should
+// be. Projects aren't created from such a template: they'd fail to import and to run.
+//
+
+const CONFIG_FOLDER: &str = "config";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Template {
+  // A window and a sprite, like 06-amethyst-starter-2d.
+  Empty2d,
+  Pong,
+  Arkanoid,
+}
+
+struct TemplateFile {
+  // Relative to the Assets or config folder.
+  path: &'static str,
+  contents: &'static [u8],
+}
+
+macro_rules! template_files {
+  ($sample:literal, [$($path:literal),* $(,)?]) => {
+    &[$(TemplateFile { path: $path, contents: include_bytes!(concat!("../../", $sample, "/", $path)) }),*]
+  };
+}
+
+impl Template {
+  pub const ALL: [Template; 3] = [Template::Empty2d, Template::Pong, Template::Arkanoid];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Template::Empty2d => "empty-2d",
+      Template::Pong => "pong",
+      Template::Arkanoid => "arkanoid",
+    }
+  }
+
+  fn assets(self) -> &'static [TemplateFile] {
+    match self {
+      Template::Empty2d => template_files!("06-amethyst-starter-2d/assets", [
+        "fonts/Bangers-Regular.ttf",
+        "fonts/OFL.txt",
+        "sprites/logo.png",
+        "sprites/logo.ron",
+      ]),
+      Template::Pong => template_files!("07-pong/assets", ["texture/pong_spritesheet.png", "texture/pong_spritesheet.ron"]),
+      Template::Arkanoid => template_files!("08-arkanoid-rust-test-input-lag/assets", [
+        "fonts/hack.ttf",
+        "fonts/joystix.ttf",
+        "prefabs/background.ron",
+        "prefabs/camera.ron",
+        "prefabs/level.ron",
+        "textures/background.png",
+        "textures/spritesheet.png",
+        "ui/game_over_menu.ron",
+        "ui/level_complete_menu.ron",
+        "ui/life.ron",
+        "ui/main_menu.ron",
+        "ui/pause_menu.ron",
+        "ui/score.ron",
+      ]),
+    }
+  }
+
+  fn config(self) -> &'static [TemplateFile] {
+    match self {
+      Template::Empty2d => template_files!("06-amethyst-starter-2d/config", ["display_config.ron", "input.ron"]),
+      Template::Pong => template_files!("07-pong/config", ["bindings.ron"]),
+      Template::Arkanoid => template_files!("08-arkanoid-rust-test-input-lag/config", [
+        "bindings.ron",
+        "display.ron",
+        "frame_limiter.ron",
+        "logger.ron",
+      ]),
+    }
+  }
+
+  // The files built in as Git LFS pointers rather than the files they stand for.
+  fn lfs_pointers(self) -> Vec<&'static str> {
+    let files = self.assets().iter().chain(self.config());
+    files.filter(|file| lfs::is_pointer(file.contents)).map(|file| file.path).collect()
+  }
+
+  // The window title in the sample's display config. 07-pong has no display config: it sets its title ("Pong!") in
+  // code, so Pong projects keep that title until it's changed in their main.rs.
+  fn title(self) -> Option<&'static str> {
+    match self {
+      Template::Empty2d => Some("Amethyst Starter 2D template"),
+      Template::Pong => None,
+      Template::Arkanoid => Some("Arkanoid"),
+    }
+  }
+}
+
+impl fmt::Display for Template {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.name())
+  }
+}
+
+impl FromStr for Template {
+  type Err = String;
+
+  fn from_str(name: &str) -> Result<Template, String> {
+    Template::ALL.iter().copied().find(|template| template.name() == name).ok_or_else(|| {
+      let names: Vec<&str> = Template::ALL.iter().map(|template| template.name()).collect();
+      format!("unknown template {:?} (expected one of: {})", name, names.join(", "))
+    })
+  }
+}
+
+// Creates a project at `project_path` from `template`. The folder may exist, but only if it's empty. Without a name, the
+// project is named after its folder.
+pub fn create_project(project_path: &Path, name: Option<&str>, template: Template) -> Result<Manifest, ProjectError> {
+  let unwritable = |path: &Path| {
+    let path = path.to_path_buf();
+    move |source| ProjectError::FileUnwritable { path, source }
+  };
+  let pointers = template.lfs_pointers();
+  if !pointers.is_empty() {
+    return Err(ProjectError::TemplateIncomplete {
+      path: project_path.to_path_buf(),
+      template: String::from(template.name()),
+      pointers: pointers.into_iter().map(String::from).collect(),
+    });
+  }
+  if project_path.exists() {
+    let unreadable = |source| ProjectError::DirectoryUnreadable { path: project_path.to_path_buf(), source };
+    let mut entries = fs::read_dir(project_path).map_err(unreadable)?;
+    if entries.next().is_some() {
+      return Err(ProjectError::ProjectExists { path: project_path.to_path_buf() });
+    }
+  }
+  fs::create_dir_all(project_path).map_err(unwritable(project_path))?;
+  let name = name.map_or_else(|| default_name(project_path), String::from);
+
+  let write_files = |folder: &Path, files: &[TemplateFile]| -> Result<(), ProjectError> {
+    fs::create_dir_all(folder).map_err(unwritable(folder))?;
+    for file in files {
+      let path = folder.join(file.path);
+      if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(unwritable(parent))?;
+      }
+      let contents = match (template.title(), std::str::from_utf8(file.contents)) {
+        (Some(title), Ok(text)) if folder.ends_with(CONFIG_FOLDER) => {
+          text.replace(&format!("{:?}", title), &format!("{:?}", name)).into_bytes()
+        }
+        _ => file.contents.to_vec(),
+      };
+      fs::write(&path, contents).map_err(unwritable(&path))?;
+    }
+    Ok(())
+  };
+  write_files(&project_path.join(DEFAULT_ASSETS_FOLDER), template.assets())?;
+  write_files(&project_path.join(CONFIG_FOLDER), template.config())?;
+
+  // The Library folder is rebuilt from the assets, so it doesn't belong in version control.
+  let gitignore = project_path.join(".gitignore");
+  fs::write(&gitignore, format!("/{}/\n", LIBRARY_FOLDER)).map_err(unwritable(&gitignore))?;
+
+  let manifest = Manifest::new(&name);
+  manifest.save(project_path)?;
+  Ok(manifest)
+}
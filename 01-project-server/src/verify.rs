@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use uuid::Uuid;
+use crate::error::ProjectError;
+use crate::ignore_rules::IgnoreRules;
+use crate::importer::find_file_references;
+use crate::lfs::{self, LFS_FIX};
+use crate::meta::{get_asset_for_meta, get_meta_file, is_meta_file, load_or_create_meta, ImporterSettings, Meta};
+use crate::project::Project;
+
+// Meta Verification
+// -----------------
+//
+// Checks that the meta files on disk agree with the assets without changing anything, so it can run in CI on a fresh
+// checkout: every asset has a readable meta file, every meta file has an asset, and no two assets share a GUID. The
+// server itself would quietly fix the first two by writing and deleting meta files, which is exactly what a commit that
+// forgot to add a meta file shouldn't rely on.
+//
+// It also catches a checkout that's missing its binary files, which otherwise only shows up when the game fails to load:
+// files that are still Git LFS pointers, and RON files referring to files that aren't there.
+//
+// Most problems come with a fix the doctor command can apply. Of two assets sharing a GUID (usually a copy-pasted folder),
+// the one the asset database knows by that GUID keeps it, or else the first in path order; the other gets a new one.
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProblemKind {
+  MissingMeta,
+  InvalidMeta,
+  // A meta file whose asset is gone.
+  OrphanedMeta,
+  DuplicateGuid,
+  InvalidIgnoreFile,
+  Unreadable,
+  // A Git LFS pointer checked out in place of the file.
+  LfsPointer,
+  // A file a RON file refers to that doesn't exist.
+  MissingFile,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fix {
+  // Write a meta file with a new GUID and default import settings.
+  CreateMeta,
+  DeleteMeta,
+  // Give the asset a new GUID, keeping its import settings.
+  NewGuid,
+  // Replace the meta file with default import settings, keeping the GUID the asset database knows, if any.
+  ResetMeta,
+}
+
+impl fmt::Display for Fix {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Fix::CreateMeta => write!(f, "create a meta file"),
+      Fix::DeleteMeta => write!(f, "delete the meta file"),
+      Fix::NewGuid => write!(f, "give it a new GUID"),
+      Fix::ResetMeta => write!(f, "reset the meta file to default settings"),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Problem {
+  pub kind: ProblemKind,
+  // Relative to the asset root.
+  pub path: PathBuf,
+  pub message: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub fix: Option<Fix>,
+  // Absolute version of `path`.
+  #[serde(skip)]
+  pub file: PathBuf,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct VerifyReport {
+  // How many assets were checked.
+  pub assets: usize,
+  pub problems: Vec<Problem>,
+}
+
+impl VerifyReport {
+  pub fn is_ok(&self) -> bool {
+    self.problems.is_empty()
+  }
+}
+
+pub fn verify(project: &Project) -> VerifyReport {
+  // The project's own rules only know the ignore files a scan has walked past, so they're loaded again along the way.
+  let (ignore, errors) = IgnoreRules::load(&project.path);
+  let mut verifier =
+    Verifier { project, ignore, root: PathBuf::new(), guids: HashMap::new(), report: VerifyReport::default() };
+  for err in errors {
+    verifier.problem(ProblemKind::InvalidIgnoreFile, err.path(), err.to_string(), None);
+  }
+  for root in &project.asset_roots {
+    verifier.root = root.clone();
+    verifier.check_folder(root);
+  }
+  verifier.report
+}
+
+// Applies the problem's fix, if it has one.
+pub fn apply_fix(project: &Project, problem: &Problem) -> Result<(), ProjectError> {
+  let fix = match problem.fix {
+    Some(fix) => fix,
+    None => return Ok(()),
+  };
+  match fix {
+    Fix::CreateMeta => load_or_create_meta(&problem.file).map(|_| ()),
+    Fix::DeleteMeta => fs::remove_file(&problem.file)
+      .map_err(|source| ProjectError::MetaUnwritable { path: problem.file.clone(), source }),
+    Fix::NewGuid => {
+      let meta_path = get_meta_file(&problem.file)?;
+      let mut meta = Meta::read(&meta_path)?;
+      meta.guid = Uuid::new_v4();
+      meta.write(&meta_path)
+    }
+    Fix::ResetMeta => {
+      let asset_path =
+        get_asset_for_meta(&problem.file).ok_or_else(|| ProjectError::InvalidAssetPath { path: problem.file.clone() })?;
+      let mut meta = Meta::new(ImporterSettings::default_for(&asset_path));
+      if let Some(guid) = project.database.guid_for_path(&project.database.relative(&asset_path)) {
+        meta.guid = guid;
+      }
+      meta.write(&problem.file)
+    }
+  }
+}
+
+struct Verifier<'a> {
+  project: &'a Project,
+  ignore: IgnoreRules,
+  // The asset root being checked, which file references are relative to.
+  root: PathBuf,
+  // The asset (absolute path) holding each GUID seen so far.
+  guids: HashMap<Uuid, PathBuf>,
+  report: VerifyReport,
+}
+
+impl Verifier<'_> {
+  fn check_folder(&mut self, folder: &Path) {
+    if let Some(err) = self.ignore.load_folder(folder) {
+      self.problem(ProblemKind::InvalidIgnoreFile, err.path(), err.to_string(), None);
+    }
+    let mut entries: Vec<PathBuf> = match fs::read_dir(folder) {
+      Ok(entries) => entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect(),
+      Err(err) => return self.problem(ProblemKind::Unreadable, folder, err.to_string(), None),
+    };
+    entries.sort();
+    for path in entries {
+      let is_dir = path.is_dir();
+      if self.ignore.is_ignored(&path, is_dir) {
+        continue;
+      }
+      if is_meta_file(&path) && !is_dir {
+        if get_asset_for_meta(&path).is_none_or(|asset| !asset.exists()) {
+          let message = String::from("meta file without an asset");
+          self.problem(ProblemKind::OrphanedMeta, &path, message, Some(Fix::DeleteMeta));
+        }
+        continue;
+      }
+      self.check_asset(&path);
+      if is_dir {
+        self.check_folder(&path);
+      } else {
+        self.check_contents(&path);
+      }
+    }
+  }
+
+  fn check_asset(&mut self, path: &Path) {
+    self.report.assets += 1;
+    let meta_path = match get_meta_file(path) {
+      Ok(meta_path) => meta_path,
+      Err(err) => return self.problem(ProblemKind::InvalidMeta, path, err.to_string(), None),
+    };
+    if !meta_path.exists() {
+      let message = String::from("asset without a meta file");
+      return self.problem(ProblemKind::MissingMeta, path, message, Some(Fix::CreateMeta));
+    }
+    let meta = match Meta::read(&meta_path) {
+      Ok(meta) => meta,
+      // Resetting a meta file from a newer server would lose settings this one doesn't know about.
+      Err(err @ ProjectError::MetaVersionUnsupported { .. }) => {
+        return self.problem(ProblemKind::InvalidMeta, &meta_path, err.to_string(), None)
+      }
+      Err(err) => return self.problem(ProblemKind::InvalidMeta, &meta_path, err.to_string(), Some(Fix::ResetMeta)),
+    };
+    let other = match self.guids.get(&meta.guid) {
+      Some(other) => other.clone(),
+      None => {
+        self.guids.insert(meta.guid, path.to_path_buf());
+        return;
+      }
+    };
+    let database = &self.project.database;
+    let (keeps, changes) = if database.path_for_guid(&meta.guid) == Some(database.relative(path).as_path()) {
+      self.guids.insert(meta.guid, path.to_path_buf());
+      (path.to_path_buf(), other)
+    } else {
+      (other, path.to_path_buf())
+    };
+    let message = format!("same GUID as {:?} ({})", database.relative(&keeps), meta.guid);
+    self.problem(ProblemKind::DuplicateGuid, &changes, message, Some(Fix::NewGuid));
+  }
+
+  fn check_contents(&mut self, path: &Path) {
+    if lfs::is_pointer_file(path) {
+      let size = fs::read(path).ok().and_then(|bytes| lfs::pointer_size(&bytes));
+      let size = size.map(|size| format!(" {} byte", size)).unwrap_or_default();
+      let message = format!("a Git LFS pointer in place of the{} file: {}", size, LFS_FIX);
+      return self.problem(ProblemKind::LfsPointer, path, message, None);
+    }
+    if !path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("ron")) {
+      return;
+    }
+    let text = match fs::read_to_string(path) {
+      Ok(text) => text,
+      Err(err) => return self.problem(ProblemKind::Unreadable, path, err.to_string(), None),
+    };
+    for reference in find_file_references(&text) {
+      let registry = self.project.pipeline.registry();
+      if registry.referenced_asset(&self.root, Path::new(&reference.path)).is_none() {
+        let line = text[..reference.span.start].matches('\n').count() + 1;
+        let message = format!(
+          "line {} refers to {:?}, which doesn't exist; if it's kept in Git LFS, {}",
+          line, reference.path, LFS_FIX
+        );
+        self.problem(ProblemKind::MissingFile, path, message, None);
+      }
+    }
+  }
+
+  fn problem(&mut self, kind: ProblemKind, path: &Path, message: String, fix: Option<Fix>) {
+    let relative = self.project.database.relative(path);
+    self.report.problems.push(Problem { kind, path: relative, message, fix, file: path.to_path_buf() });
+  }
+}
@@ -0,0 +1,68 @@
+use std::path::PathBuf;
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+
+// Asset Watcher
+// -------------
+//
+// Watches the asset roots and reports changes to them once they've settled. notify's debouncer already folds the burst of
+// raw events an editor produces on save into a single write, and pairs up the two halves of a rename.
+//
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssetEvent {
+  Created(PathBuf),
+  Modified(PathBuf),
+  Renamed { from: PathBuf, to: PathBuf },
+  Deleted(PathBuf),
+  // The watcher lost track of what happened (e.g. its event queue overflowed); the whole folder should be walked again.
+  Rescan,
+}
+
+pub struct AssetWatcher {
+  // Dropping the notify watcher stops the watch, so hold onto it for as long as events are wanted.
+  _watcher: RecommendedWatcher,
+}
+
+impl AssetWatcher {
+  // Starts watching the asset roots recursively. `on_event` is called from the watcher's own thread.
+  pub fn start<F>(roots: &[PathBuf], debounce: Duration, on_event: F) -> notify::Result<AssetWatcher>
+  where
+    F: Fn(AssetEvent) + Send + 'static,
+  {
+    let (tx, rx) = channel();
+    let mut watcher = notify::watcher(tx, debounce)?;
+    for root in roots {
+      watcher.watch(root, RecursiveMode::Recursive)?;
+    }
+
+    // Translate notify's events into asset events. The loop ends when the notify watcher is dropped.
+    thread::spawn(move || {
+      for event in rx {
+        if let Some(event) = translate(event) {
+          on_event(event);
+        }
+      }
+    });
+
+    Ok(AssetWatcher { _watcher: watcher })
+  }
+}
+
+fn translate(event: DebouncedEvent) -> Option<AssetEvent> {
+  match event {
+    DebouncedEvent::Create(path) => Some(AssetEvent::Created(path)),
+    DebouncedEvent::Write(path) => Some(AssetEvent::Modified(path)),
+    DebouncedEvent::Rename(from, to) => Some(AssetEvent::Renamed { from, to }),
+    DebouncedEvent::Remove(path) => Some(AssetEvent::Deleted(path)),
+    DebouncedEvent::Rescan => Some(AssetEvent::Rescan),
+    DebouncedEvent::Error(err, path) => {
+      println!("Watcher error ({:?}): {}", path, err);
+      None
+    }
+    // Notices arrive before the debounced event they announce, and permission changes don't affect assets.
+    DebouncedEvent::NoticeWrite(_) | DebouncedEvent::NoticeRemove(_) | DebouncedEvent::Chmod(_) => None,
+  }
+}
@@ -0,0 +1,58 @@
+// Test Helpers
+// ------------
+//
+// What the integration tests share: throwaway projects to put assets in, and control over file timestamps. Each test
+// file uses only some of them.
+//
+#![allow(dead_code)]
+
+use std::fs::{self, File};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use project_server::importer::ImporterRegistry;
+use project_server::project::Project;
+use tempfile::TempDir;
+
+pub const MANIFEST: &str = "[project]\nname = \"Test\"\nengine_version = \"0.1.0\"\nasset_roots = [\"Assets\"]\n";
+
+// An empty project: a manifest and an Assets folder.
+pub fn project() -> TempDir {
+  let dir = tempfile::tempdir().unwrap();
+  fs::create_dir_all(dir.path().join("Assets")).unwrap();
+  fs::write(dir.path().join("theseus.toml"), MANIFEST).unwrap();
+  dir
+}
+
+// Writes a file into the project's Assets, creating the folders it's in.
+pub fn write_asset(dir: &TempDir, path: &str, contents: impl AsRef<[u8]>) {
+  let path = dir.path().join("Assets").join(path);
+  fs::create_dir_all(path.parent().unwrap()).unwrap();
+  fs::write(path, contents).unwrap();
+}
+
+pub fn read_asset(dir: &TempDir, path: &str) -> String {
+  fs::read_to_string(dir.path().join("Assets").join(path)).unwrap()
+}
+
+// Opens, scans and imports the project, which has to import cleanly.
+pub fn open(dir: &TempDir) -> Project {
+  let mut project = Project::open(dir.path(), ImporterRegistry::default()).unwrap();
+  project.scan();
+  let report = project.import_all();
+  assert!(report.failed.is_empty(), "{:?}", report.failed);
+  project
+}
+
+pub fn modified(path: &Path) -> SystemTime {
+  fs::metadata(path).unwrap().modified().unwrap()
+}
+
+// Sets a file's mtime, so tests don't depend on the file system's timestamp resolution.
+pub fn set_modified(path: &Path, modified: SystemTime) {
+  File::options().write(true).open(path).unwrap().set_modified(modified).unwrap();
+}
+
+// Moves a file's mtime a minute ahead, so a rescan sees it changed even if it was written within the same tick.
+pub fn touch_later(path: &Path) {
+  set_modified(path, modified(path) + Duration::from_secs(60));
+}
@@ -0,0 +1,170 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use project_server::database::{AssetDatabase, Refresh};
+use project_server::ignore_rules::IgnoreRules;
+use project_server::meta::{get_meta_file, Meta};
+use tempfile::TempDir;
+use crate::common::{modified, set_modified};
+
+mod common;
+
+// Asset Database Tests
+// --------------------
+//
+// Rescans only re-read what changed: a file whose size, mtime and meta mtime all match the index isn't hashed again,
+// and the index survives a restart through the Library folder.
+//
+
+struct Fixture {
+  dir: TempDir,
+  assets: PathBuf,
+}
+
+impl Fixture {
+  fn new() -> Fixture {
+    let dir = tempfile::tempdir().unwrap();
+    let assets = dir.path().canonicalize().unwrap().join("Assets");
+    fs::create_dir_all(assets.join("textures")).unwrap();
+    fs::write(assets.join("textures/logo.png"), b"not really a png").unwrap();
+    fs::write(assets.join("notes.txt"), b"hello").unwrap();
+    Fixture { dir, assets }
+  }
+
+  fn library(&self) -> PathBuf {
+    self.dir.path().join("Library")
+  }
+
+  fn database(&self) -> AssetDatabase {
+    AssetDatabase::new(std::slice::from_ref(&self.assets), IgnoreRules::load(self.dir.path()).0)
+  }
+
+  fn load(&self) -> AssetDatabase {
+    AssetDatabase::load(std::slice::from_ref(&self.assets), &self.library(), IgnoreRules::load(self.dir.path()).0)
+  }
+}
+
+#[test]
+fn first_scan_adds_everything_and_creates_meta_files() {
+  let fixture = Fixture::new();
+  let mut database = fixture.database();
+  let report = database.scan();
+  let mut added = report.added.clone();
+  added.sort();
+  assert_eq!(added, vec![PathBuf::from("notes.txt"), PathBuf::from("textures"), PathBuf::from("textures/logo.png")]);
+  assert!(report.errors.is_empty(), "{:?}", report.errors);
+  assert!(fixture.assets.join("notes.txt.meta").is_file());
+  assert_eq!(database.get(Path::new("textures/logo.png")).unwrap().asset_type, "texture");
+}
+
+#[test]
+fn unchanged_files_are_not_read_again() {
+  let fixture = Fixture::new();
+  let mut database = fixture.database();
+  database.scan();
+  let path = fixture.assets.join("notes.txt");
+  let hash = database.get(Path::new("notes.txt")).unwrap().hash.clone();
+
+  // Same size and mtime: the index can't tell the contents changed, because it doesn't look.
+  let before = modified(&path);
+  fs::write(&path, b"HELLO").unwrap();
+  set_modified(&path, before);
+  assert_eq!(database.refresh(&path).unwrap(), Refresh::Unchanged);
+  assert_eq!(database.get(Path::new("notes.txt")).unwrap().hash, hash);
+
+  let report = database.scan();
+  assert!(report.added.is_empty() && report.changed.is_empty() && report.removed.is_empty());
+  assert_eq!(report.unchanged, 3);
+}
+
+#[test]
+fn touched_files_with_the_same_contents_are_unchanged() {
+  let fixture = Fixture::new();
+  let mut database = fixture.database();
+  database.scan();
+  let path = fixture.assets.join("notes.txt");
+  let touched = modified(&path) + Duration::from_secs(60);
+  set_modified(&path, touched);
+  assert_eq!(database.refresh(&path).unwrap(), Refresh::Unchanged);
+  // The new mtime is remembered, so the next scan skips the file again.
+  assert_eq!(database.get(Path::new("notes.txt")).unwrap().modified, touched);
+}
+
+#[test]
+fn modified_files_are_changed() {
+  let fixture = Fixture::new();
+  let mut database = fixture.database();
+  database.scan();
+  let path = fixture.assets.join("notes.txt");
+  let hash = database.get(Path::new("notes.txt")).unwrap().hash.clone();
+  fs::write(&path, b"hello, world").unwrap();
+  let report = database.scan();
+  assert_eq!(report.changed, vec![PathBuf::from("notes.txt")]);
+  assert_ne!(database.get(Path::new("notes.txt")).unwrap().hash, hash);
+}
+
+#[test]
+fn meta_only_changes_are_changed() {
+  let fixture = Fixture::new();
+  let mut database = fixture.database();
+  database.scan();
+  let path = fixture.assets.join("textures/logo.png");
+  let meta_path = get_meta_file(&path).unwrap();
+  let mut meta = Meta::read(&meta_path).unwrap();
+  meta.labels.push(String::from("ui"));
+  meta.write(&meta_path).unwrap();
+  set_modified(&meta_path, modified(&meta_path) + Duration::from_secs(60));
+
+  assert_eq!(database.refresh(&path).unwrap(), Refresh::Changed);
+  assert_eq!(database.get(Path::new("textures/logo.png")).unwrap().labels, vec![String::from("ui")]);
+}
+
+#[test]
+fn deleted_files_are_removed() {
+  let fixture = Fixture::new();
+  let mut database = fixture.database();
+  database.scan();
+  let guid = database.guid_for_path(Path::new("textures/logo.png")).unwrap();
+  fs::remove_dir_all(fixture.assets.join("textures")).unwrap();
+  let report = database.scan();
+  let mut removed: Vec<PathBuf> = report.removed.iter().map(|record| record.path.clone()).collect();
+  removed.sort();
+  assert_eq!(removed, vec![PathBuf::from("textures"), PathBuf::from("textures/logo.png")]);
+  assert!(database.get_by_guid(&guid).is_none());
+  assert_eq!(database.len(), 1);
+}
+
+#[test]
+fn saved_index_is_loaded_and_skips_unchanged_files() {
+  let fixture = Fixture::new();
+  let mut database = fixture.database();
+  database.scan();
+  database.save(&fixture.library()).unwrap();
+
+  let mut loaded = fixture.load();
+  assert_eq!(loaded.len(), database.len());
+  for record in database.iter() {
+    assert_eq!(loaded.get(&record.path), Some(record));
+  }
+  let report = loaded.scan();
+  assert!(report.added.is_empty() && report.changed.is_empty(), "{:?}", report);
+  assert_eq!(report.unchanged, 3);
+}
+
+#[test]
+fn outdated_index_is_rebuilt() {
+  let fixture = Fixture::new();
+  let mut database = fixture.database();
+  database.scan();
+  database.save(&fixture.library()).unwrap();
+
+  let database_path = fixture.library().join("asset-database.json");
+  let mut saved: serde_json::Value = serde_json::from_slice(&fs::read(&database_path).unwrap()).unwrap();
+  assert_eq!(saved["version"], 4);
+  saved["version"] = serde_json::json!(3);
+  fs::write(&database_path, serde_json::to_vec(&saved).unwrap()).unwrap();
+  assert!(fixture.load().is_empty());
+
+  fs::write(&database_path, b"{ not json").unwrap();
+  assert!(fixture.load().is_empty());
+}
@@ -0,0 +1,128 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use project_server::importer::ImporterRegistry;
+use project_server::meta::Meta;
+use project_server::project::Project;
+use project_server::verify::{self, Fix, ProblemKind};
+use tempfile::TempDir;
+
+// Doctor Tests
+// ------------
+//
+// 02-test-project is a small consistent project, with a meta file for every asset. Each test works on a copy of it and
+// breaks it the way projects get broken.
+//
+
+fn fixture() -> TempDir {
+  let dir = tempfile::tempdir().unwrap();
+  copy_dir(&Path::new(env!("CARGO_MANIFEST_DIR")).join("../02-test-project"), dir.path());
+  // A Library from running the server on the fixture itself isn't part of it.
+  let _ = fs::remove_dir_all(dir.path().join("Library"));
+  dir
+}
+
+fn copy_dir(from: &Path, to: &Path) {
+  fs::create_dir_all(to).unwrap();
+  for entry in fs::read_dir(from).unwrap() {
+    let path = entry.unwrap().path();
+    let target = to.join(path.file_name().unwrap());
+    if path.is_dir() {
+      copy_dir(&path, &target);
+    } else {
+      fs::copy(&path, &target).unwrap();
+    }
+  }
+}
+
+fn open(dir: &TempDir) -> Project {
+  Project::open(dir.path(), ImporterRegistry::default()).unwrap()
+}
+
+fn guid_of(dir: &TempDir, asset: &str) -> uuid::Uuid {
+  Meta::read(&dir.path().join("Assets").join(format!("{}.meta", asset))).unwrap().guid
+}
+
+fn problems(project: &Project) -> Vec<(ProblemKind, PathBuf, Option<Fix>)> {
+  let mut problems: Vec<_> =
+    verify::verify(project).problems.into_iter().map(|problem| (problem.kind, problem.path, problem.fix)).collect();
+  problems.sort_by(|a, b| a.1.cmp(&b.1));
+  problems
+}
+
+#[test]
+fn fixture_is_consistent() {
+  let dir = fixture();
+  let report = verify::verify(&open(&dir));
+  assert!(report.is_ok(), "{:?}", report.problems);
+  assert_eq!(report.assets, 6);
+}
+
+#[test]
+fn finds_and_fixes_meta_problems() {
+  let dir = fixture();
+  let assets = dir.path().join("Assets");
+  let inner_folder = guid_of(&dir, "Scripts/Inner Folder");
+  let another_one = guid_of(&dir, "Scripts/Inner Folder/another-one.txt");
+  // A copy-pasted folder, with its meta file.
+  copy_dir(&assets.join("Scripts/Inner Folder"), &assets.join("Scripts/Inner Folder copy"));
+  fs::copy(assets.join("Scripts/Inner Folder.meta"), assets.join("Scripts/Inner Folder copy.meta")).unwrap();
+  fs::remove_file(assets.join("Scripts/nothing.txt.meta")).unwrap();
+  fs::write(assets.join("just-a-note.txt.meta"), "not a meta file").unwrap();
+  fs::copy(assets.join("Scripts/my-cool-script.txt.meta"), assets.join("Scripts/gone.txt.meta")).unwrap();
+
+  let project = open(&dir);
+  assert_eq!(
+    problems(&project),
+    vec![
+      (ProblemKind::DuplicateGuid, PathBuf::from("Scripts/Inner Folder copy"), Some(Fix::NewGuid)),
+      (ProblemKind::DuplicateGuid, PathBuf::from("Scripts/Inner Folder copy/another-one.txt"), Some(Fix::NewGuid)),
+      (ProblemKind::OrphanedMeta, PathBuf::from("Scripts/gone.txt.meta"), Some(Fix::DeleteMeta)),
+      (ProblemKind::MissingMeta, PathBuf::from("Scripts/nothing.txt"), Some(Fix::CreateMeta)),
+      (ProblemKind::InvalidMeta, PathBuf::from("just-a-note.txt.meta"), Some(Fix::ResetMeta)),
+    ]
+  );
+
+  for problem in verify::verify(&project).problems {
+    verify::apply_fix(&project, &problem).unwrap();
+  }
+  assert_eq!(problems(&project), vec![]);
+  assert!(!assets.join("Scripts/gone.txt.meta").exists());
+  // The originals keep their GUIDs; only the copies get new ones.
+  assert_eq!(guid_of(&dir, "Scripts/Inner Folder"), inner_folder);
+  assert_eq!(guid_of(&dir, "Scripts/Inner Folder/another-one.txt"), another_one);
+  assert_ne!(guid_of(&dir, "Scripts/Inner Folder copy"), inner_folder);
+  assert_ne!(guid_of(&dir, "Scripts/Inner Folder copy/another-one.txt"), another_one);
+}
+
+#[test]
+fn duplicate_keeps_guid_known_to_database() {
+  let dir = fixture();
+  let mut project = open(&dir);
+  project.scan();
+  // The copy sorts first, but the database knows the GUID belongs to the original.
+  let assets = dir.path().join("Assets");
+  copy_dir(&assets.join("Scripts/Inner Folder"), &assets.join("Scripts/A Folder"));
+  fs::copy(assets.join("Scripts/Inner Folder.meta"), assets.join("Scripts/A Folder.meta")).unwrap();
+
+  assert_eq!(
+    problems(&project),
+    vec![
+      (ProblemKind::DuplicateGuid, PathBuf::from("Scripts/A Folder"), Some(Fix::NewGuid)),
+      (ProblemKind::DuplicateGuid, PathBuf::from("Scripts/A Folder/another-one.txt"), Some(Fix::NewGuid)),
+    ]
+  );
+}
+
+#[test]
+fn keeps_guid_when_resetting_known_meta() {
+  let dir = fixture();
+  let mut project = open(&dir);
+  project.scan();
+  let guid = guid_of(&dir, "just-a-note.txt");
+  fs::write(dir.path().join("Assets/just-a-note.txt.meta"), "(format_version: 1, guid: ").unwrap();
+
+  let report = verify::verify(&project);
+  assert_eq!(report.problems.len(), 1);
+  verify::apply_fix(&project, &report.problems[0]).unwrap();
+  assert_eq!(guid_of(&dir, "just-a-note.txt"), guid);
+}
@@ -0,0 +1,203 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use project_server::image::Image;
+use project_server::importer::ImporterRegistry;
+use project_server::pack;
+use project_server::project::Project;
+use project_server::verify;
+use tempfile::TempDir;
+use crate::common::{open, read_asset, touch_later, write_asset};
+
+mod common;
+
+// Generated File Tests
+// --------------------
+//
+// A texture with sprite settings generates its sprite sheet as an artifact, and an atlas folder its texture, sheet and
+// name table. Nothing is written into Assets, RON files refer to the generated files as if they were next to their
+// asset, and packs ship them there.
+//
+
+const PADDLE_META: &str = r#"(
+  format_version: 1,
+  guid: "0b6d3c39-5a0e-4a43-9c55-2f1c5f1e8d1a",
+  importer: (
+    name: "texture",
+    settings: {"sprites": "grid", "columns": 2, "names": ["paddle", "ball"]},
+  ),
+)
+"#;
+
+const ATLAS_META: &str = r#"(
+  format_version: 1,
+  guid: "6f0a8a0e-3b5d-4a8e-9f57-6d1c0b9a2e11",
+  importer: (
+    name: "atlas",
+    settings: {},
+  ),
+)
+"#;
+
+const PREFAB: &str = "(\n  sheet: File(\"textures/pong.ron\", (\"SPRITE_SHEET\", ())),\n  sprite_number: \"ball\",\n)\n";
+
+fn fixture() -> TempDir {
+  let dir = common::project();
+  write_asset(&dir, "textures/pong.png", Image::new(8, 4, [255, 255, 255, 255]).encode_png().unwrap());
+  write_asset(&dir, "textures/pong.png.meta", PADDLE_META);
+  write_asset(&dir, "prefabs/ball.ron", PREFAB);
+
+  write_asset(&dir, "sprites/ui.meta", ATLAS_META);
+  write_asset(&dir, "sprites/ui/button.png", Image::new(8, 4, [255, 0, 0, 255]).encode_png().unwrap());
+  write_asset(&dir, "sprites/ui/icons/heart.png", Image::new(4, 4, [0, 255, 0, 255]).encode_png().unwrap());
+  let menu = "(\n  sheet: File(\"sprites/ui.ron\", (\"SPRITE_SHEET\", ())),\n  sprite_number: \"icons/heart\",\n)\n";
+  write_asset(&dir, "prefabs/menu.ron", menu);
+  dir
+}
+
+fn artifacts(project: &Project, path: &str) -> Vec<String> {
+  let guid = project.database.guid_for_path(Path::new(path)).unwrap();
+  project.pipeline.record(&guid).unwrap().artifacts.clone()
+}
+
+fn read_artifact(project: &Project, path: &str, artifact: &str) -> String {
+  let guid = project.database.guid_for_path(Path::new(path)).unwrap();
+  fs::read_to_string(project.pipeline.artifacts_path(&guid).join(artifact)).unwrap()
+}
+
+#[test]
+fn sprite_sheets_are_artifacts() {
+  let dir = fixture();
+  let project = open(&dir);
+  assert_eq!(artifacts(&project, "textures/pong.png"), vec!["texture.png", "texture.ron", "sheet.ron"]);
+  assert!(read_artifact(&project, "textures/pong.png", "sheet.ron").contains("// 1: ball"));
+  assert!(!dir.path().join("Assets/textures/pong.ron").exists());
+
+  // The prefab refers to the sheet, so it depends on the texture, and its sprite name resolves through the texture.
+  let dependencies = project.dependencies.dependencies_of(Path::new("prefabs/ball.ron"));
+  assert_eq!(dependencies, vec![PathBuf::from("textures/pong.png")]);
+  assert!(read_artifact(&project, "prefabs/ball.ron", "asset.ron").contains("sprite_number: 1,"));
+  let report = verify::verify(&project);
+  assert!(report.is_ok(), "{:?}", report.problems);
+}
+
+#[test]
+fn atlases_are_artifacts() {
+  let dir = fixture();
+  let project = open(&dir);
+  assert_eq!(artifacts(&project, "sprites/ui"), vec!["atlas.png", "sheet.ron", "names.ron"]);
+  assert!(read_artifact(&project, "sprites/ui", "names.ron").contains("\"icons/heart\": 1,"));
+  for generated in ["sprites/ui.png", "sprites/ui.ron", "sprites/ui.names.ron"] {
+    assert!(!dir.path().join("Assets").join(generated).exists(), "{}", generated);
+  }
+  // No other asset's meta file is touched: the sprite names come from the folder itself.
+  assert_eq!(fs::read_to_string(dir.path().join("Assets/sprites/ui.meta")).unwrap(), ATLAS_META);
+
+  let dependencies = project.dependencies.dependencies_of(Path::new("prefabs/menu.ron"));
+  assert_eq!(dependencies, vec![PathBuf::from("sprites/ui")]);
+  assert!(read_artifact(&project, "prefabs/menu.ron", "asset.ron").contains("sprite_number: 1,"));
+  let report = verify::verify(&project);
+  assert!(report.is_ok(), "{:?}", report.problems);
+}
+
+#[test]
+fn atlases_follow_their_images() {
+  let dir = fixture();
+  let mut project = open(&dir);
+  // "arrow" sorts first, so the heart becomes sprite 2 and the prefab is imported again.
+  let arrow = Image::new(2, 2, [0, 0, 255, 255]).encode_png().unwrap();
+  fs::write(dir.path().join("Assets/sprites/ui/arrow.png"), arrow).unwrap();
+  project.scan();
+  project.import_all();
+  assert!(read_artifact(&project, "sprites/ui", "names.ron").contains("\"icons/heart\": 2,"));
+  assert!(read_artifact(&project, "prefabs/menu.ron", "asset.ron").contains("sprite_number: 2,"));
+}
+
+#[test]
+fn moving_a_texture_moves_references_to_its_sheet() {
+  let dir = fixture();
+  let mut project = open(&dir);
+  let edits = project.move_asset(Path::new("textures/pong.png"), Path::new("textures/paddles.png")).unwrap();
+  let edited: Vec<(PathBuf, String)> = edits.into_iter().map(|edit| (edit.file, edit.new_path)).collect();
+  assert_eq!(edited, vec![(PathBuf::from("prefabs/ball.ron"), String::from("textures/paddles.ron"))]);
+  assert!(read_asset(&dir, "prefabs/ball.ron").contains("File(\"textures/paddles.ron\""));
+
+  let dependencies = project.dependencies.dependencies_of(Path::new("prefabs/ball.ron"));
+  assert_eq!(dependencies, vec![PathBuf::from("textures/paddles.png")]);
+  let report = verify::verify(&project);
+  assert!(report.is_ok(), "{:?}", report.problems);
+}
+
+#[test]
+fn moving_an_atlas_moves_references_to_its_files() {
+  let dir = fixture();
+  let hud = "(\n  texture: File(\"sprites/ui.png\", (\"IMAGE\", ())),\n  names: File(\"sprites/ui.names.ron\"),\n)\n";
+  write_asset(&dir, "prefabs/hud.ron", hud);
+  let mut project = open(&dir);
+  project.move_asset(Path::new("sprites/ui"), Path::new("sprites/menu")).unwrap();
+  assert!(read_asset(&dir, "prefabs/menu.ron").contains("File(\"sprites/menu.ron\""));
+  let hud = read_asset(&dir, "prefabs/hud.ron");
+  assert!(hud.contains("File(\"sprites/menu.png\"") && hud.contains("File(\"sprites/menu.names.ron\")"), "{}", hud);
+
+  for prefab in ["prefabs/menu.ron", "prefabs/hud.ron"] {
+    assert_eq!(project.dependencies.dependencies_of(Path::new(prefab)), vec![PathBuf::from("sprites/menu")]);
+  }
+  let report = verify::verify(&project);
+  assert!(report.is_ok(), "{:?}", report.problems);
+}
+
+#[test]
+fn sheets_go_away_with_their_settings() {
+  let dir = fixture();
+  let mut project = open(&dir);
+  let meta_path = dir.path().join("Assets/textures/pong.png.meta");
+  let settings = r#"{"sprites": "grid", "columns": 2, "names": ["paddle", "ball"]}"#;
+  fs::write(&meta_path, PADDLE_META.replace(settings, "{}")).unwrap();
+  touch_later(&meta_path);
+  project.scan();
+  project.import_all();
+  assert_eq!(artifacts(&project, "textures/pong.png"), vec!["texture.png", "texture.ron"]);
+  let guid = project.database.guid_for_path(Path::new("textures/pong.png")).unwrap();
+  assert!(!project.pipeline.artifacts_path(&guid).join("sheet.ron").exists());
+}
+
+#[test]
+fn files_in_the_way_of_a_sheet_fail_the_import() {
+  let dir = fixture();
+  fs::write(dir.path().join("Assets/textures/pong.ron"), "List((texture_width: 8, texture_height: 4, sprites: []))")
+    .unwrap();
+  let mut project = Project::open(dir.path(), ImporterRegistry::default()).unwrap();
+  project.scan();
+  let report = project.import_all();
+  let failed: Vec<&PathBuf> = report.failed.iter().map(|(path, _)| path).collect();
+  assert!(failed.contains(&&PathBuf::from("textures/pong.png")), "{:?}", report.failed);
+}
+
+#[test]
+fn packs_ship_atlases_next_to_their_folders() {
+  let dir = fixture();
+  let project = open(&dir);
+  let output = dir.path().join("assets.pack");
+  let report = pack::pack(&project, &output, &[PathBuf::from("prefabs/menu.ron")]).unwrap();
+  let mut packed = report.packed.clone();
+  packed.sort();
+  let expected = ["prefabs/menu.ron", "sprites/ui.names.ron", "sprites/ui.png", "sprites/ui.ron"];
+  assert_eq!(packed, expected.iter().map(PathBuf::from).collect::<Vec<_>>());
+}
+
+#[test]
+fn packs_ship_sheets_next_to_their_textures() {
+  let dir = fixture();
+  let project = open(&dir);
+  let output = dir.path().join("assets.pack");
+  let report = pack::pack(&project, &output, &[PathBuf::from("prefabs/ball.ron")]).unwrap();
+  let mut packed = report.packed.clone();
+  packed.sort();
+  let expected = ["prefabs/ball.ron", "textures/pong.png", "textures/pong.ron"];
+  assert_eq!(packed, expected.iter().map(PathBuf::from).collect::<Vec<_>>());
+
+  let mut archive = zip::ZipArchive::new(File::open(&output).unwrap()).unwrap();
+  let mut sheet = String::new();
+  archive.by_name("textures/pong.ron").unwrap().read_to_string(&mut sheet).unwrap();
+  assert_eq!(sheet, read_artifact(&project, "textures/pong.png", "sheet.ron"));
+}
@@ -0,0 +1,86 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::Command;
+use project_server::image::Image;
+use project_server::importer::ImporterRegistry;
+use project_server::pack::{self, PackError, PackIndex, PACK_INDEX};
+use project_server::project::Project;
+use sha2::{Digest, Sha256};
+use tempfile::TempDir;
+use zip::ZipArchive;
+use crate::common::{open, write_asset};
+
+mod common;
+
+// Pack Tests
+// ----------
+//
+// Packs hold the imported assets and an index of them, and leave out meta files and anything without an importer.
+//
+
+fn fixture() -> TempDir {
+  let dir = common::project();
+  write_asset(&dir, "textures/logo.png", Image::new(4, 4, [255, 255, 255, 255]).encode_png().unwrap());
+  write_asset(&dir, "prefabs/ball.ron", "(\n  sprite: File(\"textures/logo.png\"),\n)\n");
+  write_asset(&dir, "notes/todo.txt", "pack this");
+  dir
+}
+
+fn read_entry(archive: &mut ZipArchive<File>, name: &str) -> Vec<u8> {
+  let mut contents = Vec::new();
+  archive.by_name(name).unwrap().read_to_end(&mut contents).unwrap();
+  contents
+}
+
+#[test]
+fn packs_hold_imported_assets_and_their_index() {
+  let dir = fixture();
+  let project = open(&dir);
+  let output = dir.path().join("assets.pack");
+  let report = pack::pack(&project, &output, &[]).unwrap();
+  assert_eq!(report.skipped, vec![PathBuf::from("notes/todo.txt")]);
+
+  let mut archive = ZipArchive::new(File::open(&output).unwrap()).unwrap();
+  let mut names: Vec<&str> = archive.file_names().collect();
+  names.sort();
+  // No meta files, folders or source-only files.
+  assert_eq!(names, vec!["prefabs/ball.ron", "textures/logo.png", PACK_INDEX]);
+
+  let index: PackIndex = serde_json::from_slice(&read_entry(&mut archive, PACK_INDEX)).unwrap();
+  assert_eq!(index.project, "Test");
+  let mut paths: Vec<&str> = index.entries.iter().map(|entry| entry.path.as_str()).collect();
+  paths.sort();
+  assert_eq!(paths, vec!["prefabs/ball.ron", "textures/logo.png"]);
+  for entry in &index.entries {
+    let contents = read_entry(&mut archive, &entry.path);
+    assert_eq!(entry.size, contents.len() as u64, "{}", entry.path);
+    assert_eq!(entry.hash, format!("{:x}", Sha256::digest(&contents)), "{}", entry.path);
+  }
+  assert_eq!(report.bytes, index.entries.iter().map(|entry| entry.size).sum::<u64>());
+}
+
+#[test]
+fn assets_that_failed_to_import_fail_the_pack() {
+  let dir = fixture();
+  write_asset(&dir, "textures/broken.png", "not a png");
+  let mut project = Project::open(dir.path(), ImporterRegistry::default()).unwrap();
+  project.scan();
+  project.import_all();
+  let output = dir.path().join("assets.pack");
+  match pack::pack(&project, &output, &[]) {
+    Err(PackError::NotImported(assets)) => assert_eq!(assets[0].0, PathBuf::from("textures/broken.png")),
+    other => panic!("{:?}", other),
+  }
+  assert!(!output.exists());
+
+  // The command reports it as a problem with the project rather than succeeding.
+  let packed = Command::new(env!("CARGO_BIN_EXE_project-server"))
+    .arg(dir.path())
+    .args(["pack", "--output"])
+    .arg(&output)
+    .output()
+    .unwrap();
+  assert_eq!(packed.status.code(), Some(2), "{}", String::from_utf8_lossy(&packed.stdout));
+  assert!(!output.exists());
+}
@@ -0,0 +1,161 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use project_server::changes::ChangeKind;
+use project_server::error::ProjectError;
+use project_server::references::{apply_rewrites, FileRewrite};
+use tempfile::TempDir;
+use crate::common::{open, read_asset as read, write_asset};
+
+mod common;
+
+// Reference Rewriting Tests
+// -------------------------
+//
+// Moving an asset rewrites the `File("...")` references to it through `<file>.rewrite.tmp` files, and a failure part way
+// leaves every file as it was.
+//
+
+const LEVEL: &str = "(\n  ball: File(\"prefabs/ball.ron\"),\n  background: File(\"sprites/sky.txt\"),\n)\n";
+const BALL: &str = "(\n  sprite: File(\"sprites/ball.txt\"),\n  speed: 2.0,\n)\n";
+
+fn fixture() -> TempDir {
+  let dir = common::project();
+  write_asset(&dir, "prefabs/level.ron", LEVEL);
+  write_asset(&dir, "prefabs/ball.ron", BALL);
+  write_asset(&dir, "sprites/ball.txt", "ball");
+  write_asset(&dir, "sprites/sky.txt", "sky");
+  dir
+}
+
+fn plan(dir: &TempDir, file: &str, from: &str, to: &str) -> FileRewrite {
+  let path = dir.path().join("Assets").join(file);
+  let text = fs::read_to_string(&path).unwrap();
+  FileRewrite::plan(&path, Path::new(file), text, Path::new(from), Path::new(to)).unwrap()
+}
+
+fn both_rewrites(dir: &TempDir) -> Vec<FileRewrite> {
+  vec![plan(dir, "prefabs/level.ron", "sprites", "art"), plan(dir, "prefabs/ball.ron", "sprites", "art")]
+}
+
+fn temp_files(dir: &Path) -> Vec<PathBuf> {
+  let mut temps = Vec::new();
+  for entry in fs::read_dir(dir).unwrap() {
+    let path = entry.unwrap().path();
+    if path.is_dir() {
+      temps.extend(temp_files(&path));
+    } else if path.to_string_lossy().ends_with(".rewrite.tmp") {
+      temps.push(path);
+    }
+  }
+  temps
+}
+
+#[test]
+fn plan_finds_references_to_the_asset_and_inside_it() {
+  let dir = fixture();
+  let rewrite = plan(&dir, "prefabs/level.ron", "sprites", "art");
+  assert_eq!(rewrite.edits.len(), 1);
+  assert_eq!(rewrite.edits[0].line, 3);
+  assert_eq!(rewrite.edits[0].old_path, "sprites/sky.txt");
+  assert_eq!(rewrite.edits[0].new_path, "art/sky.txt");
+  assert_eq!(rewrite.rewritten(), LEVEL.replace("sprites/sky.txt", "art/sky.txt"));
+
+  // A name that merely starts the same isn't inside the folder.
+  let text = "(a: File(\"sprites2/x.txt\"), b: File(\"sprites\"))";
+  let plan_text = |text: &str| {
+    FileRewrite::plan(Path::new("a.ron"), Path::new("a.ron"), String::from(text), Path::new("sprites"), Path::new("art"))
+  };
+  assert_eq!(plan_text(text).unwrap().rewritten(), "(a: File(\"sprites2/x.txt\"), b: File(\"art\"))");
+  assert!(plan_text("(a: File(\"other.txt\"))").is_none());
+}
+
+#[test]
+fn rewrites_are_applied_through_temp_files() {
+  let dir = fixture();
+  let rewrites = both_rewrites(&dir);
+  let mut saw_temps = Vec::new();
+  apply_rewrites(&rewrites, || {
+    saw_temps = temp_files(dir.path());
+    Ok(())
+  })
+  .unwrap();
+  // Everything is in place before the asset moves, and nothing is left behind.
+  assert!(saw_temps.is_empty(), "{:?}", saw_temps);
+  assert!(temp_files(dir.path()).is_empty());
+  assert!(read(&dir, "prefabs/level.ron").contains("File(\"art/sky.txt\")"));
+  assert!(read(&dir, "prefabs/ball.ron").contains("File(\"art/ball.txt\")"));
+}
+
+#[test]
+fn failed_writes_leave_every_file_alone() {
+  let dir = fixture();
+  let rewrites = both_rewrites(&dir);
+  // A folder where the second temp file goes makes writing it fail after the first one is written.
+  let blocker = dir.path().join("Assets/prefabs/ball.ron.rewrite.tmp");
+  fs::create_dir(&blocker).unwrap();
+  let mut moved = false;
+  let result = apply_rewrites(&rewrites, || {
+    moved = true;
+    Ok(())
+  });
+  assert!(matches!(result, Err(ProjectError::AssetUnwritable { .. })), "{:?}", result);
+  assert!(!moved);
+  assert_eq!(read(&dir, "prefabs/level.ron"), LEVEL);
+  assert_eq!(read(&dir, "prefabs/ball.ron"), BALL);
+  fs::remove_dir(&blocker).unwrap();
+  assert!(temp_files(dir.path()).is_empty());
+}
+
+#[test]
+fn failed_moves_restore_the_rewritten_files() {
+  let dir = fixture();
+  let rewrites = both_rewrites(&dir);
+  let result = apply_rewrites(&rewrites, || {
+    Err(ProjectError::AssetUnwritable { path: PathBuf::from("sprites"), source: io::Error::other("disk full") })
+  });
+  assert!(result.is_err());
+  assert_eq!(read(&dir, "prefabs/level.ron"), LEVEL);
+  assert_eq!(read(&dir, "prefabs/ball.ron"), BALL);
+  assert!(temp_files(dir.path()).is_empty());
+}
+
+#[test]
+fn files_edited_since_planning_are_not_overwritten() {
+  let dir = fixture();
+  let rewrite = plan(&dir, "prefabs/ball.ron", "sprites", "art");
+  let edited = BALL.replace("2.0", "3.0");
+  fs::write(dir.path().join("Assets/prefabs/ball.ron"), &edited).unwrap();
+  let result = apply_rewrites(&[rewrite], || Ok(()));
+  assert!(matches!(result, Err(ProjectError::FileChanged { .. })), "{:?}", result);
+  assert_eq!(read(&dir, "prefabs/ball.ron"), edited);
+  assert!(temp_files(dir.path()).is_empty());
+}
+
+#[test]
+fn moving_an_asset_rewrites_references_and_reimports_dependents_once() {
+  let dir = fixture();
+  let mut project = open(&dir);
+  let cursor = project.changes.cursor();
+  let edits = project.move_asset(Path::new("sprites"), Path::new("art")).unwrap();
+
+  let mut edited: Vec<(PathBuf, String)> = edits.into_iter().map(|edit| (edit.file, edit.new_path)).collect();
+  edited.sort();
+  assert_eq!(edited, vec![
+    (PathBuf::from("prefabs/ball.ron"), String::from("art/ball.txt")),
+    (PathBuf::from("prefabs/level.ron"), String::from("art/sky.txt")),
+  ]);
+  assert!(read(&dir, "prefabs/level.ron").contains("File(\"art/sky.txt\")"));
+  assert!(dir.path().join("Assets/art/ball.txt.meta").is_file());
+  assert!(!dir.path().join("Assets/sprites.meta").exists());
+  assert!(temp_files(dir.path()).is_empty());
+
+  // level.ron refers to the moved folder directly and through ball.ron, and was rewritten itself.
+  let changes = project.changes.since(cursor).unwrap();
+  let imports = |path: &str| {
+    changes.iter().filter(|change| change.kind == ChangeKind::Imported && change.path == Path::new(path)).count()
+  };
+  assert_eq!(imports("prefabs/level.ron"), 1);
+  assert_eq!(imports("prefabs/ball.ron"), 1);
+  assert_eq!(project.dependencies.dependencies_of(Path::new("prefabs/ball.ron")), vec![PathBuf::from("art/ball.txt")]);
+}
@@ -0,0 +1,120 @@
+use std::fs;
+use std::path::PathBuf;
+use project_server::database::AssetDatabase;
+use project_server::ignore_rules::IgnoreRules;
+use project_server::meta::{get_meta_file, Meta};
+use project_server::search::{search, Query};
+use tempfile::TempDir;
+use crate::common::touch_later;
+
+mod common;
+
+// Search Tests
+// ------------
+//
+// Queries run against a small indexed Assets folder: two textures, a font and a prefab spread over a few folders, with
+// labels and metadata on some of them.
+//
+
+struct Fixture {
+  // Kept so the folder outlives the database.
+  _dir: TempDir,
+  database: AssetDatabase,
+}
+
+impl Fixture {
+  fn new() -> Fixture {
+    let dir = tempfile::tempdir().unwrap();
+    let assets = dir.path().canonicalize().unwrap().join("Assets");
+    for (path, contents) in [
+      ("textures/ui/main_menu.png", "png"),
+      ("textures/Ball.png", "png"),
+      ("fonts/hack.ttf", "ttf"),
+      ("prefabs/main menu.ron", "()"),
+    ] {
+      fs::create_dir_all(assets.join(path).parent().unwrap()).unwrap();
+      fs::write(assets.join(path), contents).unwrap();
+    }
+    let mut database = AssetDatabase::new(std::slice::from_ref(&assets), IgnoreRules::load(dir.path()).0);
+    database.scan();
+
+    let meta_path = get_meta_file(&assets.join("textures/ui/main_menu.png")).unwrap();
+    let mut meta = Meta::read(&meta_path).unwrap();
+    meta.labels.push(String::from("UI"));
+    meta.metadata.insert(String::from("author"), String::from("Nick"));
+    meta.write(&meta_path).unwrap();
+    touch_later(&meta_path);
+    database.refresh(&assets.join("textures/ui/main_menu.png")).unwrap();
+    Fixture { _dir: dir, database }
+  }
+
+  fn search(&self, query: &str) -> Vec<PathBuf> {
+    let query = Query::parse(query).unwrap();
+    search(&self.database, &query).into_iter().map(|asset| asset.path.clone()).collect()
+  }
+}
+
+fn paths(paths: &[&str]) -> Vec<PathBuf> {
+  paths.iter().map(PathBuf::from).collect()
+}
+
+#[test]
+fn empty_queries_match_everything() {
+  let fixture = Fixture::new();
+  let all = fixture.search("");
+  assert_eq!(all.len(), fixture.database.len());
+  assert_eq!(fixture.search("   "), all);
+  // Sorted by path.
+  let mut sorted = all.clone();
+  sorted.sort();
+  assert_eq!(all, sorted);
+}
+
+#[test]
+fn type_filters() {
+  let fixture = Fixture::new();
+  assert_eq!(fixture.search("type:texture"), paths(&["textures/Ball.png", "textures/ui/main_menu.png"]));
+  assert_eq!(fixture.search("type:FOLDER"), paths(&["fonts", "prefabs", "textures", "textures/ui"]));
+  assert_eq!(fixture.search("-type:folder -type:texture"), paths(&["fonts/hack.ttf", "prefabs/main menu.ron"]));
+  assert!(fixture.search("type:text").is_empty());
+}
+
+#[test]
+fn folder_filters() {
+  let fixture = Fixture::new();
+  let everything_in_textures = paths(&["textures/Ball.png", "textures/ui", "textures/ui/main_menu.png"]);
+  assert_eq!(fixture.search("path:textures/*"), everything_in_textures);
+  assert_eq!(fixture.search("path:textures/* -type:folder"), paths(&["textures/Ball.png", "textures/ui/main_menu.png"]));
+  // Patterns match the whole path, so a folder's name alone only finds the folder.
+  assert_eq!(fixture.search("path:textures"), paths(&["textures"]));
+  assert_eq!(fixture.search("path:*/ui/*"), paths(&["textures/ui/main_menu.png"]));
+}
+
+#[test]
+fn wildcards() {
+  let fixture = Fixture::new();
+  assert_eq!(fixture.search("name:*.png"), paths(&["textures/Ball.png", "textures/ui/main_menu.png"]));
+  assert_eq!(fixture.search("name:b?ll.png"), paths(&["textures/Ball.png"]));
+  assert!(fixture.search("name:b?l.png").is_empty());
+  assert_eq!(fixture.search("name:h*k.*"), paths(&["fonts/hack.ttf"]));
+  assert_eq!(fixture.search("name:**a**.t?f"), paths(&["fonts/hack.ttf"]));
+  // A bare word matches anywhere in the name.
+  assert_eq!(fixture.search("menu"), paths(&["prefabs/main menu.ron", "textures/ui/main_menu.png"]));
+  assert_eq!(fixture.search("name:\"main menu.ron\""), paths(&["prefabs/main menu.ron"]));
+}
+
+#[test]
+fn labels_and_metadata() {
+  let fixture = Fixture::new();
+  assert_eq!(fixture.search("label:ui"), paths(&["textures/ui/main_menu.png"]));
+  assert_eq!(fixture.search("author:n*"), paths(&["textures/ui/main_menu.png"]));
+  assert_eq!(fixture.search("type:texture -label:ui"), paths(&["textures/Ball.png"]));
+  assert!(fixture.search("author:someone").is_empty());
+}
+
+#[test]
+fn invalid_queries() {
+  for query in ["name:\"unterminated", ":texture", "type:", "-label:"] {
+    assert!(Query::parse(query).is_err(), "{}", query);
+  }
+}
@@ -0,0 +1,63 @@
+use std::fs;
+use std::process::{Command, Output};
+use project_server::error::ProjectError;
+use project_server::importer::ImporterRegistry;
+use project_server::project::Project;
+use project_server::templates::{self, Template};
+
+// Template Tests
+// --------------
+//
+// Projects created from the sample games, through `new` the way they're created from the command line.
+//
+
+fn project_server(args: &[&std::ffi::OsStr]) -> Output {
+  Command::new(env!("CARGO_BIN_EXE_project-server")).args(args).output().unwrap()
+}
+
+#[test]
+fn new_pong_projects_scan_cleanly() {
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("my-pong");
+  let created = project_server(&[path.as_os_str(), "new".as_ref(), "--template".as_ref(), "pong".as_ref()]);
+  assert_eq!(created.status.code(), Some(0), "{}", String::from_utf8_lossy(&created.stdout));
+
+  assert!(path.join("Assets/texture/pong_spritesheet.png.meta").is_file());
+  assert!(path.join("config/bindings.ron").is_file());
+  let mut project = Project::open(&path, ImporterRegistry::default()).unwrap();
+  assert_eq!(project.manifest.project.name, "my-pong");
+  let scan = project.scan();
+  assert!(scan.errors.is_empty(), "{:?}", scan.errors);
+  let imports = project.import_all();
+  assert!(imports.failed.is_empty(), "{:?}", imports.failed);
+
+  let scanned = project_server(&[path.as_os_str(), "scan".as_ref()]);
+  assert_eq!(scanned.status.code(), Some(0), "{}", String::from_utf8_lossy(&scanned.stdout));
+}
+
+#[test]
+fn unknown_templates_are_refused() {
+  assert!("pacman".parse::<Template>().is_err());
+
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("pacman");
+  let created = project_server(&[path.as_os_str(), "new".as_ref(), "--template".as_ref(), "pacman".as_ref()]);
+  assert_ne!(created.status.code(), Some(0));
+  assert!(String::from_utf8_lossy(&created.stderr).contains("unknown template"));
+  assert!(!path.exists());
+}
+
+#[test]
+fn projects_are_not_created_over_existing_folders() {
+  let dir = tempfile::tempdir().unwrap();
+  fs::write(dir.path().join("notes.txt"), "keep me").unwrap();
+  match templates::create_project(dir.path(), None, Template::Pong) {
+    Err(ProjectError::ProjectExists { path }) => assert_eq!(path, dir.path()),
+    other => panic!("{:?}", other),
+  }
+  let entries: Vec<_> = fs::read_dir(dir.path()).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+  assert_eq!(entries, vec!["notes.txt"]);
+
+  let created = project_server(&[dir.path().as_os_str(), "new".as_ref(), "--template".as_ref(), "pong".as_ref()]);
+  assert_eq!(created.status.code(), Some(1), "{}", String::from_utf8_lossy(&created.stdout));
+}
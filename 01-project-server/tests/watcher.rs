@@ -0,0 +1,84 @@
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+use project_server::changes::ChangeKind;
+use project_server::image::Image;
+use project_server::project::Project;
+use project_server::watcher::{AssetEvent, AssetWatcher};
+use crate::common::{open, write_asset};
+
+mod common;
+
+// Watcher Tests
+// -------------
+//
+// The watcher on a throwaway project, with its events handed to the project the way the server hands them over.
+//
+
+const DEBOUNCE: Duration = Duration::from_millis(200);
+// How long the watcher has to stay quiet before a test takes it that everything has arrived.
+const SETTLE: Duration = Duration::from_secs(2);
+
+fn watch(project: &Project) -> (AssetWatcher, Receiver<AssetEvent>) {
+  let (events, events_rx) = mpsc::channel();
+  let watcher = AssetWatcher::start(&project.asset_roots, DEBOUNCE, move |event| {
+    let _ = events.send(event);
+  });
+  (watcher.unwrap(), events_rx)
+}
+
+// Handles the events until the watcher goes quiet, and returns them.
+fn settle(project: &mut Project, events: &Receiver<AssetEvent>) -> Vec<AssetEvent> {
+  let mut handled = Vec::new();
+  while let Ok(event) = events.recv_timeout(SETTLE) {
+    handled.push(event.clone());
+    project.handle_asset_event(event);
+  }
+  handled
+}
+
+#[test]
+fn bursts_of_writes_are_imported_once() {
+  let dir = common::project();
+  write_asset(&dir, "prefabs/ball.ron", "(speed: 0.0)\n");
+  let mut project = open(&dir);
+  let (_watcher, events) = watch(&project);
+  let cursor = project.changes.cursor();
+
+  let path = dir.path().join("Assets/prefabs/ball.ron");
+  for speed in 1..=10 {
+    fs::write(&path, format!("(speed: {}.0)\n", speed)).unwrap();
+    thread::sleep(Duration::from_millis(5));
+  }
+  let handled = settle(&mut project, &events);
+  assert_eq!(handled, vec![AssetEvent::Modified(path)]);
+
+  let changes = project.changes.since(cursor).unwrap();
+  let imports: Vec<_> = changes.iter().filter(|change| change.kind == ChangeKind::Imported).collect();
+  assert_eq!(imports.len(), 1, "{:?}", changes);
+  assert_eq!(imports[0].path, Path::new("prefabs/ball.ron"));
+  let guid = project.database.guid_for_path(Path::new("prefabs/ball.ron")).unwrap();
+  let artifact = fs::read_to_string(project.pipeline.artifacts_path(&guid).join("asset.ron")).unwrap();
+  assert!(artifact.contains("10.0"), "{}", artifact);
+}
+
+#[test]
+fn meta_files_move_with_their_assets() {
+  let dir = common::project();
+  write_asset(&dir, "textures/logo.png", Image::new(4, 4, [255, 255, 255, 255]).encode_png().unwrap());
+  fs::create_dir_all(dir.path().join("Assets/ui")).unwrap();
+  let mut project = open(&dir);
+  let guid = project.database.guid_for_path(Path::new("textures/logo.png")).unwrap();
+  let (_watcher, events) = watch(&project);
+
+  let assets = dir.path().join("Assets");
+  fs::rename(assets.join("textures/logo.png"), assets.join("ui/title.png")).unwrap();
+  settle(&mut project, &events);
+
+  assert!(assets.join("ui/title.png.meta").is_file());
+  assert!(!assets.join("textures/logo.png.meta").exists());
+  assert_eq!(project.database.guid_for_path(Path::new("ui/title.png")), Some(guid));
+  assert!(project.database.guid_for_path(Path::new("textures/logo.png")).is_none());
+}
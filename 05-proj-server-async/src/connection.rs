@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::mpsc::Sender as JobSender;
+use std::sync::{Arc, Mutex};
+use proj_server_async::protocol::{
+  self, ClientMessage, ErrorCode, Event, Reply, Request, RequestError, ServerMessage, GAME, LOCKS, SUBSCRIPTIONS,
+};
+use project_server::changes::{AssetChange, ChangeFilter};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::TcpStream;
+use tokio::sync::broadcast::{self, RecvError};
+use tokio::sync::{mpsc, oneshot};
+use crate::game::{GameJob, GameRequest, Launch};
+use crate::worker::{asset_change, Job, Subscription};
+
+// Connections
+// -----------
+//
+// Every client gets a task reading its messages and one writing to it. Each request is answered by a task of its own,
+// so the answers can overtake each other and a ping doesn't wait for an import; each subscription forwards changes
+// until it's cancelled or the client falls too far behind. Clients with the locks capability also get every presence
+// event, and clients watching the game get its output (see game.rs). Requests needing a capability the client didn't
+// ask for in its hello are answered with unsupported. When the server shuts down, the client is told before its
+// connection is closed.
+//
+
+// How the server introduces itself in the handshake.
+pub const SERVER_NAME: &str = "theseus";
+// How many messages can be waiting for a slow client before the tasks answering it wait too.
+const OUTGOING_LEN: usize = 256;
+
+// What a connection needs from the server.
+pub struct Server {
+  // The server's number for the client, unique while it runs.
+  pub peer: u64,
+  pub jobs: JobSender<Job>,
+  // Asks the server to shut down.
+  pub stop: mpsc::UnboundedSender<()>,
+  // Fires once the server has saved the project and is about to exit.
+  pub shutdown: broadcast::Receiver<()>,
+  pub presence: broadcast::Receiver<Event>,
+  pub game: mpsc::UnboundedSender<GameJob>,
+  // Subscribed to when the client watches the game.
+  pub game_events: broadcast::Sender<Event>,
+  // Held until the connection is closed, so the server can wait for every client to be told it's going away.
+  pub done: mpsc::Sender<()>,
+}
+
+type Subscriptions = Arc<Mutex<HashMap<u64, oneshot::Sender<()>>>>;
+
+pub async fn serve(mut socket: TcpStream, peer: SocketAddr, server: Server) {
+  let Server { peer: peer_id, jobs, stop, mut shutdown, presence, game, game_events, done } = server;
+  let hello = tokio::select! {
+    hello = protocol::server_handshake(&mut socket, SERVER_NAME, &[SUBSCRIPTIONS, LOCKS, GAME]) => hello,
+    _ = shutdown.recv() => return,
+  };
+  let (hello, welcome) = match hello {
+    Ok(handshake) => handshake,
+    Err(err) => {
+      println!("[{}] Handshake failed: {}", peer, err);
+      return;
+    }
+  };
+  println!("[{}] {} connected (protocol version {}).", peer, hello.client, hello.protocol_version);
+  let _ = jobs.send(Job::Connected { peer: peer_id, name: hello.client.clone() });
+  let has_locks = welcome.has_capability(LOCKS);
+
+  let (mut reader, writer) = socket.into_split();
+  let (mut outgoing, outgoing_rx) = mpsc::channel(OUTGOING_LEN);
+  let writing = tokio::spawn(write_messages(writer, outgoing_rx, peer));
+  let subscriptions = Subscriptions::default();
+  let mut next_subscription = 1;
+  // Dropped when the connection closes, which stops the presence events.
+  let (presence_cancel, presence_cancelled) = oneshot::channel();
+  if has_locks {
+    tokio::spawn(forward_events(presence, presence_cancelled, outgoing.clone()));
+  }
+  // Set while the client watches the game; dropping it stops the game events.
+  let mut game_watch = None;
+
+  loop {
+    let message = tokio::select! {
+      message = protocol::read_message(&mut reader) => message,
+      _ = shutdown.recv() => {
+        let _ = outgoing.send(ServerMessage::Event { event: Event::ServerShutdown }).await;
+        break;
+      }
+    };
+    let (id, request) = match message {
+      Ok(Some(ClientMessage::Request { id, request })) => (id, request),
+      Ok(Some(ClientMessage::Hello(_))) => {
+        println!("[{}] Unexpected hello after the handshake.", peer);
+        break;
+      }
+      Ok(None) => break,
+      // The frame can't be answered without its id, so the connection can't be trusted to stay in step either.
+      Err(err) => {
+        println!("[{}] Error reading message: {}", peer, err);
+        break;
+      }
+    };
+    if let Some(capability) = request.capability().filter(|capability| !welcome.has_capability(capability)) {
+      let message = format!("the {} capability wasn't negotiated", capability);
+      answer(&mut outgoing, id, Err(RequestError::new(ErrorCode::Unsupported, &message))).await;
+      continue;
+    }
+    match request {
+      Request::Ping => answer(&mut outgoing, id, Ok(Reply::Pong)).await,
+      Request::Shutdown => {
+        println!("[{}] Shutdown requested.", peer);
+        let _ = stop.send(());
+        answer(&mut outgoing, id, Ok(Reply::Done)).await;
+      }
+      Request::Subscribe { folder, types, since } => {
+        let subscription = next_subscription;
+        next_subscription += 1;
+        let (cancel, cancelled) = oneshot::channel();
+        subscriptions.lock().unwrap().insert(subscription, cancel);
+        let filter = ChangeFilter { folder: folder.map(PathBuf::from), types };
+        let task = subscribe(id, subscription, filter, since, cancelled, jobs.clone(), outgoing.clone());
+        let subscriptions = subscriptions.clone();
+        tokio::spawn(async move {
+          task.await;
+          subscriptions.lock().unwrap().remove(&subscription);
+        });
+      }
+      Request::Unsubscribe { subscription } => {
+        // Dropping the sender cancels the subscription.
+        subscriptions.lock().unwrap().remove(&subscription);
+        answer(&mut outgoing, id, Ok(Reply::Done)).await;
+      }
+      Request::WatchGame => {
+        let (cancel, cancelled) = oneshot::channel();
+        tokio::spawn(forward_events(game_events.subscribe(), cancelled, outgoing.clone()));
+        game_watch = Some(cancel);
+        answer(&mut outgoing, id, Ok(Reply::Done)).await;
+      }
+      Request::UnwatchGame => {
+        game_watch = None;
+        answer(&mut outgoing, id, Ok(Reply::Done)).await;
+      }
+      Request::BuildGame { release } => {
+        tokio::spawn(ask_game(id, GameRequest::Start(Launch::Build { release }), game.clone(), outgoing.clone()));
+      }
+      Request::RunGame { release, args } => {
+        let request = GameRequest::Start(Launch::Run { release, args });
+        tokio::spawn(ask_game(id, request, game.clone(), outgoing.clone()));
+      }
+      Request::StopGame => {
+        tokio::spawn(ask_game(id, GameRequest::Stop, game.clone(), outgoing.clone()));
+      }
+      Request::RestartGame => {
+        tokio::spawn(ask_game(id, GameRequest::Restart, game.clone(), outgoing.clone()));
+      }
+      Request::GameStatus => {
+        tokio::spawn(ask_game(id, GameRequest::Status, game.clone(), outgoing.clone()));
+      }
+      request => {
+        tokio::spawn(ask_worker(peer_id, has_locks, id, request, jobs.clone(), outgoing.clone()));
+      }
+    }
+  }
+
+  // Once the subscriptions are cancelled and the answers still being worked out are sent, nothing holds on to the
+  // outgoing channel, and the writer finishes.
+  subscriptions.lock().unwrap().clear();
+  drop(presence_cancel);
+  drop(game_watch);
+  drop(outgoing);
+  let _ = writing.await;
+  let _ = jobs.send(Job::Disconnected { peer: peer_id });
+  println!("[{}] Disconnected.", peer);
+  drop(done);
+}
+
+async fn write_messages(mut writer: OwnedWriteHalf, mut outgoing: mpsc::Receiver<ServerMessage>, peer: SocketAddr) {
+  while let Some(message) = outgoing.recv().await {
+    if let Err(err) = protocol::write_message(&mut writer, &message).await {
+      println!("[{}] Error writing message: {}", peer, err);
+      break;
+    }
+  }
+}
+
+async fn answer(outgoing: &mut mpsc::Sender<ServerMessage>, id: u64, result: Result<Reply, RequestError>) {
+  let message = match result {
+    Ok(result) => ServerMessage::Response { id, result },
+    Err(error) => ServerMessage::Error { id, error },
+  };
+  // A closed channel means the client is gone.
+  let _ = outgoing.send(message).await;
+}
+
+fn shutting_down() -> RequestError {
+  RequestError::new(ErrorCode::Internal, "the server is shutting down")
+}
+
+async fn ask_worker(
+  peer: u64,
+  has_locks: bool,
+  id: u64,
+  request: Request,
+  jobs: JobSender<Job>,
+  mut outgoing: mpsc::Sender<ServerMessage>,
+) {
+  let (respond, response) = oneshot::channel();
+  let result = match jobs.send(Job::Request { peer, request, respond }) {
+    Ok(()) => response.await.unwrap_or_else(|_| Err(shutting_down())),
+    Err(_) => Err(shutting_down()),
+  };
+  // Clients that don't know about locks wouldn't understand the error code.
+  let result = result.map_err(|error| match error.code {
+    ErrorCode::AssetLocked if !has_locks => RequestError { code: ErrorCode::ProjectError, ..error },
+    _ => error,
+  });
+  answer(&mut outgoing, id, result).await;
+}
+
+async fn ask_game(
+  id: u64,
+  request: GameRequest,
+  game: mpsc::UnboundedSender<GameJob>,
+  mut outgoing: mpsc::Sender<ServerMessage>,
+) {
+  let (respond, response) = oneshot::channel();
+  let result = match game.send(GameJob { request, respond }) {
+    Ok(()) => response.await.unwrap_or_else(|_| Err(shutting_down())),
+    Err(_) => Err(shutting_down()),
+  };
+  answer(&mut outgoing, id, result).await;
+}
+
+// Forwards presence or game events until cancelled.
+async fn forward_events(
+  mut presence: broadcast::Receiver<Event>,
+  mut cancelled: oneshot::Receiver<()>,
+  mut outgoing: mpsc::Sender<ServerMessage>,
+) {
+  loop {
+    let event = tokio::select! {
+      event = presence.recv() => event,
+      _ = &mut cancelled => return,
+    };
+    match event {
+      Ok(event) => {
+        if outgoing.send(ServerMessage::Event { event }).await.is_err() {
+          return;
+        }
+      }
+      // Presence changes are few, so this takes a client that isn't reading at all; it can list presence again. A
+      // game writing faster than a client reads costs the client some of its output.
+      Err(RecvError::Lagged(_)) => continue,
+      Err(RecvError::Closed) => return,
+    }
+  }
+}
+
+// Starts a subscription on the worker, answers the request, then forwards changes until the subscription is cancelled.
+async fn subscribe(
+  id: u64,
+  subscription: u64,
+  filter: ChangeFilter,
+  since: Option<u64>,
+  mut cancelled: oneshot::Receiver<()>,
+  jobs: JobSender<Job>,
+  mut outgoing: mpsc::Sender<ServerMessage>,
+) {
+  let (respond, response) = oneshot::channel();
+  let started = match jobs.send(Job::Subscribe { since, respond }) {
+    Ok(()) => response.await.unwrap_or_else(|_| Err(shutting_down())),
+    Err(_) => Err(shutting_down()),
+  };
+  let Subscription { cursor, backlog, mut changes } = match started {
+    Ok(started) => started,
+    Err(error) => return answer(&mut outgoing, id, Err(error)).await,
+  };
+  answer(&mut outgoing, id, Ok(Reply::Subscribed { subscription, cursor })).await;
+
+  let event = |change: &AssetChange| ServerMessage::Event {
+    event: Event::AssetChanged { subscription, change: asset_change(change) },
+  };
+  for change in backlog.iter().filter(|change| filter.matches(change)) {
+    if outgoing.send(event(change)).await.is_err() {
+      return;
+    }
+  }
+  loop {
+    let change = tokio::select! {
+      change = changes.recv() => change,
+      _ = &mut cancelled => return,
+    };
+    let message = match change {
+      Ok(change) if filter.matches(&change) => event(&change),
+      Ok(_) => continue,
+      // The client can't keep up; it has to list the asset tree again rather than miss changes.
+      Err(RecvError::Lagged(_)) => {
+        let _ = outgoing.send(ServerMessage::Event { event: Event::SubscriptionExpired { subscription } }).await;
+        return;
+      }
+      Err(RecvError::Closed) => return,
+    };
+    if outgoing.send(message).await.is_err() {
+      return;
+    }
+  }
+}
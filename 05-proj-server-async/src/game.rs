@@ -0,0 +1,607 @@
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use proj_server_async::protocol::{Diagnostic, ErrorCode, Event, GameProcess, OutputStream, Reply, RequestError};
+use project_server::manifest::Manifest;
+use serde::Deserialize;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::time;
+
+// Game Supervisor
+// ---------------
+//
+// The server builds and runs the project's game (see [game] in theseus.toml) with cargo, one build or run at a time.
+// Cargo reports in JSON on stdout until the build finishes; its compiler messages become diagnostics, and every other
+// line from cargo or the game is passed on as output. The supervisor task owns the state clients ask about, while each
+// process gets a task of its own that reads its output and waits for it to exit, so a noisy game never holds up a stop
+// request.
+//
+
+// How many lines of output the supervisor can be behind on before the processes wait for it.
+const OUTPUT_LEN: usize = 256;
+// How long to wait for the rest of the output once a process exits. Output only stays open longer when the process
+// left children of its own behind.
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Where the game is and how to build it, from the manifest.
+pub struct GameCrate {
+  pub path: PathBuf,
+  pub package: Option<String>,
+  pub cargo_args: Vec<String>,
+}
+
+impl GameCrate {
+  pub fn from_manifest(manifest: &Manifest, project_path: &Path) -> Option<GameCrate> {
+    let path = manifest.game_path(project_path)?;
+    let package = manifest.game.as_ref().and_then(|game| game.package.clone());
+    let cargo_args = manifest.game.as_ref().map(|game| game.cargo_args.clone()).unwrap_or_default();
+    Some(GameCrate { path, package, cargo_args })
+  }
+}
+
+#[derive(Clone)]
+pub enum Launch {
+  Build { release: bool },
+  Run { release: bool, args: Vec<String> },
+}
+
+pub enum GameRequest {
+  Start(Launch),
+  // Answered once the process has exited.
+  Stop,
+  // Answered once the new process has started.
+  Restart,
+  Status,
+}
+
+pub struct GameJob {
+  pub request: GameRequest,
+  pub respond: oneshot::Sender<Result<Reply, RequestError>>,
+}
+
+// What a process's task reports.
+enum Output {
+  Line(OutputStream, String),
+  Diagnostic(Diagnostic),
+  Built(bool),
+  Exited { code: Option<i32>, stopped: bool },
+}
+
+// The build or run in progress, or else the last one.
+struct Current {
+  state: GameProcess,
+  launch: Launch,
+  // Sending (or dropping) this kills the process.
+  kill: Option<oneshot::Sender<()>>,
+  // Waiting for the process to exit.
+  stops: Vec<oneshot::Sender<Result<Reply, RequestError>>>,
+  restarts: Vec<oneshot::Sender<Result<Reply, RequestError>>>,
+}
+
+struct Supervisor {
+  game: Option<GameCrate>,
+  events: broadcast::Sender<Event>,
+  output: mpsc::Sender<(u64, Output)>,
+  current: Option<Current>,
+  next_process: u64,
+}
+
+// Works through game requests until the server shuts down, sending every game event to `events`. Whatever is still
+// running then is killed.
+pub async fn supervise(
+  game: Option<GameCrate>,
+  mut jobs: mpsc::UnboundedReceiver<GameJob>,
+  events: broadcast::Sender<Event>,
+  mut shutdown: broadcast::Receiver<()>,
+) {
+  let (output, mut output_rx) = mpsc::channel(OUTPUT_LEN);
+  let mut supervisor = Supervisor { game, events, output, current: None, next_process: 1 };
+  loop {
+    tokio::select! {
+      job = jobs.recv() => match job {
+        Some(GameJob { request, respond }) => supervisor.handle(request, respond),
+        None => break,
+      },
+      Some((process, output)) = output_rx.recv() => supervisor.output(process, output),
+      _ = shutdown.recv() => break,
+    }
+  }
+  if supervisor.running().is_some() {
+    println!("Stopping the game.");
+  }
+}
+
+impl Supervisor {
+  fn handle(&mut self, request: GameRequest, respond: oneshot::Sender<Result<Reply, RequestError>>) {
+    match request {
+      GameRequest::Start(launch) => {
+        let _ = respond.send(self.start(launch));
+      }
+      GameRequest::Stop => match self.running() {
+        Some(current) => {
+          current.stops.push(respond);
+          if let Some(kill) = current.kill.take() {
+            let _ = kill.send(());
+          }
+        }
+        None => {
+          let _ = respond.send(Ok(self.status()));
+        }
+      },
+      GameRequest::Restart => {
+        if let Some(current) = self.running() {
+          current.restarts.push(respond);
+          if let Some(kill) = current.kill.take() {
+            let _ = kill.send(());
+          }
+          return;
+        }
+        let started = match self.current.as_ref().map(|current| current.launch.clone()) {
+          Some(launch) => self.start(launch),
+          None => Err(RequestError::new(ErrorCode::ProjectError, "nothing to restart; build or run the game first")),
+        };
+        let _ = respond.send(started);
+      }
+      GameRequest::Status => {
+        let _ = respond.send(Ok(self.status()));
+      }
+    }
+  }
+
+  fn start(&mut self, launch: Launch) -> Result<Reply, RequestError> {
+    if let Some(current) = self.running() {
+      let message = format!("{} is still running; stop or restart it", current.state.command);
+      return Err(RequestError::new(ErrorCode::GameRunning, &message));
+    }
+    let game = self.game.as_ref().ok_or_else(|| {
+      RequestError::new(ErrorCode::Unsupported, "the project has no game; add a [game] section to theseus.toml")
+    })?;
+    let args = cargo_args(game, &launch);
+    let command = format!("cargo {}", args.join(" "));
+    let child = Command::new("cargo")
+      .arg(&args[0])
+      .arg("--message-format=json")
+      .args(&args[1..])
+      .current_dir(&game.path)
+      .stdin(Stdio::null())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped())
+      .kill_on_drop(true)
+      .spawn()
+      .map_err(|err| RequestError::new(ErrorCode::ProjectError, &format!("couldn't start {}: {}", command, err)))?;
+
+    let process = self.next_process;
+    self.next_process += 1;
+    let (kill, killed) = oneshot::channel();
+    tokio::spawn(watch_process(process, child, killed, self.output.clone()));
+    println!("Game: started {} (process {}).", command, process);
+    self.tell(Event::GameStarted { process, command: command.clone() });
+    let state = GameProcess { process, command, running: true, built: None, exit_code: None, diagnostics: Vec::new() };
+    self.current = Some(Current { state, launch, kill: Some(kill), stops: Vec::new(), restarts: Vec::new() });
+    Ok(self.status())
+  }
+
+  fn output(&mut self, process: u64, output: Output) {
+    // Output from a process that's been restarted since still goes to the clients, but the state is the new one's.
+    let current = self.current.as_mut().filter(|current| current.state.process == process);
+    let event = match output {
+      Output::Line(stream, line) => Event::GameOutput { process, stream, line },
+      Output::Diagnostic(diagnostic) => {
+        if let Some(current) = current {
+          current.state.diagnostics.push(diagnostic.clone());
+        }
+        Event::GameDiagnostic { process, diagnostic }
+      }
+      Output::Built(success) => {
+        if let Some(current) = current {
+          current.state.built = Some(success);
+        }
+        Event::GameBuilt { process, success }
+      }
+      Output::Exited { code, stopped } => {
+        let (stops, restarts) = match current {
+          Some(current) => {
+            current.state.running = false;
+            current.state.exit_code = code;
+            current.kill = None;
+            (current.stops.split_off(0), current.restarts.split_off(0))
+          }
+          None => (Vec::new(), Vec::new()),
+        };
+        match code {
+          Some(code) => println!("Game: process {} exited with {}.", process, code),
+          None => println!("Game: process {} was killed.", process),
+        }
+        self.tell(Event::GameExited { process, code, stopped });
+        for stop in stops {
+          let _ = stop.send(Ok(self.status()));
+        }
+        if !restarts.is_empty() {
+          let launch = self.current.as_ref().expect("restarted process is current").launch.clone();
+          let started = self.start(launch);
+          for restart in restarts {
+            let _ = restart.send(started.clone());
+          }
+        }
+        return;
+      }
+    };
+    self.tell(event);
+  }
+
+  fn running(&mut self) -> Option<&mut Current> {
+    self.current.as_mut().filter(|current| current.state.running)
+  }
+
+  fn status(&self) -> Reply {
+    Reply::Game { process: self.current.as_ref().map(|current| current.state.clone()) }
+  }
+
+  // Nobody may be watching.
+  fn tell(&self, event: Event) {
+    let _ = self.events.send(event);
+  }
+}
+
+fn cargo_args(game: &GameCrate, launch: &Launch) -> Vec<String> {
+  let (subcommand, release, game_args) = match launch {
+    Launch::Build { release } => ("build", *release, None),
+    Launch::Run { release, args } => ("run", *release, Some(args)),
+  };
+  let mut args = vec![String::from(subcommand)];
+  if release {
+    args.push(String::from("--release"));
+  }
+  if let Some(package) = &game.package {
+    args.push(String::from("--package"));
+    args.push(package.clone());
+  }
+  args.extend(game.cargo_args.iter().cloned());
+  if let Some(game_args) = game_args.filter(|game_args| !game_args.is_empty()) {
+    args.push(String::from("--"));
+    args.extend(game_args.iter().cloned());
+  }
+  args
+}
+
+// Reads the process's output until it exits or is killed, then reports how it exited.
+async fn watch_process(
+  process: u64,
+  mut child: Child,
+  killed: oneshot::Receiver<()>,
+  mut output: mpsc::Sender<(u64, Output)>,
+) {
+  let stdout = child.stdout.take().map(|stdout| read_output(process, OutputStream::Stdout, stdout, output.clone()));
+  let stderr = child.stderr.take().map(|stderr| read_output(process, OutputStream::Stderr, stderr, output.clone()));
+  let (stdout, stderr) = (stdout.map(tokio::spawn), stderr.map(tokio::spawn));
+  let (status, stopped) = tokio::select! {
+    status = &mut child => (status, false),
+    _ = killed => {
+      kill_tree(&mut child).await;
+      ((&mut child).await, true)
+    }
+  };
+  // Whatever the process wrote before exiting comes before the exit.
+  for reader in stdout.into_iter().chain(stderr) {
+    let _ = time::timeout(OUTPUT_DRAIN_TIMEOUT, reader).await;
+  }
+  let code = status.ok().and_then(|status| status.code());
+  let _ = output.send((process, Output::Exited { code, stopped })).await;
+}
+
+// On Unix, cargo run execs the game in its own place, so killing the process kills the game.
+#[cfg(not(windows))]
+async fn kill_tree(child: &mut Child) {
+  let _ = child.kill();
+}
+
+// On Windows, cargo run starts the game as a child process of its own, which has to go too.
+#[cfg(windows)]
+async fn kill_tree(child: &mut Child) {
+  let pid = child.id().to_string();
+  let mut taskkill = Command::new("taskkill");
+  taskkill.args(&["/T", "/F", "/PID", &pid]).stdout(Stdio::null()).stderr(Stdio::null());
+  if !taskkill.status().await.is_ok_and(|status| status.success()) {
+    let _ = child.kill();
+  }
+}
+
+async fn read_output(
+  process: u64,
+  stream: OutputStream,
+  reader: impl AsyncRead + Unpin,
+  mut output: mpsc::Sender<(u64, Output)>,
+) {
+  let mut reader = BufReader::new(reader);
+  let mut bytes = Vec::new();
+  // Cargo's messages end with build-finished. Anything after that is the game's, even if it looks like one.
+  let mut building = stream == OutputStream::Stdout;
+  loop {
+    bytes.clear();
+    match reader.read_until(b'\n', &mut bytes).await {
+      Ok(0) | Err(_) => return,
+      Ok(_) => {}
+    }
+    // A game can write anything; it's passed on as text regardless.
+    let line = String::from_utf8_lossy(&bytes).trim_end_matches(&['\r', '\n'][..]).to_string();
+    let cargo = if building { cargo_message(&line) } else { None };
+    let message = match cargo {
+      Some(Some(message)) => message,
+      Some(None) => continue,
+      None => Output::Line(stream, line),
+    };
+    if let Output::Built(_) = message {
+      building = false;
+    }
+    if output.send((process, message)).await.is_err() {
+      return;
+    }
+  }
+}
+
+// Cargo's JSON Messages
+// ---------------------
+//
+// With --message-format=json, cargo writes one JSON object per line, each with a "reason". Only compiler messages and
+// the end of the build matter here. The game's own output is on the same stdout once it runs, so nothing after the end
+// of the build is read as cargo's.
+//
+
+#[derive(Deserialize)]
+struct CargoMessage {
+  reason: String,
+  message: Option<CompilerMessage>,
+  success: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct CompilerMessage {
+  message: String,
+  code: Option<CompilerCode>,
+  level: String,
+  spans: Vec<Span>,
+  rendered: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CompilerCode {
+  code: String,
+}
+
+#[derive(Deserialize)]
+struct Span {
+  file_name: String,
+  line_start: u32,
+  column_start: u32,
+  is_primary: bool,
+}
+
+// What a line from cargo means: None if it isn't from cargo at all, Some(None) if it's something to skip.
+fn cargo_message(line: &str) -> Option<Option<Output>> {
+  if !line.starts_with('{') {
+    return None;
+  }
+  let message: CargoMessage = serde_json::from_str(line).ok()?;
+  match (message.reason.as_str(), message.message, message.success) {
+    ("compiler-message", Some(message), _) => Some(diagnostic(message).map(Output::Diagnostic)),
+    ("build-finished", _, Some(success)) => Some(Some(Output::Built(success))),
+    _ => Some(None),
+  }
+}
+
+// Skips the compiler's closing remarks ("aborting due to 2 previous errors", "3 warnings emitted", "For more
+// information about this error, ..."), which only repeat what the diagnostics before them said.
+fn diagnostic(message: CompilerMessage) -> Option<Diagnostic> {
+  let summary = message.message.starts_with("aborting due to") || message.message.ends_with("emitted");
+  if message.spans.is_empty() && (summary || message.level == "failure-note") {
+    return None;
+  }
+  let primary = message.spans.iter().find(|span| span.is_primary);
+  Some(Diagnostic {
+    level: message.level,
+    message: message.message,
+    code: message.code.map(|code| code.code),
+    file: primary.map(|span| span.file_name.clone()),
+    line: primary.map(|span| span.line_start),
+    column: primary.map(|span| span.column_start),
+    rendered: message.rendered,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+  use tempfile::TempDir;
+  use super::*;
+
+  // Recorded from `cargo build --message-format=json` on a crate with a type error, trimmed of fields nothing reads.
+  const ERROR_LINE: &str = concat!(
+    r#"{"reason":"compiler-message","package_id":"path+file:///tmp/g#0.1.0","message":{"rendered":"error[E0308]: "#,
+    r#"mismatched types\n --> src/main.rs:3:16\n","$message_type":"diagnostic","children":[],"level":"error","#,
+    r#""message":"mismatched types","spans":[{"byte_end":49,"byte_start":45,"column_end":20,"column_start":16,"#,
+    r#""file_name":"src/main.rs","is_primary":true,"label":"expected `u32`, found `&str`","line_end":3,"#,
+    r#""line_start":3},"#,
+    r#"{"byte_end":42,"byte_start":39,"column_end":13,"column_start":10,"file_name":"src/main.rs","is_primary":false,"#,
+    r#""label":"expected due to this","line_end":3,"line_start":3}],"code":{"code":"E0308","explanation":"..."}}}"#,
+  );
+  const ABORTING_LINE: &str = concat!(
+    r#"{"reason":"compiler-message","package_id":"path+file:///tmp/g#0.1.0","message":{"rendered":"error: aborting "#,
+    r#"due to 1 previous error\n\n","$message_type":"diagnostic","children":[],"level":"error","#,
+    r#""message":"aborting due to 1 previous error","spans":[],"code":null}}"#,
+  );
+  const EXPLAIN_LINE: &str = concat!(
+    r#"{"reason":"compiler-message","package_id":"path+file:///tmp/g#0.1.0","message":{"rendered":"For more "#,
+    r#"information about this error, try `rustc --explain E0308`.\n","$message_type":"diagnostic","children":[],"#,
+    r#""level":"failure-note","message":"For more information about this error, try `rustc --explain E0308`.","#,
+    r#""spans":[],"code":null}}"#,
+  );
+  const ARTIFACT_LINE: &str = r#"{"reason":"compiler-artifact","package_id":"path+file:///tmp/g#0.1.0","fresh":false}"#;
+  const FAILED_LINE: &str = r#"{"reason":"build-finished","success":false}"#;
+
+  // A game that says something that looks like cargo's JSON, then exits with the code it's given or runs until it's
+  // stopped.
+  const GAME_MAIN: &str = r#"
+fn main() {
+  println!("{{\"reason\": \"build-finished\", \"success\": false}}");
+  match std::env::args().nth(1) {
+    Some(code) => std::process::exit(code.parse().unwrap()),
+    None => loop {
+      std::thread::sleep(std::time::Duration::from_millis(50));
+    },
+  }
+}
+"#;
+
+  // Generous, since the first run builds the game.
+  const EVENT_TIMEOUT: Duration = Duration::from_secs(120);
+
+  fn diagnostic_of(line: &str) -> Option<Diagnostic> {
+    match cargo_message(line) {
+      Some(Some(Output::Diagnostic(diagnostic))) => Some(diagnostic),
+      Some(None) => None,
+      _ => panic!("not a compiler message: {}", line),
+    }
+  }
+
+  #[test]
+  fn compiler_messages_become_diagnostics() {
+    let diagnostic = diagnostic_of(ERROR_LINE).unwrap();
+    assert_eq!(diagnostic.level, "error");
+    assert_eq!(diagnostic.message, "mismatched types");
+    assert_eq!(diagnostic.code.as_deref(), Some("E0308"));
+    // The primary span, not the label pointing at the type.
+    assert_eq!(diagnostic.file.as_deref(), Some("src/main.rs"));
+    assert_eq!((diagnostic.line, diagnostic.column), (Some(3), Some(16)));
+    assert!(diagnostic.rendered.unwrap().starts_with("error[E0308]: mismatched types"));
+  }
+
+  #[test]
+  fn closing_remarks_are_skipped() {
+    assert!(diagnostic_of(ABORTING_LINE).is_none());
+    assert!(diagnostic_of(EXPLAIN_LINE).is_none());
+    let warnings = ABORTING_LINE.replace("aborting due to 1 previous error", "2 warnings emitted");
+    assert!(diagnostic_of(&warnings).is_none());
+  }
+
+  #[test]
+  fn other_cargo_messages_are_read() {
+    assert!(matches!(cargo_message(ARTIFACT_LINE), Some(None)));
+    assert!(matches!(cargo_message(FAILED_LINE), Some(Some(Output::Built(false)))));
+    let finished = FAILED_LINE.replace("false", "true");
+    assert!(matches!(cargo_message(&finished), Some(Some(Output::Built(true)))));
+    // Not cargo's.
+    assert!(cargo_message("   Compiling g v0.1.0").is_none());
+    assert!(cargo_message("{ not json").is_none());
+  }
+
+  // The supervisor running on a tiny game crate of its own.
+  struct Harness {
+    jobs: mpsc::UnboundedSender<GameJob>,
+    events: broadcast::Receiver<Event>,
+    shutdown: broadcast::Sender<()>,
+    _dir: TempDir,
+  }
+
+  impl Harness {
+    fn start() -> Harness {
+      let dir = tempfile::tempdir().unwrap();
+      let manifest = "[package]\nname = \"game\"\nversion = \"0.1.0\"\nedition = \"2018\"\n\n[workspace]\n";
+      fs::create_dir_all(dir.path().join("src")).unwrap();
+      fs::write(dir.path().join("Cargo.toml"), manifest).unwrap();
+      fs::write(dir.path().join("src/main.rs"), GAME_MAIN).unwrap();
+      let cargo_args = vec![String::from("--offline")];
+      let game = GameCrate { path: dir.path().to_path_buf(), package: None, cargo_args };
+      let (jobs, jobs_rx) = mpsc::unbounded_channel();
+      let (events_tx, events) = broadcast::channel(OUTPUT_LEN);
+      let (shutdown, shutdown_rx) = broadcast::channel(1);
+      tokio::spawn(supervise(Some(game), jobs_rx, events_tx, shutdown_rx));
+      Harness { jobs, events, shutdown, _dir: dir }
+    }
+
+    async fn ask(&self, request: GameRequest) -> Result<Reply, RequestError> {
+      let (respond, response) = oneshot::channel();
+      self.jobs.send(GameJob { request, respond }).ok().unwrap();
+      time::timeout(EVENT_TIMEOUT, response).await.unwrap().unwrap()
+    }
+
+    // Skips events until one `wanted` picks out.
+    async fn wait_for<T>(&mut self, wanted: impl Fn(Event) -> Option<T>) -> T {
+      loop {
+        let event = time::timeout(EVENT_TIMEOUT, self.events.recv()).await.unwrap().unwrap();
+        if let Some(found) = wanted(event) {
+          return found;
+        }
+      }
+    }
+
+    async fn status(&self) -> GameProcess {
+      match self.ask(GameRequest::Status).await {
+        Ok(Reply::Game { process: Some(process) }) => process,
+        other => panic!("{:?}", other),
+      }
+    }
+  }
+
+  fn run(args: &[&str]) -> GameRequest {
+    GameRequest::Start(Launch::Run { release: false, args: args.iter().map(|arg| String::from(*arg)).collect() })
+  }
+
+  fn exited(event: Event) -> Option<(u64, Option<i32>, bool)> {
+    match event {
+      Event::GameExited { process, code, stopped } => Some((process, code, stopped)),
+      _ => None,
+    }
+  }
+
+  #[tokio::test]
+  async fn exits_are_reported_with_the_game_output() {
+    let mut harness = Harness::start();
+    harness.ask(run(&["3"])).await.unwrap();
+    let built = harness.wait_for(|event| match event {
+      Event::GameBuilt { success, .. } => Some(success),
+      _ => None,
+    });
+    assert!(built.await);
+    // The game's line looks like cargo's, but comes after the build.
+    let line = harness.wait_for(|event| match event {
+      Event::GameOutput { stream: OutputStream::Stdout, line, .. } => Some(line),
+      _ => None,
+    });
+    assert!(line.await.contains("build-finished"));
+    assert_eq!(harness.wait_for(exited).await, (1, Some(3), false));
+
+    let status = harness.status().await;
+    assert!(!status.running);
+    assert_eq!((status.built, status.exit_code), (Some(true), Some(3)));
+    let _ = harness.shutdown.send(());
+  }
+
+  #[tokio::test]
+  async fn games_can_be_stopped_and_restarted() {
+    let mut harness = Harness::start();
+    harness.ask(run(&[])).await.unwrap();
+    // Running, since the game itself has written something.
+    harness.wait_for(|event| matches!(event, Event::GameOutput { stream: OutputStream::Stdout, .. }).then(|| ())).await;
+    match harness.ask(run(&[])).await {
+      Err(error) => assert_eq!(error.code, ErrorCode::GameRunning),
+      other => panic!("{:?}", other),
+    }
+
+    // A restart is answered once the new process has started.
+    match harness.ask(GameRequest::Restart).await {
+      Ok(Reply::Game { process: Some(process) }) => assert_eq!((process.process, process.running), (2, true)),
+      other => panic!("{:?}", other),
+    }
+    assert_eq!(harness.wait_for(exited).await, (1, None, true));
+
+    // A stop is answered once the process has exited.
+    match harness.ask(GameRequest::Stop).await {
+      Ok(Reply::Game { process: Some(process) }) => assert_eq!((process.process, process.running), (2, false)),
+      other => panic!("{:?}", other),
+    }
+    assert_eq!(harness.wait_for(exited).await, (2, None, true));
+    let _ = harness.shutdown.send(());
+  }
+}
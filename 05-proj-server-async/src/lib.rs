@@ -0,0 +1,6 @@
+// Theseus Async Project Server
+// ----------------------------
+//
+// The pieces of the async project server that clients can reuse: the wire protocol.
+//
+pub mod protocol;
@@ -1,18 +1,196 @@
-use tokio::net::{TcpListener, TcpStream};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process;
+use std::sync::mpsc;
+use std::time::Duration;
+use project_server::importer::ImporterRegistry;
+use project_server::lock::ProjectLock;
+use project_server::project::Project;
+use project_server::watcher::AssetWatcher;
+use structopt::StructOpt;
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, mpsc as async_mpsc};
+use tokio::time;
+use crate::connection::Server;
+use crate::game::GameCrate;
+use crate::worker::Job;
 
+mod connection;
+mod game;
+mod presence;
+mod stop;
+mod worker;
+
+// Constants
+// ---------
+//
+// How long the watcher waits for a file to settle before reporting a change.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(200);
+// How many changes a subscriber can fall behind before its subscription expires.
+const CHANGE_BUFFER_LEN: usize = 1024;
+// How many presence events a client with the locks capability can fall behind before it misses some.
+const PRESENCE_BUFFER_LEN: usize = 256;
+// How many game events a client watching the game can fall behind before it misses some.
+const GAME_BUFFER_LEN: usize = 1024;
+// How long to wait before accepting again after a failed accept, e.g. when out of file descriptors.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+// How long the server waits for clients to be told it's shutting down.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+const EXIT_ERROR: i32 = 1;
+
+// Command Line Interface
+// ----------------------
+//
+#[derive(Debug, StructOpt)]
+struct Cli {
+  // The path of the project folder to open.
+  #[structopt(parse(from_os_str))]
+  project_path: PathBuf,
+  // Where to listen for clients. By default any free port on localhost; clients find it in the project's lock file.
+  #[structopt(long, default_value = "127.0.0.1:0")]
+  address: SocketAddr,
+  // Instead of serving the project, stop the server running on it (see stop.rs).
+  #[structopt(long)]
+  stop: bool,
+}
+
+// Main
+// ----
+//
+// The project lives on a worker thread from the blocking pool (see worker.rs); the runtime's own threads only accept
+// clients and move messages (see connection.rs). Ctrl+C stops accepting, has the worker save the project, then tells
+// every client the server is going away.
+//
 #[tokio::main]
 pub async fn main() {
-  let mut socket_listener = TcpListener::bind("127.0.0.1:4589").await.unwrap();
+  let args = Cli::from_args();
+  if args.stop {
+    if let Err(err) = stop::stop(&args.project_path).await {
+      fail("Error stopping server", &err);
+    }
+    return;
+  }
+  let project = match Project::open(&args.project_path, ImporterRegistry::default()) {
+    Ok(project) => project,
+    Err(err) => fail("Error opening project", &err),
+  };
+  println!("Project: {} (engine version {})", project.manifest.project.name, project.manifest.project.engine_version);
+  let game = GameCrate::from_manifest(&project.manifest, &project.path);
+  if let Some(game) = &game {
+    println!("Game: {:?}", game.path);
+  }
+  let mut lock = match ProjectLock::acquire(&project.library_path, "serve") {
+    Ok(lock) => lock,
+    Err(err) => fail("Error opening project", &err),
+  };
+  let mut listener = match TcpListener::bind(args.address).await {
+    Ok(listener) => listener,
+    Err(err) => fail(&format!("Error listening on {}", args.address), &err),
+  };
+  let address = listener.local_addr().unwrap_or(args.address);
+  if let Err(err) = lock.set_address(address) {
+    println!("Error: {}", err);
+  }
+
+  // Jobs for the project worker. The watcher starts before the scan, so nothing changed during it goes unnoticed.
+  let (jobs, jobs_rx) = mpsc::channel();
+  let watcher_jobs = jobs.clone();
+  let watcher = match AssetWatcher::start(&project.asset_roots, WATCH_DEBOUNCE, move |event| {
+    let _ = watcher_jobs.send(Job::Asset(event));
+  }) {
+    Ok(watcher) => watcher,
+    Err(err) => fail("Error starting the Assets watcher", &err),
+  };
+  let (changes, _) = broadcast::channel(CHANGE_BUFFER_LEN);
+  let (presence, _) = broadcast::channel(PRESENCE_BUFFER_LEN);
+  let worker_presence = presence.clone();
+  println!("Project path: {:?}", project.path);
+  let worker = tokio::task::spawn_blocking(move || worker::run(project, jobs_rx, changes, worker_presence));
 
+  // Ctrl+C, the termination signal and the shutdown request all stop the accept loop.
+  let (stop, mut stop_rx) = async_mpsc::unbounded_channel();
+  let signal_stop = stop.clone();
+  tokio::spawn(async move {
+    stop_signal().await;
+    println!("Received Ctrl+C or termination signal.");
+    let _ = signal_stop.send(());
+  });
+  let (shutdown, _) = broadcast::channel(1);
+  let (done, mut done_rx) = async_mpsc::channel::<()>(1);
+
+  // Builds and runs the game, until the server shuts down.
+  let (game_jobs, game_jobs_rx) = async_mpsc::unbounded_channel();
+  let (game_events, _) = broadcast::channel(GAME_BUFFER_LEN);
+  tokio::spawn(game::supervise(game, game_jobs_rx, game_events.clone(), shutdown.subscribe()));
+
+  println!("Theseus project server listening on {}.\nCtrl+C to stop the server.\n", address);
+  let mut next_peer = 1;
   loop {
-    let (socket, _conn_info) = socket_listener.accept().await.unwrap();
+    tokio::select! {
+      accepted = listener.accept() => match accepted {
+        Ok((socket, peer)) => {
+          let server = Server {
+            peer: next_peer,
+            jobs: jobs.clone(),
+            stop: stop.clone(),
+            shutdown: shutdown.subscribe(),
+            presence: presence.subscribe(),
+            game: game_jobs.clone(),
+            game_events: game_events.clone(),
+            done: done.clone(),
+          };
+          next_peer += 1;
+          tokio::spawn(connection::serve(socket, peer, server));
+        }
+        // One failed accept doesn't take the server down with it.
+        Err(err) => {
+          println!("Error accepting a connection: {}", err);
+          time::delay_for(ACCEPT_RETRY_DELAY).await;
+        }
+      },
+      _ = stop_rx.recv() => break,
+    }
+  }
+
+  println!("Shutting down...");
+  drop(listener);
+  drop(watcher);
+  let _ = jobs.send(Job::Shutdown);
+  if let Err(err) = worker.await {
+    println!("Error: the project worker stopped unexpectedly: {}", err);
+  }
+  // Clients are told only once the project is saved, so whatever they ask next time sees everything up to now.
+  let _ = shutdown.send(());
+  drop(done);
+  // Every connection holds a sender until it's closed, so this returns once they all are.
+  let _ = time::timeout(SHUTDOWN_TIMEOUT, done_rx.recv()).await;
+  drop(lock);
+  println!("Server stopped.");
+}
 
-    tokio::spawn(async move {
-      process(socket).await;
-    });
+#[cfg(unix)]
+async fn stop_signal() {
+  use tokio::signal::unix::{signal, SignalKind};
+  match signal(SignalKind::terminate()) {
+    Ok(mut terminate) => {
+      tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+      }
+    }
+    Err(_) => {
+      let _ = tokio::signal::ctrl_c().await;
+    }
   }
 }
 
-async fn process(_socket: TcpStream) {
-  println!("hi");
+#[cfg(not(unix))]
+async fn stop_signal() {
+  let _ = tokio::signal::ctrl_c().await;
+}
+
+fn fail(context: &str, err: &dyn std::fmt::Display) -> ! {
+  println!("{}: {}", context, err);
+  process::exit(EXIT_ERROR);
 }
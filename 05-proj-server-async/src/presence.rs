@@ -0,0 +1,309 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use proj_server_async::protocol::Peer;
+use uuid::Uuid;
+
+// Presence
+// --------
+//
+// Who's connected, which assets each client has open, and who holds which locks. Assets are kept by GUID, so opens
+// and locks follow an asset when it's moved; the path beside each is the last one seen, for telling clients about an
+// asset that's since been deleted. Leases run on the worker's clock: it wakes at next_expiry() to let them go.
+//
+
+// The longest lease a client can ask for. A client that needs longer renews it, so a lock can't outlive a crashed
+// editor by more than this.
+pub const MAX_LEASE: Duration = Duration::from_secs(10 * 60);
+
+// What changed, for the worker to tell the clients.
+pub enum Change {
+  Opened { peer: Peer, path: PathBuf },
+  Closed { peer: Peer, path: PathBuf },
+  Locked { lock: Lock },
+  Unlocked { holder: Peer, path: PathBuf },
+}
+
+#[derive(Clone)]
+pub struct Lock {
+  pub guid: Uuid,
+  pub path: PathBuf,
+  pub holder: Peer,
+  pub expires: Instant,
+}
+
+struct Lease {
+  holder: u64,
+  path: PathBuf,
+  expires: Instant,
+}
+
+#[derive(Default)]
+pub struct Presence {
+  names: BTreeMap<u64, String>,
+  // The assets each client has open.
+  open: BTreeMap<u64, BTreeMap<Uuid, PathBuf>>,
+  locks: HashMap<Uuid, Lease>,
+}
+
+impl Presence {
+  pub fn join(&mut self, peer: u64, name: &str) {
+    self.names.insert(peer, String::from(name));
+  }
+
+  // Closes everything the client had open and lets go of its locks.
+  pub fn leave(&mut self, peer: u64) -> Vec<Change> {
+    let who = self.peer(peer);
+    let mut changes: Vec<Change> = self
+      .open
+      .remove(&peer)
+      .unwrap_or_default()
+      .into_values()
+      .map(|path| Change::Closed { peer: who.clone(), path })
+      .collect();
+    let held: Vec<Uuid> = self.locks.iter().filter(|(_, lease)| lease.holder == peer).map(|(guid, _)| *guid).collect();
+    for guid in held {
+      let lease = self.locks.remove(&guid).expect("held lock exists");
+      changes.push(Change::Unlocked { holder: who.clone(), path: lease.path });
+    }
+    self.names.remove(&peer);
+    changes
+  }
+
+  pub fn peer(&self, peer: u64) -> Peer {
+    named(&self.names, peer)
+  }
+
+  pub fn peers(&self) -> Vec<(Peer, Vec<&Path>)> {
+    let open = |peer| self.open.get(peer).map(|open| open.values().map(|path| path.as_path()).collect());
+    self.names.keys().map(|peer| (self.peer(*peer), open(peer).unwrap_or_default())).collect()
+  }
+
+  pub fn open(&mut self, peer: u64, guid: Uuid, path: &Path) -> Option<Change> {
+    let open = self.open.entry(peer).or_default();
+    if open.insert(guid, path.to_path_buf()).is_some() {
+      return None;
+    }
+    Some(Change::Opened { peer: self.peer(peer), path: path.to_path_buf() })
+  }
+
+  pub fn close(&mut self, peer: u64, guid: Uuid) -> Option<Change> {
+    let path = self.open.get_mut(&peer)?.remove(&guid)?;
+    Some(Change::Closed { peer: self.peer(peer), path })
+  }
+
+  // Locks an asset for `peer`, or renews its lease. Fails with the lock if someone else holds it.
+  pub fn lock(&mut self, peer: u64, guid: Uuid, path: &Path, lease: Duration) -> Result<Change, Lock> {
+    if let Some(lock) = self.lock_on(guid) {
+      if lock.holder.id != peer {
+        return Err(lock);
+      }
+    }
+    let expires = Instant::now() + lease.min(MAX_LEASE);
+    self.locks.insert(guid, Lease { holder: peer, path: path.to_path_buf(), expires });
+    Ok(Change::Locked { lock: self.lock_on(guid).expect("just locked") })
+  }
+
+  // Unlocks an asset `peer` holds. Fails with the lock if someone else holds it; unlocking an asset nobody holds does
+  // nothing.
+  pub fn unlock(&mut self, peer: u64, guid: Uuid) -> Result<Option<Change>, Lock> {
+    match self.lock_on(guid) {
+      Some(lock) if lock.holder.id != peer => Err(lock),
+      Some(lock) => {
+        self.locks.remove(&guid);
+        Ok(Some(Change::Unlocked { holder: lock.holder, path: lock.path }))
+      }
+      None => Ok(None),
+    }
+  }
+
+  pub fn lock_on(&self, guid: Uuid) -> Option<Lock> {
+    let lease = self.locks.get(&guid)?;
+    Some(Lock { guid, path: lease.path.clone(), holder: self.peer(lease.holder), expires: lease.expires })
+  }
+
+  pub fn locks(&self) -> Vec<Lock> {
+    let mut locks: Vec<Lock> = self.locks.keys().filter_map(|guid| self.lock_on(*guid)).collect();
+    locks.sort_by(|a, b| a.path.cmp(&b.path));
+    locks
+  }
+
+  // A lock someone other than `peer` holds on `path`, a folder it's in, or anything inside it.
+  pub fn locked_for(&self, peer: u64, path: &Path) -> Option<Lock> {
+    self
+      .locks()
+      .into_iter()
+      .find(|lock| lock.holder.id != peer && (path.starts_with(&lock.path) || lock.path.starts_with(path)))
+  }
+
+  pub fn next_expiry(&self) -> Option<Instant> {
+    self.locks.values().map(|lease| lease.expires).min()
+  }
+
+  // Lets go of the leases that ran out by `now`.
+  pub fn expire(&mut self, now: Instant) -> Vec<Change> {
+    let expired: Vec<Uuid> =
+      self.locks.iter().filter(|(_, lease)| lease.expires <= now).map(|(guid, _)| *guid).collect();
+    let mut changes = Vec::new();
+    for guid in expired {
+      let lease = self.locks.remove(&guid).expect("expired lock exists");
+      changes.push(Change::Unlocked { holder: self.peer(lease.holder), path: lease.path });
+    }
+    changes
+  }
+
+  // Brings the paths up to date after assets move, and closes and unlocks assets that no longer exist.
+  pub fn follow_assets<'a>(&mut self, path_for_guid: impl Fn(&Uuid) -> Option<&'a Path>) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let names = &self.names;
+    for (peer, open) in self.open.iter_mut() {
+      open.retain(|guid, path| match path_for_guid(guid) {
+        Some(current) => {
+          if current != path.as_path() {
+            *path = current.to_path_buf();
+          }
+          true
+        }
+        None => {
+          changes.push(Change::Closed { peer: named(names, *peer), path: path.clone() });
+          false
+        }
+      });
+    }
+    self.locks.retain(|guid, lease| match path_for_guid(guid) {
+      Some(current) => {
+        if current != lease.path.as_path() {
+          lease.path = current.to_path_buf();
+        }
+        true
+      }
+      None => {
+        changes.push(Change::Unlocked { holder: named(names, lease.holder), path: lease.path.clone() });
+        false
+      }
+    });
+    changes
+  }
+}
+
+fn named(names: &BTreeMap<u64, String>, peer: u64) -> Peer {
+  let name = names.get(&peer).cloned().unwrap_or_else(|| String::from("unknown"));
+  Peer { id: peer, name }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const EDITOR: u64 = 1;
+  const SCRIPT: u64 = 2;
+  const LEASE: Duration = Duration::from_secs(60);
+
+  fn presence() -> Presence {
+    let mut presence = Presence::default();
+    presence.join(EDITOR, "editor");
+    presence.join(SCRIPT, "script");
+    presence
+  }
+
+  fn guid(n: u128) -> Uuid {
+    Uuid::from_u128(n)
+  }
+
+  fn unlocked(changes: &[Change]) -> Vec<(u64, &Path)> {
+    changes
+      .iter()
+      .filter_map(|change| match change {
+        Change::Unlocked { holder, path } => Some((holder.id, path.as_path())),
+        _ => None,
+      })
+      .collect()
+  }
+
+  #[test]
+  fn leases_expire() {
+    let mut presence = presence();
+    let started = Instant::now();
+    assert!(presence.lock(EDITOR, guid(1), Path::new("textures/logo.png"), LEASE).is_ok());
+    presence.lock(EDITOR, guid(2), Path::new("prefabs/ball.ron"), 2 * LEASE).ok().unwrap();
+    let first = presence.next_expiry().unwrap();
+    assert!(first >= started + LEASE && first < started + 2 * LEASE);
+
+    assert!(presence.expire(started).is_empty());
+    let changes = presence.expire(first);
+    assert_eq!(unlocked(&changes), vec![(EDITOR, Path::new("textures/logo.png"))]);
+    assert!(presence.lock_on(guid(1)).is_none());
+    // Someone else can have it now.
+    assert!(presence.lock(SCRIPT, guid(1), Path::new("textures/logo.png"), LEASE).is_ok());
+    assert!(presence.next_expiry().unwrap() > first);
+  }
+
+  #[test]
+  fn holders_renew_their_leases() {
+    let mut presence = presence();
+    let path = Path::new("textures/logo.png");
+    presence.lock(EDITOR, guid(1), path, LEASE).ok().unwrap();
+    let expires = presence.lock_on(guid(1)).unwrap().expires;
+    presence.lock(EDITOR, guid(1), path, 2 * LEASE).ok().unwrap();
+    let renewed = presence.lock_on(guid(1)).unwrap();
+    assert!(renewed.expires > expires);
+    assert_eq!(renewed.holder.id, EDITOR);
+    assert!(presence.expire(expires).is_empty());
+
+    // Nobody else can renew or unlock it.
+    let held = presence.lock(SCRIPT, guid(1), path, LEASE).err().unwrap();
+    assert_eq!((held.holder.id, held.expires), (EDITOR, renewed.expires));
+    assert_eq!(presence.unlock(SCRIPT, guid(1)).err().unwrap().holder.id, EDITOR);
+    assert!(matches!(presence.unlock(EDITOR, guid(1)), Ok(Some(Change::Unlocked { .. }))));
+    assert!(matches!(presence.unlock(EDITOR, guid(1)), Ok(None)));
+  }
+
+  #[test]
+  fn leases_are_clamped_to_the_longest() {
+    let mut presence = presence();
+    let before = Instant::now();
+    presence.lock(EDITOR, guid(1), Path::new("textures/logo.png"), Duration::from_secs(24 * 60 * 60)).ok().unwrap();
+    let expires = presence.lock_on(guid(1)).unwrap().expires;
+    assert!(expires >= before + MAX_LEASE && expires <= Instant::now() + MAX_LEASE);
+  }
+
+  #[test]
+  fn locks_cover_folders_and_what_is_in_them() {
+    let mut presence = presence();
+    presence.lock(EDITOR, guid(1), Path::new("sprites/ui"), LEASE).ok().unwrap();
+    // Inside the locked folder, the folder itself and the folders it's in.
+    for path in ["sprites/ui/button.png", "sprites/ui", "sprites"] {
+      let lock = presence.locked_for(SCRIPT, Path::new(path)).unwrap();
+      assert_eq!(lock.path, Path::new("sprites/ui"), "{}", path);
+    }
+    // Beside it, even with the same prefix.
+    for path in ["sprites/ui.png", "sprites/uikit", "textures"] {
+      assert!(presence.locked_for(SCRIPT, Path::new(path)).is_none(), "{}", path);
+    }
+    // Not for the holder.
+    assert!(presence.locked_for(EDITOR, Path::new("sprites/ui/button.png")).is_none());
+  }
+
+  #[test]
+  fn leaving_lets_go_of_everything() {
+    let mut presence = presence();
+    presence.open(EDITOR, guid(1), Path::new("prefabs/ball.ron")).unwrap();
+    presence.lock(EDITOR, guid(1), Path::new("prefabs/ball.ron"), LEASE).ok().unwrap();
+    presence.lock(EDITOR, guid(2), Path::new("textures/logo.png"), LEASE).ok().unwrap();
+    presence.lock(SCRIPT, guid(3), Path::new("prefabs/level.ron"), LEASE).ok().unwrap();
+
+    let changes = presence.leave(EDITOR);
+    match &changes[0] {
+      Change::Closed { peer, path } => assert_eq!((peer.id, path.as_path()), (EDITOR, Path::new("prefabs/ball.ron"))),
+      _ => panic!("the asset wasn't closed first"),
+    }
+    let mut released = unlocked(&changes);
+    released.sort();
+    assert_eq!(released, vec![(EDITOR, Path::new("prefabs/ball.ron")), (EDITOR, Path::new("textures/logo.png"))]);
+
+    assert!(presence.lock_on(guid(1)).is_none() && presence.lock_on(guid(2)).is_none());
+    assert_eq!(presence.lock_on(guid(3)).unwrap().holder.id, SCRIPT);
+    let peers: Vec<u64> = presence.peers().into_iter().map(|(peer, _)| peer.id).collect();
+    assert_eq!(peers, vec![SCRIPT]);
+  }
+}
@@ -0,0 +1,562 @@
+use std::fmt;
+use std::io;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use uuid::Uuid;
+
+// Wire Protocol
+// -------------
+//
+// Editors, games and scripts all talk to the project server the same way. Every message is a frame: a 4 byte
+// big-endian length followed by that many bytes of JSON. Frames can hold anything JSON can, so nobody has to escape
+// newlines, and a reader always knows how much to wait for.
+//
+// A connection starts with a handshake. The client says hello with the protocol version it speaks and the optional
+// features it wants; the server answers with welcome and the features it has, or with rejected (and closes the
+// connection) when it doesn't speak that version:
+//
+//   -> {"type": "hello", "protocol_version": 1, "client": "editor", "capabilities": ["subscriptions"]}
+//   <- {"type": "welcome", "protocol_version": 1, "server": "theseus", "capabilities": ["subscriptions"]}
+//
+// After that the client sends requests, each with an id of its choosing, and the server answers every one with a
+// response or an error carrying the same id. Answers can come in any order. Events can arrive at any time:
+//
+//   -> {"type": "request", "id": 1, "method": "resolve_guid", "params": {"guid": "..."}}
+//   <- {"type": "response", "id": 1, "result": {"kind": "path", "path": "textures/logo.png"}}
+//   <- {"type": "error", "id": 2, "error": {"code": "asset_not_found", "message": "..."}}
+//   <- {"type": "event", "event": "asset_changed", "subscription": 1, "change": {...}}
+//
+// The version only changes when a message changes in a way an older peer would misread. New requests and events come
+// with a capability, so a peer can tell whether the other side has them without a version bump.
+//
+
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// Frames longer than this are refused rather than read, so a bad length can't make a peer allocate gigabytes.
+pub const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+// Capabilities: optional features, named in the handshake.
+// Subscribing to asset changes, with asset_changed and subscription_expired events.
+pub const SUBSCRIPTIONS: &str = "subscriptions";
+// Opening and locking assets, with the asset_locked error and the presence events.
+pub const LOCKS: &str = "locks";
+// Building and running the project's game, with the game requests and events.
+pub const GAME: &str = "game";
+
+// Messages
+// --------
+//
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+  // The first message on every connection.
+  Hello(Hello),
+  Request {
+    id: u64,
+    #[serde(flatten)]
+    request: Request,
+  },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+  // The answer to a hello the server accepts.
+  Welcome(Welcome),
+  // The answer to a hello the server doesn't accept, before it closes the connection.
+  Rejected { protocol_version: u32, reason: String },
+  Response { id: u64, result: Reply },
+  Error { id: u64, error: RequestError },
+  Event {
+    #[serde(flatten)]
+    event: Event,
+  },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hello {
+  pub protocol_version: u32,
+  // Who's connecting, for the server's log.
+  pub client: String,
+  // The optional features the client wants to use.
+  pub capabilities: Vec<String>,
+}
+
+impl Hello {
+  pub fn has_capability(&self, capability: &str) -> bool {
+    self.capabilities.iter().any(|name| name == capability)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Welcome {
+  pub protocol_version: u32,
+  pub server: String,
+  // The optional features the server has.
+  pub capabilities: Vec<String>,
+}
+
+impl Welcome {
+  pub fn has_capability(&self, capability: &str) -> bool {
+    self.capabilities.iter().any(|name| name == capability)
+  }
+}
+
+// Asset paths are relative to their asset root, with "" standing for the root itself.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum Request {
+  Ping,
+  // The assets directly inside a folder.
+  ListFolder { path: String },
+  GetAsset { path: String },
+  ResolveGuid { guid: Uuid },
+  // The assets matching a query such as "type:texture label:ui name:*menu*", by path, at most `limit` of them.
+  Search { query: String, limit: Option<usize> },
+  // Moves an asset, carrying its meta file along and rewriting the File("...") references to it. With dry_run, only
+  // lists the edits.
+  MoveAsset { from: String, to: String, dry_run: bool },
+  // Renames an asset within its folder, like move_asset.
+  RenameAsset { path: String, name: String, dry_run: bool },
+  // Deletes an asset (a folder with everything in it) and its meta file.
+  DeleteAsset { path: String },
+  // A PNG thumbnail of a texture, at most 128 pixels a side.
+  GetThumbnail { path: String },
+  // Previews of the sprite sheets in a RON file: the texture scaled up, with every sprite outlined and numbered.
+  GetSpritePreviews { path: String },
+  // The changes after the change numbered `since`, for clients that poll rather than subscribe. Only the cursor for
+  // None.
+  ChangesSince { since: Option<u64> },
+  // Sends an asset_changed event for every change from now on (or after the change numbered `since`) to an asset in
+  // `folder` with one of `types`. Needs the subscriptions capability.
+  Subscribe { folder: Option<String>, types: Vec<String>, since: Option<u64> },
+  Unsubscribe { subscription: u64 },
+  // Tells the other clients that this one has an asset open, or no longer has. These and the other presence requests
+  // need the locks capability. A client's open assets and locks are let go when it disconnects.
+  OpenAsset { path: String },
+  CloseAsset { path: String },
+  // Locks an asset (a folder with everything in it) for `lease_secs` seconds. While it's locked, other clients can't
+  // move or delete it, or move an asset it refers to, since that rewrites it. Locking it again renews the lease.
+  LockAsset { path: String, lease_secs: u64 },
+  UnlockAsset { path: String },
+  // Who's connected, what they have open and what's locked.
+  ListPresence,
+  // Builds the project's game with cargo build, or builds and runs it with cargo run, passing `args` on to the game.
+  // These and the other game requests need the game capability. One build or run goes at a time.
+  BuildGame { release: bool },
+  RunGame { release: bool, args: Vec<String> },
+  // Stops the build or run in progress.
+  StopGame,
+  // Stops the build or run in progress, if any, and starts the last one again.
+  RestartGame,
+  // The build or run in progress, or else the last one.
+  GameStatus,
+  // Sends the game events to this client from now on, or stops sending them.
+  WatchGame,
+  UnwatchGame,
+  // Saves the project and stops the server.
+  Shutdown,
+}
+
+impl Request {
+  // The capability the client needs to have negotiated to make the request, if any.
+  pub fn capability(&self) -> Option<&'static str> {
+    match self {
+      Request::Subscribe { .. } | Request::Unsubscribe { .. } => Some(SUBSCRIPTIONS),
+      Request::OpenAsset { .. }
+      | Request::CloseAsset { .. }
+      | Request::LockAsset { .. }
+      | Request::UnlockAsset { .. }
+      | Request::ListPresence => Some(LOCKS),
+      Request::BuildGame { .. }
+      | Request::RunGame { .. }
+      | Request::StopGame
+      | Request::RestartGame
+      | Request::GameStatus
+      | Request::WatchGame
+      | Request::UnwatchGame => Some(GAME),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Reply {
+  Pong,
+  Assets { assets: Vec<AssetInfo> },
+  Asset { asset: AssetInfo },
+  Path { path: String },
+  // The asset where it was moved to (or, for a dry run, where it still is) and the references rewritten to follow it.
+  Moved { asset: AssetInfo, edits: Vec<ReferenceEdit> },
+  Subscribed { subscription: u64, cursor: u64 },
+  Thumbnail { thumbnail: Thumbnail },
+  SpritePreviews { previews: Vec<SheetPreview> },
+  // `cursor` is the latest change recorded.
+  Changes { cursor: u64, changes: Vec<AssetChange> },
+  Locked { lock: AssetLock },
+  // `you` is the client that asked.
+  Presence { you: Peer, peers: Vec<PeerPresence>, locks: Vec<AssetLock> },
+  // None if nothing has been built or run yet.
+  Game { process: Option<GameProcess> },
+  // For requests with nothing to answer.
+  Done,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+  AssetChanged { subscription: u64, change: AssetChange },
+  // The subscription fell too far behind and was dropped; the client has to list the asset tree again.
+  SubscriptionExpired { subscription: u64 },
+  // Sent to every client before the server closes their connections.
+  ServerShutdown,
+  // Presence events, sent to every client with the locks capability.
+  AssetOpened { peer: Peer, path: String },
+  AssetClosed { peer: Peer, path: String },
+  // An asset was locked, or its lease renewed.
+  AssetLocked { lock: AssetLock },
+  // The holder unlocked the asset, disconnected, or let the lease run out.
+  AssetUnlocked { path: String, holder: Peer },
+  // Game events, sent to the clients watching the game. `process` numbers each build or run.
+  GameStarted { process: u64, command: String },
+  // A line the game or cargo wrote, without its line ending.
+  GameOutput { process: u64, stream: OutputStream, line: String },
+  // An error, warning or note from the compiler.
+  GameDiagnostic { process: u64, diagnostic: Diagnostic },
+  // Cargo finished building; for a run, the game starts next if the build succeeded.
+  GameBuilt { process: u64, success: bool },
+  // `code` is None when the process was killed or died from a signal; `stopped` says whether a client stopped it.
+  GameExited { process: u64, code: Option<i32>, stopped: bool },
+}
+
+// A connected client, numbered by the server, under the name from its hello.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Peer {
+  pub id: u64,
+  pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerPresence {
+  pub peer: Peer,
+  // The assets the client has open.
+  pub open: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AssetLock {
+  pub guid: Uuid,
+  pub path: String,
+  pub holder: Peer,
+  // How long the lease has left when the message is sent.
+  pub expires_in_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GameProcess {
+  pub process: u64,
+  // The cargo command line, e.g. "cargo run --release".
+  pub command: String,
+  pub running: bool,
+  // Whether cargo built the game, once it's done building.
+  pub built: Option<bool>,
+  pub exit_code: Option<i32>,
+  // The compiler's diagnostics so far, in the order it gave them.
+  pub diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputStream {
+  Stdout,
+  Stderr,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Diagnostic {
+  // "error", "warning", "note", ...
+  pub level: String,
+  pub message: String,
+  // The error code or lint, e.g. "E0308" or "unused_variables".
+  pub code: Option<String>,
+  // Where the compiler points, relative to the game's Cargo workspace. Line and column are 1-based.
+  pub file: Option<String>,
+  pub line: Option<u32>,
+  pub column: Option<u32>,
+  // The whole diagnostic as the compiler would print it.
+  pub rendered: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AssetInfo {
+  pub guid: Uuid,
+  pub path: String,
+  // The asset's importer ("folder", "texture", ...).
+  #[serde(rename = "type")]
+  pub asset_type: String,
+  pub is_folder: bool,
+  pub size: u64,
+  // Seconds since the Unix epoch.
+  pub modified: u64,
+  pub hash: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReferenceEdit {
+  // The file with the reference.
+  pub file: String,
+  // 1-based.
+  pub line: usize,
+  pub old_path: String,
+  pub new_path: String,
+}
+
+// Previews are PNGs in the server's cache, which stay valid until the asset they're drawn from changes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Thumbnail {
+  // The absolute path of the cached PNG.
+  pub file: String,
+  pub width: u32,
+  pub height: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SheetPreview {
+  // The absolute path of the cached PNG.
+  pub file: String,
+  pub width: u32,
+  pub height: u32,
+  // The sheet's texture.
+  pub texture: String,
+  // Preview pixels per texture pixel.
+  pub scale: u32,
+  // In texture pixels, in the order Amethyst numbers them from 0.
+  pub sprites: Vec<Sprite>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sprite {
+  pub x: u32,
+  pub y: u32,
+  pub width: u32,
+  pub height: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+  Added,
+  Modified,
+  Moved,
+  Removed,
+  Imported,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AssetChange {
+  pub seq: u64,
+  pub kind: ChangeKind,
+  pub guid: Uuid,
+  pub path: String,
+  // Where a moved asset was before.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub from: Option<String>,
+  #[serde(rename = "type")]
+  pub asset_type: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RequestError {
+  pub code: ErrorCode,
+  pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+  AssetNotFound,
+  AssetExists,
+  InvalidPath,
+  // A search query that doesn't parse.
+  InvalidQuery,
+  ProjectError,
+  // The asset couldn't be previewed: an image that doesn't decode, a RON file without sprite sheets, ...
+  PreviewFailed,
+  // `since` is older than the server remembers.
+  CursorExpired,
+  // Another client holds a lock on the asset. Clients without the locks capability get project_error instead.
+  AssetLocked,
+  // A build or run is already in progress; stop or restart it instead.
+  GameRunning,
+  // A request the server doesn't handle, such as one needing a capability that wasn't negotiated.
+  Unsupported,
+  Internal,
+}
+
+impl RequestError {
+  pub fn new(code: ErrorCode, message: &str) -> RequestError {
+    RequestError { code, message: String::from(message) }
+  }
+}
+
+impl fmt::Display for RequestError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.message)
+  }
+}
+
+impl std::error::Error for RequestError {}
+
+// Errors
+// ------
+//
+
+#[derive(Debug)]
+pub enum ProtocolError {
+  Io(io::Error),
+  // The peer closed the connection.
+  Closed,
+  // A frame longer than MAX_FRAME_LEN, by its length prefix.
+  FrameTooLarge(usize),
+  // A frame that isn't a message this side understands.
+  Malformed(serde_json::Error),
+  // The peer sent something other than what the handshake called for.
+  UnexpectedMessage(String),
+  // The server didn't accept the client's hello, or the client's hello asked for a version the server doesn't speak.
+  Rejected { protocol_version: u32, reason: String },
+}
+
+impl fmt::Display for ProtocolError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ProtocolError::Io(err) => write!(f, "{}", err),
+      ProtocolError::Closed => write!(f, "connection closed"),
+      ProtocolError::FrameTooLarge(len) => write!(f, "{} byte frame is over the {} byte limit", len, MAX_FRAME_LEN),
+      ProtocolError::Malformed(err) => write!(f, "malformed message: {}", err),
+      ProtocolError::UnexpectedMessage(message) => write!(f, "unexpected message: {}", message),
+      ProtocolError::Rejected { protocol_version, reason } => {
+        write!(f, "handshake rejected (protocol version {}): {}", protocol_version, reason)
+      }
+    }
+  }
+}
+
+impl std::error::Error for ProtocolError {}
+
+impl From<io::Error> for ProtocolError {
+  fn from(err: io::Error) -> ProtocolError {
+    ProtocolError::Io(err)
+  }
+}
+
+// Framing
+// -------
+//
+
+// The message as a frame, length prefix and all.
+pub fn encode<T: Serialize>(message: &T) -> Result<Vec<u8>, ProtocolError> {
+  let body = serde_json::to_vec(message).map_err(ProtocolError::Malformed)?;
+  if body.len() > MAX_FRAME_LEN {
+    return Err(ProtocolError::FrameTooLarge(body.len()));
+  }
+  let mut frame = Vec::with_capacity(4 + body.len());
+  frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+  frame.extend_from_slice(&body);
+  Ok(frame)
+}
+
+// The message in a frame's body, without its length prefix.
+pub fn decode<T: DeserializeOwned>(body: &[u8]) -> Result<T, ProtocolError> {
+  serde_json::from_slice(body).map_err(ProtocolError::Malformed)
+}
+
+pub async fn write_message<W, T>(writer: &mut W, message: &T) -> Result<(), ProtocolError>
+where
+  W: AsyncWrite + Unpin,
+  T: Serialize,
+{
+  writer.write_all(&encode(message)?).await?;
+  writer.flush().await?;
+  Ok(())
+}
+
+// The next message, or None if the peer closed the connection between messages.
+pub async fn read_message<R, T>(reader: &mut R) -> Result<Option<T>, ProtocolError>
+where
+  R: AsyncRead + Unpin,
+  T: DeserializeOwned,
+{
+  let mut prefix = [0; 4];
+  let mut filled = 0;
+  while filled < prefix.len() {
+    match reader.read(&mut prefix[filled..]).await? {
+      0 if filled == 0 => return Ok(None),
+      0 => return Err(ProtocolError::Closed),
+      read => filled += read,
+    }
+  }
+  let len = u32::from_be_bytes(prefix) as usize;
+  if len > MAX_FRAME_LEN {
+    return Err(ProtocolError::FrameTooLarge(len));
+  }
+  let mut body = vec![0; len];
+  reader.read_exact(&mut body).await.map_err(|err| match err.kind() {
+    io::ErrorKind::UnexpectedEof => ProtocolError::Closed,
+    _ => ProtocolError::Io(err),
+  })?;
+  decode(&body).map(Some)
+}
+
+// Handshake
+// ---------
+//
+
+// The client's side: says hello and waits for the server to welcome it.
+pub async fn client_handshake<S>(stream: &mut S, client: &str, capabilities: &[&str]) -> Result<Welcome, ProtocolError>
+where
+  S: AsyncRead + AsyncWrite + Unpin,
+{
+  let capabilities = capabilities.iter().map(|name| String::from(*name)).collect();
+  let hello = Hello { protocol_version: PROTOCOL_VERSION, client: String::from(client), capabilities };
+  write_message(stream, &ClientMessage::Hello(hello)).await?;
+  match read_message(stream).await? {
+    Some(ServerMessage::Welcome(welcome)) => Ok(welcome),
+    Some(ServerMessage::Rejected { protocol_version, reason }) => {
+      Err(ProtocolError::Rejected { protocol_version, reason })
+    }
+    Some(other) => Err(ProtocolError::UnexpectedMessage(format!("{:?} before welcome", other))),
+    None => Err(ProtocolError::Closed),
+  }
+}
+
+// The server's side: waits for the client's hello and welcomes it, or rejects it if it speaks another version. The
+// welcome names the capabilities both sides have, which are the only ones the connection uses. Returns the hello and the
+// welcome.
+pub async fn server_handshake<S>(
+  stream: &mut S,
+  server: &str,
+  capabilities: &[&str],
+) -> Result<(Hello, Welcome), ProtocolError>
+where
+  S: AsyncRead + AsyncWrite + Unpin,
+{
+  let hello = match read_message(stream).await? {
+    Some(ClientMessage::Hello(hello)) => hello,
+    Some(other) => return Err(ProtocolError::UnexpectedMessage(format!("{:?} before hello", other))),
+    None => return Err(ProtocolError::Closed),
+  };
+  if hello.protocol_version != PROTOCOL_VERSION {
+    let reason = format!("server speaks protocol version {}, not {}", PROTOCOL_VERSION, hello.protocol_version);
+    let rejected = ServerMessage::Rejected { protocol_version: PROTOCOL_VERSION, reason: reason.clone() };
+    write_message(stream, &rejected).await?;
+    return Err(ProtocolError::Rejected { protocol_version: hello.protocol_version, reason });
+  }
+  let capabilities =
+    capabilities.iter().filter(|name| hello.has_capability(name)).map(|name| String::from(*name)).collect();
+  let welcome = Welcome { protocol_version: PROTOCOL_VERSION, server: String::from(server), capabilities };
+  write_message(stream, &ServerMessage::Welcome(welcome.clone())).await?;
+  Ok((hello, welcome))
+}
@@ -0,0 +1,68 @@
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use proj_server_async::protocol::{self, ClientMessage, ProtocolError, Request, ServerMessage};
+use project_server::database::LIBRARY_FOLDER;
+use project_server::lock::{LockInfo, LockStatus, ProjectLock};
+use tokio::net::TcpStream;
+use tokio::time;
+
+// Stopping a Server
+// -----------------
+//
+// `proj-server-async <project> --stop` asks the server running on the project to save and shut down, and waits until it
+// has let go of the project's lock file. project-server's stop command runs this too.
+//
+
+// How the stop command introduces itself in the handshake.
+const CLIENT_NAME: &str = "stop";
+// How long to wait for the server to answer.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+// How long to wait for the server to save the project and exit.
+const STOP_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Returns an error message if the server couldn't be stopped.
+pub async fn stop(project_path: &Path) -> Result<(), String> {
+  let library_path = project_path.join(LIBRARY_FOLDER);
+  let (pid, address) = match ProjectLock::status(&library_path) {
+    LockStatus::Unlocked => {
+      println!("No server is running on this project.");
+      return Ok(());
+    }
+    LockStatus::Locked(Some(LockInfo { pid, address: Some(address), .. })) => (pid, address),
+    LockStatus::Locked(Some(LockInfo { pid, command, .. })) => {
+      return Err(format!("`{}` (pid {}) is working on the project, not a server; let it finish", command, pid));
+    }
+    LockStatus::Locked(None) => return Err(String::from("the project is locked, but its lock file can't be read")),
+  };
+  println!("Stopping the server (pid {}) on {}...", pid, address);
+  let no_answer = |_| Err(format!("no answer from the server after {} seconds", CLIENT_TIMEOUT.as_secs()));
+  time::timeout(CLIENT_TIMEOUT, request_shutdown(address)).await.unwrap_or_else(no_answer)?;
+  let started = Instant::now();
+  while ProjectLock::status(&library_path) != LockStatus::Unlocked {
+    if started.elapsed() > STOP_TIMEOUT {
+      return Err(format!("still running after {} seconds", STOP_TIMEOUT.as_secs()));
+    }
+    time::delay_for(Duration::from_millis(50)).await;
+  }
+  println!("Server stopped.");
+  Ok(())
+}
+
+// Returns once the server has answered the shutdown request; it may still be saving the project.
+async fn request_shutdown(address: SocketAddr) -> Result<(), String> {
+  let failed = |err: ProtocolError| err.to_string();
+  let mut stream = TcpStream::connect(address).await.map_err(|err| err.to_string())?;
+  protocol::client_handshake(&mut stream, CLIENT_NAME, &[]).await.map_err(failed)?;
+  let request = ClientMessage::Request { id: 1, request: Request::Shutdown };
+  protocol::write_message(&mut stream, &request).await.map_err(failed)?;
+  loop {
+    match protocol::read_message(&mut stream).await.map_err(failed)? {
+      Some(ServerMessage::Response { id: 1, .. }) => return Ok(()),
+      Some(ServerMessage::Error { id: 1, error }) => return Err(format!("shutdown refused: {}", error.message)),
+      // Events sent before the answer.
+      Some(_) => continue,
+      None => return Err(String::from("the server closed the connection without answering")),
+    }
+  }
+}
@@ -0,0 +1,449 @@
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use proj_server_async::protocol::{
+  self, AssetInfo, AssetLock, ErrorCode, Event, PeerPresence, Reply, Request, RequestError,
+};
+use project_server::changes::{AssetChange, ChangeKind};
+use project_server::database::AssetRecord;
+use project_server::error::ProjectError;
+use project_server::preview::{self, PreviewError, SheetPreview, Thumbnail};
+use project_server::project::Project;
+use project_server::references::{reference_string, ReferenceEdit};
+use project_server::search::{search, Query};
+use project_server::sprites::Sprite;
+use project_server::watcher::AssetEvent;
+use tokio::sync::{broadcast, oneshot};
+use uuid::Uuid;
+use crate::presence::{Change, Lock, Presence};
+
+// Project Worker
+// --------------
+//
+// The project is owned by a single thread from the blocking pool, since scanning, importing and moving assets all block
+// on the file system. Connections and the watcher hand it jobs over a channel and it works through them in order, so a
+// request always sees every change the watcher reported before it. The worker also keeps track of presence (see
+// presence.rs), so nothing can move or delete an asset between checking its lock and carrying out the request.
+//
+
+pub enum Job {
+  // A client finished its handshake; `peer` is the server's number for it.
+  Connected { peer: u64, name: String },
+  Request { peer: u64, request: Request, respond: oneshot::Sender<Result<Reply, RequestError>> },
+  // Changes recorded after `since` (if given) and a receiver for every change from now on.
+  Subscribe { since: Option<u64>, respond: oneshot::Sender<Result<Subscription, RequestError>> },
+  Asset(AssetEvent),
+  Disconnected { peer: u64 },
+  // Saves the project and ends the worker.
+  Shutdown,
+}
+
+pub struct Subscription {
+  // The latest change recorded when the subscription started.
+  pub cursor: u64,
+  // The changes after `since` that were recorded before the subscription started.
+  pub backlog: Vec<AssetChange>,
+  pub changes: broadcast::Receiver<AssetChange>,
+}
+
+struct Worker {
+  project: Project,
+  presence: Presence,
+  changes: broadcast::Sender<AssetChange>,
+  // Presence events, for the clients with the locks capability.
+  events: broadcast::Sender<Event>,
+}
+
+// Scans and imports the project, then works through jobs until told to shut down or every sender is gone. Every change
+// the project records is sent to `changes`, and every change to presence to `events`.
+pub fn run(
+  mut project: Project,
+  jobs: Receiver<Job>,
+  changes: broadcast::Sender<AssetChange>,
+  events: broadcast::Sender<Event>,
+) {
+  let project_changes = changes.clone();
+  project.changes.on_change(move |change| {
+    // Nobody may be subscribed; the change log keeps it for later.
+    let _ = project_changes.send(change.clone());
+  });
+  update_project(&mut project);
+
+  let mut worker = Worker { project, presence: Presence::default(), changes, events };
+  loop {
+    // Wakes up when the next lease runs out, if there's no job before then.
+    let job = match worker.presence.next_expiry() {
+      Some(expiry) => match jobs.recv_timeout(expiry.saturating_duration_since(Instant::now())) {
+        Ok(job) => Some(job),
+        Err(RecvTimeoutError::Timeout) => None,
+        Err(RecvTimeoutError::Disconnected) => break,
+      },
+      None => match jobs.recv() {
+        Ok(job) => Some(job),
+        Err(_) => break,
+      },
+    };
+    let expired = worker.presence.expire(Instant::now());
+    worker.tell(expired);
+    match job {
+      Some(Job::Connected { peer, name }) => worker.presence.join(peer, &name),
+      Some(Job::Request { peer, request, respond }) => {
+        // The connection may have closed in the meantime; there's no one left to tell.
+        let _ = respond.send(worker.handle(peer, request));
+      }
+      Some(Job::Subscribe { since, respond }) => {
+        let _ = respond.send(subscribe(&worker.project, since, &worker.changes));
+      }
+      Some(Job::Asset(event)) => worker.project.handle_asset_event(event),
+      Some(Job::Disconnected { peer }) => {
+        let left = worker.presence.leave(peer);
+        worker.tell(left);
+      }
+      Some(Job::Shutdown) => break,
+      None => {}
+    }
+    let database = &worker.project.database;
+    let gone = worker.presence.follow_assets(|guid| database.path_for_guid(guid));
+    worker.tell(gone);
+  }
+  if let Err(err) = worker.project.save() {
+    println!("Error saving project library {:?}: {}", worker.project.library_path, err);
+  }
+}
+
+// Brings the asset index and imports up to date with whatever changed while the server wasn't running.
+fn update_project(project: &mut Project) {
+  println!("Scanning project assets ({} indexed)...", project.database.len());
+  let scan = project.scan();
+  for err in &scan.errors {
+    println!("Error: {}", err);
+  }
+  println!(
+    "Scan complete: {} added, {} changed, {} removed, {} unchanged.",
+    scan.added.len(),
+    scan.changed.len(),
+    scan.removed.len(),
+    scan.unchanged
+  );
+  let import = project.import_all();
+  for (path, err) in &import.failed {
+    println!("Error importing {:?}: {}", path, err);
+  }
+  println!(
+    "Import complete: {} imported, {} failed, {} up to date.",
+    import.imported.len(),
+    import.failed.len(),
+    import.up_to_date
+  );
+}
+
+impl Worker {
+  fn handle(&mut self, peer: u64, request: Request) -> Result<Reply, RequestError> {
+    let project = &mut self.project;
+    match request {
+      Request::Ping => Ok(Reply::Pong),
+      Request::ListFolder { path } => {
+        let folder = PathBuf::from(path);
+        if !folder.as_os_str().is_empty() {
+          match project.database.get(&folder) {
+            Some(record) if record.is_folder => {}
+            Some(_) => return Err(RequestError::new(ErrorCode::InvalidPath, &format!("{:?} is not a folder", folder))),
+            None => return Err(not_found(folder)),
+          }
+        }
+        let mut records: Vec<&AssetRecord> =
+          project.database.iter().filter(|record| record.path.parent() == Some(folder.as_path())).collect();
+        records.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(Reply::Assets { assets: records.into_iter().map(asset_info).collect() })
+      }
+      Request::GetAsset { path } => {
+        let path = PathBuf::from(path);
+        let record = project.database.get(&path).ok_or_else(|| not_found(path.clone()))?;
+        Ok(Reply::Asset { asset: asset_info(record) })
+      }
+      Request::ResolveGuid { guid } => match project.database.path_for_guid(&guid) {
+        Some(path) => Ok(Reply::Path { path: path_string(path) }),
+        None => Err(RequestError::new(ErrorCode::AssetNotFound, &format!("no asset with GUID {}", guid))),
+      },
+      Request::Search { query, limit } => {
+        let query = Query::parse(&query).map_err(|err| RequestError::new(ErrorCode::InvalidQuery, &err.to_string()))?;
+        let found = search(&project.database, &query);
+        let limit = limit.unwrap_or(found.len());
+        Ok(Reply::Assets { assets: found.into_iter().take(limit).map(asset_info).collect() })
+      }
+      Request::MoveAsset { from, to, dry_run } => self.move_asset(peer, PathBuf::from(from), PathBuf::from(to), dry_run),
+      Request::RenameAsset { path, name, dry_run } => {
+        let mut parts = Path::new(&name).components();
+        if !matches!((parts.next(), parts.next()), (Some(Component::Normal(_)), None)) {
+          return Err(RequestError::new(ErrorCode::InvalidPath, &format!("{:?} is not a file name", name)));
+        }
+        let to = Path::new(&path).with_file_name(&name);
+        self.move_asset(peer, PathBuf::from(path), to, dry_run)
+      }
+      Request::DeleteAsset { path } => {
+        check_unlocked(&self.presence, peer, Path::new(&path))?;
+        project.delete_asset(Path::new(&path)).map_err(request_error)?;
+        Ok(Reply::Done)
+      }
+      Request::GetThumbnail { path } => {
+        let thumbnail = preview::thumbnail(project, Path::new(&path)).map_err(preview_error)?;
+        Ok(Reply::Thumbnail { thumbnail: thumbnail_info(&thumbnail) })
+      }
+      Request::GetSpritePreviews { path } => {
+        let previews = preview::sprite_sheet_previews(project, Path::new(&path)).map_err(preview_error)?;
+        Ok(Reply::SpritePreviews { previews: previews.iter().map(sheet_preview).collect() })
+      }
+      Request::ChangesSince { since } => {
+        let changes = match since {
+          Some(since) => project.changes.since(since).ok_or_else(|| cursor_expired(since))?,
+          None => Vec::new(),
+        };
+        Ok(Reply::Changes { cursor: project.changes.cursor(), changes: changes.iter().map(asset_change).collect() })
+      }
+      Request::OpenAsset { path } => {
+        let (guid, path) = find(project, &path)?;
+        let opened = self.presence.open(peer, guid, &path);
+        self.tell(opened);
+        Ok(Reply::Done)
+      }
+      Request::CloseAsset { path } => {
+        let (guid, _) = find(project, &path)?;
+        let closed = self.presence.close(peer, guid);
+        self.tell(closed);
+        Ok(Reply::Done)
+      }
+      Request::LockAsset { path, lease_secs } => {
+        let (guid, path) = find(project, &path)?;
+        let lease = Duration::from_secs(lease_secs);
+        let locked = self.presence.lock(peer, guid, &path, lease).map_err(|lock| locked(&lock))?;
+        let lock = match &locked {
+          Change::Locked { lock } => asset_lock(lock),
+          _ => unreachable!("locking an asset locks it"),
+        };
+        self.tell(Some(locked));
+        Ok(Reply::Locked { lock })
+      }
+      Request::UnlockAsset { path } => {
+        let (guid, _) = find(project, &path)?;
+        let unlocked = self.presence.unlock(peer, guid).map_err(|lock| locked(&lock))?;
+        self.tell(unlocked);
+        Ok(Reply::Done)
+      }
+      Request::ListPresence => {
+        let peers = self.presence.peers().into_iter().map(|(peer, open)| PeerPresence {
+          peer,
+          open: open.into_iter().map(path_string).collect(),
+        });
+        Ok(Reply::Presence {
+          you: self.presence.peer(peer),
+          peers: peers.collect(),
+          locks: self.presence.locks().iter().map(asset_lock).collect(),
+        })
+      }
+      // Connections answer these themselves; they only reach the worker by mistake.
+      Request::Subscribe { .. }
+      | Request::Unsubscribe { .. }
+      | Request::BuildGame { .. }
+      | Request::RunGame { .. }
+      | Request::StopGame
+      | Request::RestartGame
+      | Request::GameStatus
+      | Request::WatchGame
+      | Request::UnwatchGame
+      | Request::Shutdown => {
+        Err(RequestError::new(ErrorCode::Internal, "request handled by the connection, not the project"))
+      }
+    }
+  }
+
+  fn move_asset(&mut self, peer: u64, from: PathBuf, to: PathBuf, dry_run: bool) -> Result<Reply, RequestError> {
+    let project = &mut self.project;
+    // A dry run answers with the asset where it still is.
+    let (edits, moved) = if dry_run {
+      (project.preview_move(&from, &to).map_err(request_error)?, from)
+    } else {
+      // The files whose references follow the asset are edited too, so a lock on any of them holds the move up before
+      // anything is touched.
+      let planned = project.preview_move(&from, &to).map_err(request_error)?;
+      check_unlocked(&self.presence, peer, &from)?;
+      check_unlocked(&self.presence, peer, &to)?;
+      for edit in &planned {
+        check_unlocked(&self.presence, peer, &edit.file)?;
+      }
+      (project.move_asset(&from, &to).map_err(request_error)?, to)
+    };
+    let asset = project.database.get(&moved).map(asset_info).ok_or_else(|| not_found(moved))?;
+    Ok(Reply::Moved { asset, edits: edits.iter().map(reference_edit).collect() })
+  }
+
+  // Sends presence changes to the clients that want them.
+  fn tell(&self, changes: impl IntoIterator<Item = Change>) {
+    for change in changes {
+      // Nobody may be listening.
+      let _ = self.events.send(presence_event(change));
+    }
+  }
+}
+
+// Fails when someone other than `peer` holds a lock that covers `path`.
+fn check_unlocked(presence: &Presence, peer: u64, path: &Path) -> Result<(), RequestError> {
+  match presence.locked_for(peer, path) {
+    Some(lock) => Err(locked(&lock)),
+    None => Ok(()),
+  }
+}
+
+// The GUID and path of the asset at `path`.
+fn find(project: &Project, path: &str) -> Result<(Uuid, PathBuf), RequestError> {
+  let path = PathBuf::from(path);
+  match project.database.get(&path) {
+    Some(record) => Ok((record.guid, path)),
+    None => Err(not_found(path)),
+  }
+}
+
+fn subscribe(
+  project: &Project,
+  since: Option<u64>,
+  changes: &broadcast::Sender<AssetChange>,
+) -> Result<Subscription, RequestError> {
+  // Subscribing on the worker means no change can fall between the backlog and the receiver.
+  let backlog = match since {
+    Some(since) => project.changes.since(since).ok_or_else(|| cursor_expired(since))?,
+    None => Vec::new(),
+  };
+  Ok(Subscription { cursor: project.changes.cursor(), backlog, changes: changes.subscribe() })
+}
+
+// Conversions
+// -----------
+//
+// The protocol has its own copies of the project's types, so clients don't depend on how the server stores them.
+//
+
+fn request_error(err: ProjectError) -> RequestError {
+  let code = match err {
+    ProjectError::AssetNotFound { .. } => ErrorCode::AssetNotFound,
+    ProjectError::AssetExists { .. } => ErrorCode::AssetExists,
+    ProjectError::InvalidAssetPath { .. } | ProjectError::NonUtf8Path { .. } => ErrorCode::InvalidPath,
+    _ => ErrorCode::ProjectError,
+  };
+  RequestError::new(code, &err.to_string())
+}
+
+fn preview_error(err: PreviewError) -> RequestError {
+  match err {
+    PreviewError::Project(err) => request_error(err),
+    err => RequestError::new(ErrorCode::PreviewFailed, &err.to_string()),
+  }
+}
+
+fn cursor_expired(since: u64) -> RequestError {
+  let message = format!("changes after {} are no longer known; list the assets again", since);
+  RequestError::new(ErrorCode::CursorExpired, &message)
+}
+
+fn not_found(path: PathBuf) -> RequestError {
+  request_error(ProjectError::AssetNotFound { path })
+}
+
+fn locked(lock: &Lock) -> RequestError {
+  let message = format!(
+    "{:?} is locked by {} (client {}) for another {}s",
+    lock.path,
+    lock.holder.name,
+    lock.holder.id,
+    secs_left(lock.expires)
+  );
+  RequestError::new(ErrorCode::AssetLocked, &message)
+}
+
+// Rounded up, so a lease with any time left never shows 0.
+fn secs_left(expires: Instant) -> u64 {
+  let left = expires.saturating_duration_since(Instant::now());
+  left.as_secs() + if left.subsec_nanos() > 0 { 1 } else { 0 }
+}
+
+// Asset paths use "/" whatever the platform, as RON files refer to them.
+fn path_string(path: &Path) -> String {
+  reference_string(path).unwrap_or_else(|| path.to_string_lossy().into_owned())
+}
+
+fn asset_info(record: &AssetRecord) -> AssetInfo {
+  AssetInfo {
+    guid: record.guid,
+    path: path_string(&record.path),
+    asset_type: record.asset_type.clone(),
+    is_folder: record.is_folder,
+    size: record.size,
+    modified: record.modified.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+    hash: record.hash.clone(),
+  }
+}
+
+fn reference_edit(edit: &ReferenceEdit) -> protocol::ReferenceEdit {
+  protocol::ReferenceEdit {
+    file: path_string(&edit.file),
+    line: edit.line,
+    old_path: edit.old_path.clone(),
+    new_path: edit.new_path.clone(),
+  }
+}
+
+fn thumbnail_info(thumbnail: &Thumbnail) -> protocol::Thumbnail {
+  protocol::Thumbnail {
+    file: thumbnail.file.to_string_lossy().into_owned(),
+    width: thumbnail.width,
+    height: thumbnail.height,
+  }
+}
+
+fn sheet_preview(preview: &SheetPreview) -> protocol::SheetPreview {
+  protocol::SheetPreview {
+    file: preview.file.to_string_lossy().into_owned(),
+    width: preview.width,
+    height: preview.height,
+    texture: path_string(&preview.texture),
+    scale: preview.scale,
+    sprites: preview.sprites.iter().map(sprite).collect(),
+  }
+}
+
+fn sprite(sprite: &Sprite) -> protocol::Sprite {
+  protocol::Sprite { x: sprite.x, y: sprite.y, width: sprite.width, height: sprite.height }
+}
+
+pub fn asset_change(change: &AssetChange) -> protocol::AssetChange {
+  protocol::AssetChange {
+    seq: change.seq,
+    kind: match change.kind {
+      ChangeKind::Added => protocol::ChangeKind::Added,
+      ChangeKind::Modified => protocol::ChangeKind::Modified,
+      ChangeKind::Moved => protocol::ChangeKind::Moved,
+      ChangeKind::Removed => protocol::ChangeKind::Removed,
+      ChangeKind::Imported => protocol::ChangeKind::Imported,
+    },
+    guid: change.guid,
+    path: path_string(&change.path),
+    from: change.from.as_deref().map(path_string),
+    asset_type: change.asset_type.clone(),
+  }
+}
+
+fn asset_lock(lock: &Lock) -> AssetLock {
+  AssetLock {
+    guid: lock.guid,
+    path: path_string(&lock.path),
+    holder: lock.holder.clone(),
+    expires_in_secs: secs_left(lock.expires),
+  }
+}
+
+fn presence_event(change: Change) -> Event {
+  match change {
+    Change::Opened { peer, path } => Event::AssetOpened { peer, path: path_string(&path) },
+    Change::Closed { peer, path } => Event::AssetClosed { peer, path: path_string(&path) },
+    Change::Locked { lock } => Event::AssetLocked { lock: asset_lock(&lock) },
+    Change::Unlocked { holder, path } => Event::AssetUnlocked { path: path_string(&path), holder },
+  }
+}
@@ -0,0 +1,82 @@
+// Test Helpers
+// ------------
+//
+// What the tests that talk to a real server share: a throwaway project, the server running on it, and clients speaking
+// the protocol by hand.
+//
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+use proj_server_async::protocol::{self, ClientMessage, Request, ServerMessage};
+use project_server::database::LIBRARY_FOLDER;
+use project_server::lock::ProjectLock;
+use tempfile::TempDir;
+use tokio::net::TcpStream;
+use tokio::time;
+
+pub const MANIFEST: &str = "[project]\nname = \"Test\"\nengine_version = \"0.1.0\"\nasset_roots = [\"Assets\"]\n";
+
+// An empty project: a manifest and an Assets folder.
+pub fn project() -> TempDir {
+  let dir = tempfile::tempdir().unwrap();
+  fs::create_dir_all(dir.path().join("Assets")).unwrap();
+  fs::write(dir.path().join("theseus.toml"), MANIFEST).unwrap();
+  dir
+}
+
+// Writes a file into the project's Assets, creating the folders it's in.
+pub fn write_asset(dir: &TempDir, path: &str, contents: impl AsRef<[u8]>) {
+  let path = dir.path().join("Assets").join(path);
+  fs::create_dir_all(path.parent().unwrap()).unwrap();
+  fs::write(path, contents).unwrap();
+}
+
+pub fn read_asset(dir: &TempDir, path: &str) -> String {
+  fs::read_to_string(dir.path().join("Assets").join(path)).unwrap()
+}
+
+// The server process, killed if a test fails before it's shut down.
+pub struct Server(pub Child);
+
+impl Drop for Server {
+  fn drop(&mut self) {
+    let _ = self.0.kill();
+    let _ = self.0.wait();
+  }
+}
+
+// Starts the server on the project and waits for it to say where it listens.
+pub async fn start_server(project_path: &Path) -> (Server, String) {
+  let child = Command::new(env!("CARGO_BIN_EXE_proj-server-async")).arg(project_path).stdout(Stdio::null()).spawn();
+  let server = Server(child.unwrap());
+  let started = Instant::now();
+  loop {
+    if let Some(address) = ProjectLock::server_address(&project_path.join(LIBRARY_FOLDER)) {
+      return (server, address.to_string());
+    }
+    assert!(started.elapsed() < Duration::from_secs(30), "the server didn't start");
+    time::delay_for(Duration::from_millis(20)).await;
+  }
+}
+
+// Connects and shakes hands, asking for `capabilities`.
+pub async fn join(address: &str, client: &str, capabilities: &[&str]) -> TcpStream {
+  let mut stream = TcpStream::connect(address).await.unwrap();
+  protocol::client_handshake(&mut stream, client, capabilities).await.unwrap();
+  stream
+}
+
+// Sends a request and waits for its answer, skipping events.
+pub async fn ask(stream: &mut TcpStream, id: u64, request: Request) -> ServerMessage {
+  protocol::write_message(stream, &ClientMessage::Request { id, request }).await.unwrap();
+  loop {
+    match protocol::read_message(stream).await.unwrap() {
+      Some(ServerMessage::Event { .. }) => continue,
+      Some(message) => return message,
+      None => panic!("the server closed the connection"),
+    }
+  }
+}
@@ -0,0 +1,537 @@
+use std::fmt::Debug;
+use proj_server_async::protocol::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::net::{TcpListener, TcpStream};
+use uuid::Uuid;
+use crate::common::{ask, join, read_asset, start_server, write_asset};
+
+mod common;
+
+// Protocol Tests
+// --------------
+//
+// Every message the protocol has goes through a frame and back unchanged. The `covers_*` functions match without a
+// wildcard, so adding a message without adding it to the samples here doesn't compile. The last tests talk to the
+// server itself, run on a throwaway project.
+//
+
+fn asset() -> AssetInfo {
+  AssetInfo {
+    guid: Uuid::from_u128(0x1234),
+    path: String::from("textures/logo.png"),
+    asset_type: String::from("texture"),
+    is_folder: false,
+    size: 14812,
+    modified: 1_600_000_000,
+    hash: String::from("5b1aad9f"),
+  }
+}
+
+fn change(kind: ChangeKind, from: Option<&str>) -> AssetChange {
+  AssetChange {
+    seq: 42,
+    kind,
+    guid: Uuid::from_u128(0x1234),
+    path: String::from("textures/logo.png"),
+    from: from.map(String::from),
+    asset_type: String::from("texture"),
+  }
+}
+
+fn peer() -> Peer {
+  Peer { id: 2, name: String::from("editor") }
+}
+
+fn lock() -> AssetLock {
+  let path = String::from("levels/level.ron");
+  AssetLock { guid: Uuid::from_u128(0x1234), path, holder: peer(), expires_in_secs: 60 }
+}
+
+fn diagnostic() -> Diagnostic {
+  Diagnostic {
+    level: String::from("error"),
+    message: String::from("mismatched types"),
+    code: Some(String::from("E0308")),
+    file: Some(String::from("src/main.rs")),
+    line: Some(12),
+    column: Some(9),
+    rendered: Some(String::from("error[E0308]: mismatched types\n")),
+  }
+}
+
+fn requests() -> Vec<Request> {
+  vec![
+    Request::Ping,
+    Request::ListFolder { path: String::new() },
+    Request::GetAsset { path: String::from("textures/logo.png") },
+    Request::ResolveGuid { guid: Uuid::from_u128(0x1234) },
+    Request::Search { query: String::from("type:texture name:*menu*"), limit: Some(20) },
+    Request::Search { query: String::new(), limit: None },
+    Request::MoveAsset { from: String::from("a.png"), to: String::from("b/a.png"), dry_run: true },
+    Request::RenameAsset { path: String::from("b/a.png"), name: String::from("c.png"), dry_run: false },
+    Request::DeleteAsset { path: String::from("a.png") },
+    Request::GetThumbnail { path: String::from("textures/logo.png") },
+    Request::GetSpritePreviews { path: String::from("textures/pong.ron") },
+    Request::ChangesSince { since: Some(7) },
+    Request::ChangesSince { since: None },
+    Request::Subscribe { folder: Some(String::from("textures")), types: vec![String::from("texture")], since: Some(7) },
+    Request::Subscribe { folder: None, types: Vec::new(), since: None },
+    Request::Unsubscribe { subscription: 3 },
+    Request::OpenAsset { path: String::from("levels/level.ron") },
+    Request::CloseAsset { path: String::from("levels/level.ron") },
+    Request::LockAsset { path: String::from("levels/level.ron"), lease_secs: 60 },
+    Request::UnlockAsset { path: String::from("levels/level.ron") },
+    Request::ListPresence,
+    Request::BuildGame { release: true },
+    Request::RunGame { release: false, args: vec![String::from("--level"), String::from("2")] },
+    Request::StopGame,
+    Request::RestartGame,
+    Request::GameStatus,
+    Request::WatchGame,
+    Request::UnwatchGame,
+    Request::Shutdown,
+  ]
+}
+
+fn replies() -> Vec<Reply> {
+  let edit = ReferenceEdit {
+    file: String::from("prefabs/level.ron"),
+    line: 7,
+    old_path: String::from("a.png"),
+    new_path: String::from("b/a.png"),
+  };
+  vec![
+    Reply::Pong,
+    Reply::Assets { assets: vec![asset(), asset()] },
+    Reply::Asset { asset: asset() },
+    Reply::Path { path: String::from("textures/logo.png") },
+    Reply::Moved { asset: asset(), edits: vec![edit] },
+    Reply::Subscribed { subscription: 3, cursor: 42 },
+    Reply::Thumbnail { thumbnail: Thumbnail { file: String::from("/tmp/thumbnail.png"), width: 128, height: 64 } },
+    Reply::SpritePreviews {
+      previews: vec![SheetPreview {
+        file: String::from("/tmp/preview.png"),
+        width: 256,
+        height: 128,
+        texture: String::from("textures/pong.png"),
+        scale: 16,
+        sprites: vec![Sprite { x: 0, y: 0, width: 4, height: 16 }, Sprite { x: 4, y: 0, width: 4, height: 4 }],
+      }],
+    },
+    Reply::Changes { cursor: 42, changes: vec![change(ChangeKind::Moved, Some("old/logo.png"))] },
+    Reply::Locked { lock: lock() },
+    Reply::Presence {
+      you: peer(),
+      peers: vec![PeerPresence { peer: peer(), open: vec![String::from("levels/level.ron")] }],
+      locks: vec![lock()],
+    },
+    Reply::Game { process: None },
+    Reply::Game {
+      process: Some(GameProcess {
+        process: 3,
+        command: String::from("cargo run"),
+        running: false,
+        built: Some(false),
+        exit_code: Some(101),
+        diagnostics: vec![diagnostic()],
+      }),
+    },
+    Reply::Done,
+  ]
+}
+
+fn events() -> Vec<Event> {
+  let mut events: Vec<Event> = [
+    (ChangeKind::Added, None),
+    (ChangeKind::Modified, None),
+    (ChangeKind::Moved, Some("old/logo.png")),
+    (ChangeKind::Removed, None),
+    (ChangeKind::Imported, None),
+  ]
+  .iter()
+  .map(|&(kind, from)| Event::AssetChanged { subscription: 3, change: change(kind, from) })
+  .collect();
+  events.push(Event::SubscriptionExpired { subscription: 3 });
+  events.push(Event::ServerShutdown);
+  events.push(Event::AssetOpened { peer: peer(), path: String::from("levels/level.ron") });
+  events.push(Event::AssetClosed { peer: peer(), path: String::from("levels/level.ron") });
+  events.push(Event::AssetLocked { lock: lock() });
+  events.push(Event::AssetUnlocked { path: String::from("levels/level.ron"), holder: peer() });
+  events.push(Event::GameStarted { process: 3, command: String::from("cargo run") });
+  events.push(Event::GameOutput { process: 3, stream: OutputStream::Stdout, line: String::from("Level 1") });
+  events.push(Event::GameOutput { process: 3, stream: OutputStream::Stderr, line: String::new() });
+  events.push(Event::GameDiagnostic { process: 3, diagnostic: diagnostic() });
+  events.push(Event::GameBuilt { process: 3, success: true });
+  events.push(Event::GameExited { process: 3, code: None, stopped: true });
+  events
+}
+
+fn error_codes() -> Vec<ErrorCode> {
+  vec![
+    ErrorCode::AssetNotFound,
+    ErrorCode::AssetExists,
+    ErrorCode::InvalidPath,
+    ErrorCode::InvalidQuery,
+    ErrorCode::ProjectError,
+    ErrorCode::PreviewFailed,
+    ErrorCode::CursorExpired,
+    ErrorCode::AssetLocked,
+    ErrorCode::GameRunning,
+    ErrorCode::Unsupported,
+    ErrorCode::Internal,
+  ]
+}
+
+fn client_messages() -> Vec<ClientMessage> {
+  let hello = Hello { protocol_version: PROTOCOL_VERSION, client: String::from("editor"), capabilities: Vec::new() };
+  let mut messages = vec![ClientMessage::Hello(hello)];
+  let requests = requests().into_iter().enumerate();
+  messages.extend(requests.map(|(id, request)| ClientMessage::Request { id: id as u64, request }));
+  messages
+}
+
+fn server_messages() -> Vec<ServerMessage> {
+  let capabilities = vec![String::from(SUBSCRIPTIONS)];
+  let welcome = Welcome { protocol_version: PROTOCOL_VERSION, server: String::from("theseus"), capabilities };
+  let mut messages = vec![
+    ServerMessage::Welcome(welcome),
+    ServerMessage::Rejected { protocol_version: PROTOCOL_VERSION, reason: String::from("too old") },
+  ];
+  let replies = replies().into_iter().enumerate();
+  messages.extend(replies.map(|(id, result)| ServerMessage::Response { id: id as u64, result }));
+  let errors = error_codes().into_iter().map(|code| RequestError::new(code, "no"));
+  messages.extend(errors.map(|error| ServerMessage::Error { id: 9, error }));
+  messages.extend(events().into_iter().map(|event| ServerMessage::Event { event }));
+  messages
+}
+
+#[allow(dead_code)]
+fn covers_client_message(message: &ClientMessage) {
+  match message {
+    ClientMessage::Hello(_) | ClientMessage::Request { .. } => {}
+  }
+}
+
+#[allow(dead_code)]
+fn covers_request(request: &Request) {
+  match request {
+    Request::Ping
+    | Request::ListFolder { .. }
+    | Request::GetAsset { .. }
+    | Request::ResolveGuid { .. }
+    | Request::Search { .. }
+    | Request::MoveAsset { .. }
+    | Request::RenameAsset { .. }
+    | Request::DeleteAsset { .. }
+    | Request::GetThumbnail { .. }
+    | Request::GetSpritePreviews { .. }
+    | Request::ChangesSince { .. }
+    | Request::Subscribe { .. }
+    | Request::Unsubscribe { .. }
+    | Request::OpenAsset { .. }
+    | Request::CloseAsset { .. }
+    | Request::LockAsset { .. }
+    | Request::UnlockAsset { .. }
+    | Request::ListPresence
+    | Request::BuildGame { .. }
+    | Request::RunGame { .. }
+    | Request::StopGame
+    | Request::RestartGame
+    | Request::GameStatus
+    | Request::WatchGame
+    | Request::UnwatchGame
+    | Request::Shutdown => {}
+  }
+}
+
+#[allow(dead_code)]
+fn covers_server_message(message: &ServerMessage) {
+  match message {
+    ServerMessage::Welcome(_)
+    | ServerMessage::Rejected { .. }
+    | ServerMessage::Response { .. }
+    | ServerMessage::Error { .. }
+    | ServerMessage::Event { .. } => {}
+  }
+}
+
+#[allow(dead_code)]
+fn covers_reply(reply: &Reply) {
+  match reply {
+    Reply::Pong
+    | Reply::Assets { .. }
+    | Reply::Asset { .. }
+    | Reply::Path { .. }
+    | Reply::Moved { .. }
+    | Reply::Subscribed { .. }
+    | Reply::Thumbnail { .. }
+    | Reply::SpritePreviews { .. }
+    | Reply::Changes { .. }
+    | Reply::Locked { .. }
+    | Reply::Presence { .. }
+    | Reply::Game { .. }
+    | Reply::Done => {}
+  }
+}
+
+#[allow(dead_code)]
+fn covers_event(event: &Event) {
+  match event {
+    Event::AssetChanged { .. }
+    | Event::SubscriptionExpired { .. }
+    | Event::ServerShutdown
+    | Event::AssetOpened { .. }
+    | Event::AssetClosed { .. }
+    | Event::AssetLocked { .. }
+    | Event::AssetUnlocked { .. }
+    | Event::GameStarted { .. }
+    | Event::GameOutput { .. }
+    | Event::GameDiagnostic { .. }
+    | Event::GameBuilt { .. }
+    | Event::GameExited { .. } => {}
+  }
+}
+
+#[allow(dead_code)]
+fn covers_error_code(code: ErrorCode) {
+  match code {
+    ErrorCode::AssetNotFound
+    | ErrorCode::AssetExists
+    | ErrorCode::InvalidPath
+    | ErrorCode::InvalidQuery
+    | ErrorCode::ProjectError
+    | ErrorCode::PreviewFailed
+    | ErrorCode::CursorExpired
+    | ErrorCode::AssetLocked
+    | ErrorCode::GameRunning
+    | ErrorCode::Unsupported
+    | ErrorCode::Internal => {}
+  }
+}
+
+fn round_trip<T: Serialize + DeserializeOwned + PartialEq + Debug>(message: &T) {
+  let frame = encode(message).unwrap();
+  let len = u32::from_be_bytes([frame[0], frame[1], frame[2], frame[3]]) as usize;
+  assert_eq!(len, frame.len() - 4, "length prefix of {:?}", message);
+  let decoded: T = decode(&frame[4..]).unwrap();
+  assert_eq!(&decoded, message);
+}
+
+#[test]
+fn client_messages_round_trip() {
+  for message in client_messages() {
+    round_trip(&message);
+  }
+}
+
+#[test]
+fn server_messages_round_trip() {
+  for message in server_messages() {
+    round_trip(&message);
+  }
+}
+
+#[test]
+fn messages_look_like_the_documentation() {
+  let request = ClientMessage::Request { id: 1, request: Request::GetAsset { path: String::from("a.png") } };
+  let json: serde_json::Value = decode(&encode(&request).unwrap()[4..]).unwrap();
+  assert_eq!(json, serde_json::json!({"type": "request", "id": 1, "method": "get_asset", "params": {"path": "a.png"}}));
+
+  let response = ServerMessage::Response { id: 1, result: Reply::Path { path: String::from("a.png") } };
+  let json: serde_json::Value = decode(&encode(&response).unwrap()[4..]).unwrap();
+  assert_eq!(json, serde_json::json!({"type": "response", "id": 1, "result": {"kind": "path", "path": "a.png"}}));
+
+  let event = ServerMessage::Event { event: Event::SubscriptionExpired { subscription: 3 } };
+  let json: serde_json::Value = decode(&encode(&event).unwrap()[4..]).unwrap();
+  assert_eq!(json, serde_json::json!({"type": "event", "event": "subscription_expired", "subscription": 3}));
+}
+
+#[tokio::test]
+async fn messages_stream_in_order() {
+  let mut stream = Vec::new();
+  for message in server_messages() {
+    write_message(&mut stream, &message).await.unwrap();
+  }
+  let mut reader = stream.as_slice();
+  for message in server_messages() {
+    assert_eq!(read_message::<_, ServerMessage>(&mut reader).await.unwrap(), Some(message));
+  }
+  assert!(read_message::<_, ServerMessage>(&mut reader).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn truncated_frames_are_errors() {
+  let frame = encode(&ClientMessage::Request { id: 1, request: Request::Ping }).unwrap();
+  for cut in [2, frame.len() - 1].iter() {
+    let mut reader = &frame[..*cut];
+    match read_message::<_, ClientMessage>(&mut reader).await {
+      Err(ProtocolError::Closed) => {}
+      other => panic!("cut at {}: {:?}", cut, other),
+    }
+  }
+}
+
+#[tokio::test]
+async fn oversized_frames_are_refused() {
+  let mut frame = ((MAX_FRAME_LEN + 1) as u32).to_be_bytes().to_vec();
+  frame.extend_from_slice(b"{}");
+  let mut reader = frame.as_slice();
+  match read_message::<_, ClientMessage>(&mut reader).await {
+    Err(ProtocolError::FrameTooLarge(len)) => assert_eq!(len, MAX_FRAME_LEN + 1),
+    other => panic!("{:?}", other),
+  }
+}
+
+#[tokio::test]
+async fn malformed_frames_are_errors() {
+  let mut frame = 7u32.to_be_bytes().to_vec();
+  frame.extend_from_slice(b"{\"a\":1}");
+  let mut reader = frame.as_slice();
+  match read_message::<_, ClientMessage>(&mut reader).await {
+    Err(ProtocolError::Malformed(_)) => {}
+    other => panic!("{:?}", other),
+  }
+}
+
+async fn connect() -> (TcpStream, TcpStream) {
+  let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let address = listener.local_addr().unwrap();
+  let (client, server) = tokio::join!(TcpStream::connect(address), listener.accept());
+  (client.unwrap(), server.unwrap().0)
+}
+
+#[tokio::test]
+async fn handshake_exchanges_versions_and_capabilities() {
+  let (mut client, mut server) = connect().await;
+  let (welcome, handshake) = tokio::join!(
+    client_handshake(&mut client, "editor", &[SUBSCRIPTIONS, "teleport"]),
+    server_handshake(&mut server, "theseus", &[SUBSCRIPTIONS, LOCKS]),
+  );
+  let (welcome, (hello, sent)) = (welcome.unwrap(), handshake.unwrap());
+  let capabilities = vec![String::from(SUBSCRIPTIONS), String::from("teleport")];
+  assert_eq!(hello, Hello { protocol_version: PROTOCOL_VERSION, client: String::from("editor"), capabilities });
+  assert_eq!(welcome, sent);
+  assert_eq!(welcome.protocol_version, PROTOCOL_VERSION);
+  assert_eq!(welcome.server, "theseus");
+  // Only the capabilities both sides have are welcomed.
+  assert_eq!(welcome.capabilities, vec![String::from(SUBSCRIPTIONS)]);
+}
+
+#[tokio::test]
+async fn handshake_rejects_other_versions() {
+  let (mut client, mut server) = connect().await;
+  let version = PROTOCOL_VERSION + 1;
+  let hello = Hello { protocol_version: version, client: String::from("future"), capabilities: Vec::new() };
+  let client_side = async {
+    write_message(&mut client, &ClientMessage::Hello(hello)).await.unwrap();
+    read_message::<_, ServerMessage>(&mut client).await.unwrap()
+  };
+  let (answer, result) = tokio::join!(client_side, server_handshake(&mut server, "theseus", &[]));
+  match answer {
+    Some(ServerMessage::Rejected { protocol_version, .. }) => assert_eq!(protocol_version, PROTOCOL_VERSION),
+    other => panic!("{:?}", other),
+  }
+  match result {
+    Err(ProtocolError::Rejected { protocol_version, .. }) => assert_eq!(protocol_version, version),
+    other => panic!("{:?}", other),
+  }
+}
+
+#[tokio::test]
+async fn handshake_needs_hello_first() {
+  let (mut client, mut server) = connect().await;
+  let request = ClientMessage::Request { id: 1, request: Request::Ping };
+  let (written, result) =
+    tokio::join!(write_message(&mut client, &request), server_handshake(&mut server, "theseus", &[]));
+  written.unwrap();
+  match result {
+    Err(ProtocolError::UnexpectedMessage(_)) => {}
+    other => panic!("{:?}", other),
+  }
+}
+
+#[tokio::test]
+async fn moves_wait_for_locks_on_the_files_they_rewrite() {
+  let dir = common::project();
+  let level = "(\n  ball: File(\"prefabs/ball.ron\"),\n)\n";
+  write_asset(&dir, "prefabs/level.ron", level);
+  write_asset(&dir, "prefabs/ball.ron", "(speed: 2.0)\n");
+  let assets = dir.path().join("Assets");
+  let (mut server, address) = start_server(dir.path()).await;
+  let mut editor = join(&address, "editor", &[LOCKS]).await;
+  let mut script = join(&address, "script", &[LOCKS]).await;
+
+  let lock = Request::LockAsset { path: String::from("prefabs/level.ron"), lease_secs: 60 };
+  match ask(&mut editor, 1, lock).await {
+    ServerMessage::Response { result: Reply::Locked { .. }, .. } => {}
+    other => panic!("{:?}", other),
+  }
+  // Moving the ball rewrites the level, which the editor holds, so nothing moves.
+  let (from, to) = (String::from("prefabs/ball.ron"), String::from("ball.ron"));
+  let move_ball = Request::MoveAsset { from, to, dry_run: false };
+  match ask(&mut script, 1, move_ball.clone()).await {
+    ServerMessage::Error { error, .. } => assert_eq!(error.code, ErrorCode::AssetLocked),
+    other => panic!("{:?}", other),
+  }
+  assert_eq!(read_asset(&dir, "prefabs/level.ron"), level);
+  assert!(assets.join("prefabs/ball.ron").exists() && !assets.join("ball.ron").exists());
+
+  // The holder of the lock can.
+  match ask(&mut editor, 2, move_ball).await {
+    ServerMessage::Response { result: Reply::Moved { edits, .. }, .. } => assert_eq!(edits.len(), 1),
+    other => panic!("{:?}", other),
+  }
+  assert!(read_asset(&dir, "prefabs/level.ron").contains("File(\"ball.ron\")"));
+
+  ask(&mut editor, 3, Request::Shutdown).await;
+  assert!(server.0.wait().unwrap().success());
+}
+
+#[tokio::test]
+async fn requests_need_their_capabilities() {
+  let dir = common::project();
+  write_asset(&dir, "prefabs/ball.ron", "(speed: 2.0)\n");
+  let (mut server, address) = start_server(dir.path()).await;
+  let mut bare = join(&address, "script", &[]).await;
+  let mut editor = join(&address, "editor", &[LOCKS]).await;
+
+  let path = || String::from("prefabs/ball.ron");
+  let needing = vec![
+    (Request::Subscribe { folder: None, types: Vec::new(), since: None }, SUBSCRIPTIONS),
+    (Request::Unsubscribe { subscription: 1 }, SUBSCRIPTIONS),
+    (Request::OpenAsset { path: path() }, LOCKS),
+    (Request::CloseAsset { path: path() }, LOCKS),
+    (Request::LockAsset { path: path(), lease_secs: 60 }, LOCKS),
+    (Request::UnlockAsset { path: path() }, LOCKS),
+    (Request::ListPresence, LOCKS),
+    (Request::BuildGame { release: false }, GAME),
+    (Request::RunGame { release: false, args: Vec::new() }, GAME),
+    (Request::StopGame, GAME),
+    (Request::RestartGame, GAME),
+    (Request::GameStatus, GAME),
+    (Request::WatchGame, GAME),
+    (Request::UnwatchGame, GAME),
+  ];
+  for (id, (request, capability)) in (1..).zip(needing) {
+    assert_eq!(request.capability(), Some(capability));
+    match ask(&mut bare, id, request.clone()).await {
+      ServerMessage::Error { error, .. } => {
+        assert_eq!(error.code, ErrorCode::Unsupported, "{:?}", request);
+        assert!(error.message.contains(capability), "{:?}: {}", request, error.message);
+      }
+      other => panic!("{:?}: {:?}", request, other),
+    }
+    let negotiated = ask(&mut editor, id, request.clone()).await;
+    let refused = matches!(&negotiated, ServerMessage::Error { error, .. } if error.message.contains("capability"));
+    assert_eq!(refused, capability != LOCKS, "{:?}: {:?}", request, negotiated);
+  }
+
+  // Requests without a capability work for everyone.
+  match ask(&mut bare, 100, Request::GetAsset { path: path() }).await {
+    ServerMessage::Response { result: Reply::Asset { .. }, .. } => {}
+    other => panic!("{:?}", other),
+  }
+  ask(&mut bare, 101, Request::Shutdown).await;
+  assert!(server.0.wait().unwrap().success());
+}
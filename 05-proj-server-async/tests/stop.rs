@@ -0,0 +1,50 @@
+use std::path::Path;
+use std::process::{Command, Output};
+use project_server::database::LIBRARY_FOLDER;
+use project_server::lock::{LockStatus, ProjectLock};
+use crate::common::{start_server, write_asset};
+
+mod common;
+
+// Stop Tests
+// ----------
+//
+// `proj-server-async <project> --stop`, which project-server's stop command runs, against a real server.
+//
+
+fn stop(project_path: &Path) -> Output {
+  Command::new(env!("CARGO_BIN_EXE_proj-server-async")).arg(project_path).arg("--stop").output().unwrap()
+}
+
+#[tokio::test]
+async fn stop_shuts_down_the_running_server() {
+  let dir = common::project();
+  write_asset(&dir, "prefabs/ball.ron", "(speed: 2.0)\n");
+  let (mut server, _) = start_server(dir.path()).await;
+
+  let output = stop(dir.path());
+  let printed = String::from_utf8_lossy(&output.stdout);
+  assert!(output.status.success(), "{}", printed);
+  assert!(printed.contains("Server stopped."), "{}", printed);
+  // It only returns once the server has let go of the project.
+  assert_eq!(ProjectLock::status(&dir.path().join(LIBRARY_FOLDER)), LockStatus::Unlocked);
+  assert!(server.0.wait().unwrap().success());
+}
+
+#[test]
+fn stop_without_a_server_does_nothing() {
+  let dir = common::project();
+  let output = stop(dir.path());
+  assert!(output.status.success());
+  assert!(String::from_utf8_lossy(&output.stdout).contains("No server is running"));
+}
+
+#[test]
+fn stop_leaves_other_commands_alone() {
+  let dir = common::project();
+  let library_path = dir.path().join(LIBRARY_FOLDER);
+  let _lock = ProjectLock::acquire(&library_path, "scan").unwrap();
+  let output = stop(dir.path());
+  assert!(!output.status.success());
+  assert!(String::from_utf8_lossy(&output.stdout).contains("`scan`"));
+}
@@ -0,0 +1,65 @@
+use std::{collections::HashMap, fs::File, io::Read, path::Path, sync::Mutex};
+
+use amethyst::{assets::Source, error::Error};
+use serde_derive::Deserialize;
+use sha2::{Digest, Sha256};
+use zip::ZipArchive;
+
+// The index the project server writes into every pack (see 01-project-server/src/pack.rs).
+const PACK_INDEX: &str = "theseus-pack.json";
+const PACK_FORMAT_VERSION: u32 = 1;
+
+#[derive(Deserialize)]
+struct PackIndex {
+    format_version: u32,
+    entries: Vec<PackEntry>,
+}
+
+#[derive(Deserialize)]
+struct PackEntry {
+    path: String,
+    size: u64,
+    hash: String,
+}
+
+// An asset source reading from a pack built with `project-server <project> pack`, in place of the assets folder.
+pub struct PackSource {
+    archive: Mutex<ZipArchive<File>>,
+    entries: HashMap<String, PackEntry>,
+}
+
+impl PackSource {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<PackSource, Error> {
+        let mut archive = ZipArchive::new(File::open(path.as_ref())?)?;
+        let index: PackIndex = {
+            let file = archive.by_name(PACK_INDEX)?;
+            serde_json::from_reader(file)?
+        };
+        if index.format_version != PACK_FORMAT_VERSION {
+            return Err(Error::from_string(format!("{:?}: unsupported pack format version {}", path.as_ref(), index.format_version)));
+        }
+        let entries = index.entries.into_iter().map(|entry| (entry.path.clone(), entry)).collect();
+        Ok(PackSource { archive: Mutex::new(archive), entries })
+    }
+}
+
+impl Source for PackSource {
+    fn modified(&self, path: &str) -> Result<u64, Error> {
+        // Packs don't change while the game runs, so there's nothing to hot reload.
+        if !self.entries.contains_key(path) {
+            return Err(Error::from_string(format!("{}: not in the pack", path)));
+        }
+        Ok(0)
+    }
+
+    fn load(&self, path: &str) -> Result<Vec<u8>, Error> {
+        let entry = self.entries.get(path).ok_or_else(|| Error::from_string(format!("{}: not in the pack", path)))?;
+        let mut archive = self.archive.lock().map_err(|_| Error::from_string("pack archive lock poisoned"))?;
+        let mut bytes = Vec::with_capacity(entry.size as usize);
+        archive.by_name(path)?.read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != entry.size || format!("{:x}", Sha256::digest(&bytes)) != entry.hash {
+            return Err(Error::from_string(format!("{}: contents don't match the pack index", path)));
+        }
+        Ok(bytes)
+    }
+}
@@ -3,3 +3,7 @@ pub const ARENA_HEIGHT: f32 = 600.0;
 
 pub const LIFE_TEXT_ID: &str = "life";
 pub const SCORE_TEXT_ID: &str = "score";
+
+pub const ASSETS_DIR: &str = "assets";
+// A shipped build loads its assets from the pack next to it instead of the assets folder.
+pub const ASSETS_PACK: &str = "assets.pack";
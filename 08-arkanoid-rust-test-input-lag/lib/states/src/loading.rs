@@ -1,6 +1,17 @@
 use crate::MainMenuState;
 
 use components::{ArkanoidPrefabData, CameraPrefabData, GamePrefabHandles, MenuPrefabHandles, PrefabHandles};
+use project_server::{
+    importer::find_file_references,
+    lfs::{self, LFS_FIX},
+};
+use resources::{ASSETS_DIR, ASSETS_PACK};
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
 
 use amethyst::{
     assets::{PrefabLoader, ProgressCounter, RonFormat},
@@ -10,9 +21,21 @@ use amethyst::{
     ui::UiLoader,
 };
 
+// Textures and fonts are kept in Git LFS (see .gitattributes). A checkout made without it has small text pointer files in
+// their place, or nothing at all, which Amethyst only reports as assets failing to load. The checks are the project
+// server's, so the game and the server agree on what's a pointer and what's a reference.
+
+enum AssetProblem {
+    // A Git LFS pointer checked out in place of the file.
+    LfsPointer(PathBuf),
+    // A file a RON file refers to that isn't there.
+    Missing { path: PathBuf, referenced_by: PathBuf },
+}
+
 #[derive(Default)]
 pub struct LoadingState {
     progress_counter: ProgressCounter,
+    asset_problems: Vec<AssetProblem>,
 }
 
 impl SimpleState for LoadingState {
@@ -22,6 +45,15 @@ impl SimpleState for LoadingState {
         // Show cursor
         *world.write_resource() = HideCursor { hide: false };
 
+        // A pack has every file it needs, but the assets folder may come from a checkout without Git LFS.
+        if !Path::new(ASSETS_PACK).exists() {
+            self.asset_problems = check_assets(Path::new(ASSETS_DIR));
+            if !self.asset_problems.is_empty() {
+                report_asset_problems(&self.asset_problems);
+                return;
+            }
+        }
+
         type SystemData<'s> = (UiLoader<'s>, PrefabLoader<'s, CameraPrefabData>, PrefabLoader<'s, SpriteScenePrefab>, PrefabLoader<'s, ArkanoidPrefabData>);
 
         let prefab_handles = world.exec(|(ui_loader, camera_loader, sprite_loader, arkanoid_loader): SystemData| PrefabHandles {
@@ -44,10 +76,17 @@ impl SimpleState for LoadingState {
     }
 
     fn update(&mut self, _data: &mut StateData<GameData>) -> SimpleTrans {
+        if !self.asset_problems.is_empty() {
+            return Trans::Quit;
+        }
+
         println!("Loading: {}%", 100 * self.progress_counter.num_finished() / self.progress_counter.num_assets());
 
         if self.progress_counter.num_failed() > 0 {
-            println!("Error when loading assets.");
+            println!("Error when loading assets:");
+            for error in self.progress_counter.errors() {
+                println!("  {}: {}", error.asset_name, error.error);
+            }
             return Trans::Quit;
         }
 
@@ -58,3 +97,51 @@ impl SimpleState for LoadingState {
         Trans::None
     }
 }
+
+// Finds the files in the assets folder that are Git LFS pointers, and the files RON files refer to that are missing.
+fn check_assets(assets_dir: &Path) -> Vec<AssetProblem> {
+    let mut files = Vec::new();
+    find_files(assets_dir, &mut files);
+    files.sort();
+
+    let mut problems = Vec::new();
+    // Each missing file is reported once, with the first file referring to it.
+    let mut missing = BTreeMap::new();
+    for file in files {
+        if lfs::is_pointer_file(&file) {
+            problems.push(AssetProblem::LfsPointer(file));
+        } else if file.extension().map_or(false, |ext| ext == "ron") {
+            let text = fs::read_to_string(&file).unwrap_or_default();
+            for reference in find_file_references(&text) {
+                let path = assets_dir.join(reference.path);
+                if !path.exists() {
+                    missing.entry(path).or_insert_with(|| file.clone());
+                }
+            }
+        }
+    }
+    problems.extend(missing.into_iter().map(|(path, referenced_by)| AssetProblem::Missing { path, referenced_by }));
+    problems
+}
+
+fn find_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            find_files(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
+
+fn report_asset_problems(problems: &[AssetProblem]) {
+    println!("Can't load assets, {} file(s) are missing:", problems.len());
+    for problem in problems {
+        match problem {
+            AssetProblem::LfsPointer(path) => println!("  {}: a Git LFS pointer instead of the file", path.display()),
+            AssetProblem::Missing { path, referenced_by } => println!("  {}: not found (used by {})", path.display(), referenced_by.display()),
+        }
+    }
+    println!("To get them, {} in the repository.", LFS_FIX);
+}
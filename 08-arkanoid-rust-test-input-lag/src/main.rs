@@ -1,9 +1,12 @@
 use bundle::StartingBundle;
 use components::{ArkanoidPrefabData, CameraPrefabData};
-use resources::CurrentState;
+use pack::PackSource;
+use resources::{CurrentState, ASSETS_DIR, ASSETS_PACK};
 use states::LoadingState;
 use systems::ArkanoidBundle;
 
+use std::path::Path;
+
 use amethyst::{assets::PrefabLoaderSystemDesc, core::frame_limiter::FrameRateLimitConfig, prelude::*, renderer::sprite::prefab::SpriteScenePrefab, LoggerConfig};
 
 fn main() -> amethyst::Result<()> {
@@ -19,8 +22,11 @@ fn main() -> amethyst::Result<()> {
         .with_system_desc(PrefabLoaderSystemDesc::<SpriteScenePrefab>::default(), "", &[])
         .with_system_desc(PrefabLoaderSystemDesc::<ArkanoidPrefabData>::default(), "", &[]);
 
-    Application::build("assets", LoadingState::default())?
-        .with_frame_limit_config(FrameRateLimitConfig::load("config/frame_limiter.ron")?)
+    let mut app = Application::build(ASSETS_DIR, LoadingState::default())?;
+    if Path::new(ASSETS_PACK).exists() {
+        app = app.with_default_source(PackSource::open(ASSETS_PACK)?);
+    }
+    app.with_frame_limit_config(FrameRateLimitConfig::load("config/frame_limiter.ron")?)
         .with_resource(CurrentState::default())
         .build(game_data)?
         .run();
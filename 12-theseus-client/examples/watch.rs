@@ -0,0 +1,40 @@
+use std::path::PathBuf;
+use theseus_client::{Client, SubscriptionEvent};
+
+// Lists the top level assets of the project in the folder given (or the current one), then prints every change to them
+// until Ctrl+C. Keeps going through server restarts: run it, restart the server, and change an asset.
+#[tokio::main]
+async fn main() {
+  let project_path = std::env::args().nth(1).map(PathBuf::from).unwrap_or_else(|| PathBuf::from("."));
+  let client = match Client::connect_to_project(&project_path).await {
+    Ok(client) => client,
+    Err(err) => {
+      println!("Error: {}", err);
+      return;
+    }
+  };
+  println!("Connected to {} for {:?}.", client.server().server, project_path);
+
+  match client.list_assets("").await {
+    Ok(assets) => {
+      for asset in assets {
+        println!("{:>10} {} ({})", asset.size, asset.path, asset.asset_type);
+      }
+    }
+    Err(err) => println!("Error listing assets: {}", err),
+  }
+
+  let mut changes = match client.subscribe_changes(None, &[]).await {
+    Ok(changes) => changes,
+    Err(err) => {
+      println!("Error subscribing to changes: {}", err);
+      return;
+    }
+  };
+  while let Some(event) = changes.next().await {
+    match event {
+      SubscriptionEvent::Changed(change) => println!("[{}] {:?}: {}", change.seq, change.kind, change.path),
+      SubscriptionEvent::Missed => println!("Missed some changes; the list above may be out of date."),
+    }
+  }
+}
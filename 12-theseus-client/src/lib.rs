@@ -0,0 +1,459 @@
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+use proj_server_async::protocol::{ProtocolError, Reply, Request, RequestError, Welcome};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time;
+use uuid::Uuid;
+use crate::manager::{Command, Filter, Target};
+
+pub use proj_server_async::protocol::{
+  AssetChange, AssetInfo, AssetLock, ChangeKind, Diagnostic, ErrorCode, GameProcess, OutputStream, Peer, PeerPresence,
+  ReferenceEdit, SheetPreview, Sprite, Thumbnail,
+};
+
+mod manager;
+
+// Theseus Client
+// --------------
+//
+// A typed client for the project server, so tools don't each have to speak the wire protocol themselves. A Client is a
+// handle to a manager task that owns the connection: every call sends it a command along with a channel to answer on,
+// like the command manager in 03-test-tokio-redis/examples/channels.rs, so any number of tasks can share one
+// connection by cloning the Client. The manager numbers requests and matches the answers up (see manager.rs).
+//
+// When the connection drops, requests waiting on it fail with Disconnected, since there's no telling whether the server
+// carried them out. The manager reconnects on its own and starts every subscription again from the last change it
+// delivered, so subscribers see each change once whether or not the connection dropped in between. Game watchers are
+// watching again once reconnected, though whatever the game wrote in between is lost. Open assets and locks belong to
+// the connection, so they're let go when it drops and have to be taken again.
+//
+
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+  // How the client introduces itself in the handshake.
+  pub name: String,
+  // How long a request (or connecting) can take, including any time spent reconnecting, before it fails with Timeout.
+  pub request_timeout: Duration,
+  // How long to wait before reconnecting. It doubles after every failed attempt, up to max_reconnect_delay.
+  pub reconnect_delay: Duration,
+  pub max_reconnect_delay: Duration,
+}
+
+impl Default for ClientConfig {
+  fn default() -> ClientConfig {
+    ClientConfig {
+      name: String::from("theseus-client"),
+      request_timeout: Duration::from_secs(10),
+      reconnect_delay: Duration::from_millis(100),
+      max_reconnect_delay: Duration::from_secs(5),
+    }
+  }
+}
+
+#[derive(Clone)]
+pub struct Client {
+  commands: mpsc::UnboundedSender<Command>,
+  request_timeout: Duration,
+  welcome: Welcome,
+}
+
+// Where an asset was moved to (or, for preview_move, where it still is) and the references rewritten to follow it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Moved {
+  pub asset: AssetInfo,
+  pub edits: Vec<ReferenceEdit>,
+}
+
+impl Client {
+  pub async fn connect(address: &str) -> Result<Client, ClientError> {
+    Client::connect_with(address, ClientConfig::default()).await
+  }
+
+  pub async fn connect_with(address: &str, config: ClientConfig) -> Result<Client, ClientError> {
+    Client::start(Target::Address(String::from(address)), config).await
+  }
+
+  // Connects to the server working on the project folder at `project_path`, wherever its lock file says it listens.
+  // Reconnects follow the server to whatever address it has after a restart.
+  pub async fn connect_to_project(project_path: &Path) -> Result<Client, ClientError> {
+    Client::connect_to_project_with(project_path, ClientConfig::default()).await
+  }
+
+  pub async fn connect_to_project_with(project_path: &Path, config: ClientConfig) -> Result<Client, ClientError> {
+    Client::start(Target::Project(project_path.to_path_buf()), config).await
+  }
+
+  async fn start(target: Target, config: ClientConfig) -> Result<Client, ClientError> {
+    let (connection, welcome) = manager::connect(&target, &config).await?;
+    let (commands, commands_rx) = mpsc::unbounded_channel();
+    let request_timeout = config.request_timeout;
+    tokio::spawn(manager::run(target, config, connection, commands_rx));
+    Ok(Client { commands, request_timeout, welcome })
+  }
+
+  // The server's answer to the first handshake: its name, protocol version and capabilities.
+  pub fn server(&self) -> &Welcome {
+    &self.welcome
+  }
+
+  pub async fn ping(&self) -> Result<(), ClientError> {
+    match self.request(Request::Ping).await? {
+      Reply::Pong => Ok(()),
+      reply => Err(unexpected(reply)),
+    }
+  }
+
+  // The assets directly in a folder, by path; "" is the asset root.
+  pub async fn list_assets(&self, folder: &str) -> Result<Vec<AssetInfo>, ClientError> {
+    match self.request(Request::ListFolder { path: String::from(folder) }).await? {
+      Reply::Assets { assets } => Ok(assets),
+      reply => Err(unexpected(reply)),
+    }
+  }
+
+  pub async fn get_asset(&self, path: &str) -> Result<AssetInfo, ClientError> {
+    match self.request(Request::GetAsset { path: String::from(path) }).await? {
+      Reply::Asset { asset } => Ok(asset),
+      reply => Err(unexpected(reply)),
+    }
+  }
+
+  // The path of the asset with a GUID.
+  pub async fn resolve_guid(&self, guid: Uuid) -> Result<String, ClientError> {
+    match self.request(Request::ResolveGuid { guid }).await? {
+      Reply::Path { path } => Ok(path),
+      reply => Err(unexpected(reply)),
+    }
+  }
+
+  // The assets matching a query such as "type:texture label:ui name:*menu*", by path, at most `limit` of them.
+  pub async fn search(&self, query: &str, limit: Option<usize>) -> Result<Vec<AssetInfo>, ClientError> {
+    match self.request(Request::Search { query: String::from(query), limit }).await? {
+      Reply::Assets { assets } => Ok(assets),
+      reply => Err(unexpected(reply)),
+    }
+  }
+
+  // Moves (or renames) an asset, along with its meta file, and rewrites the references to it.
+  pub async fn move_asset(&self, from: &str, to: &str) -> Result<Moved, ClientError> {
+    self.move_request(Request::MoveAsset { from: String::from(from), to: String::from(to), dry_run: false }).await
+  }
+
+  // What move_asset would rewrite, without moving anything.
+  pub async fn preview_move(&self, from: &str, to: &str) -> Result<Moved, ClientError> {
+    self.move_request(Request::MoveAsset { from: String::from(from), to: String::from(to), dry_run: true }).await
+  }
+
+  // Renames an asset within its folder, like move_asset. `name` is the new file name, without a folder.
+  pub async fn rename_asset(&self, path: &str, name: &str) -> Result<Moved, ClientError> {
+    self.move_request(Request::RenameAsset { path: String::from(path), name: String::from(name), dry_run: false }).await
+  }
+
+  pub async fn delete_asset(&self, path: &str) -> Result<(), ClientError> {
+    self.done(Request::DeleteAsset { path: String::from(path) }).await
+  }
+
+  // A PNG thumbnail of a texture, drawn into the server's cache.
+  pub async fn thumbnail(&self, path: &str) -> Result<Thumbnail, ClientError> {
+    match self.request(Request::GetThumbnail { path: String::from(path) }).await? {
+      Reply::Thumbnail { thumbnail } => Ok(thumbnail),
+      reply => Err(unexpected(reply)),
+    }
+  }
+
+  // Previews of the sprite sheets in a RON file, with every sprite outlined and numbered.
+  pub async fn sprite_previews(&self, path: &str) -> Result<Vec<SheetPreview>, ClientError> {
+    match self.request(Request::GetSpritePreviews { path: String::from(path) }).await? {
+      Reply::SpritePreviews { previews } => Ok(previews),
+      reply => Err(unexpected(reply)),
+    }
+  }
+
+  // The latest change's number and the changes after `since`, for tools that poll rather than subscribe.
+  pub async fn changes_since(&self, since: Option<u64>) -> Result<(u64, Vec<AssetChange>), ClientError> {
+    match self.request(Request::ChangesSince { since }).await? {
+      Reply::Changes { cursor, changes } => Ok((cursor, changes)),
+      reply => Err(unexpected(reply)),
+    }
+  }
+
+  // Changes to assets in `folder` (or anywhere, for None) of the given types (or any type, for none), from now on.
+  pub async fn subscribe_changes(&self, folder: Option<&str>, types: &[&str]) -> Result<Subscription, ClientError> {
+    let types = types.iter().map(|name| String::from(*name)).collect();
+    let filter = Filter { folder: folder.map(String::from), types };
+    let (events, events_rx) = mpsc::unbounded_channel();
+    let (respond, response) = oneshot::channel();
+    self.commands.send(Command::Subscribe { filter, events, respond }).map_err(|_| ClientError::Closed)?;
+    let id = self.answer(response).await?;
+    Ok(Subscription { id, events: events_rx, commands: self.commands.clone() })
+  }
+
+  // Tells the other clients this one has an asset open.
+  pub async fn open_asset(&self, path: &str) -> Result<(), ClientError> {
+    self.done(Request::OpenAsset { path: String::from(path) }).await
+  }
+
+  pub async fn close_asset(&self, path: &str) -> Result<(), ClientError> {
+    self.done(Request::CloseAsset { path: String::from(path) }).await
+  }
+
+  // Locks an asset (a folder with everything in it) so other clients can't move or delete it, for `lease` or until
+  // it's unlocked. Locking it again renews the lease. Fails with the asset_locked error code if someone else holds it.
+  pub async fn lock_asset(&self, path: &str, lease: Duration) -> Result<AssetLock, ClientError> {
+    let lease_secs = lease.as_secs() + if lease.subsec_nanos() > 0 { 1 } else { 0 };
+    match self.request(Request::LockAsset { path: String::from(path), lease_secs }).await? {
+      Reply::Locked { lock } => Ok(lock),
+      reply => Err(unexpected(reply)),
+    }
+  }
+
+  pub async fn unlock_asset(&self, path: &str) -> Result<(), ClientError> {
+    self.done(Request::UnlockAsset { path: String::from(path) }).await
+  }
+
+  // Who's connected, what they have open and what's locked.
+  pub async fn presence(&self) -> Result<PresenceInfo, ClientError> {
+    match self.request(Request::ListPresence).await? {
+      Reply::Presence { you, peers, locks } => Ok(PresenceInfo { you, peers, locks }),
+      reply => Err(unexpected(reply)),
+    }
+  }
+
+  // Every change to presence from now on.
+  pub fn watch_presence(&self) -> PresenceEvents {
+    let (events, events_rx) = mpsc::unbounded_channel();
+    // If the manager is gone, the receiver ends right away.
+    let _ = self.commands.send(Command::WatchPresence { events });
+    PresenceEvents { events: events_rx }
+  }
+
+  // Builds the project's game with cargo build. Answered once the build has started; watch_game follows it from there.
+  pub async fn build_game(&self, release: bool) -> Result<GameProcess, ClientError> {
+    self.start_game(Request::BuildGame { release }).await
+  }
+
+  // Builds and runs the project's game with cargo run, passing `args` on to the game.
+  pub async fn run_game(&self, release: bool, args: &[&str]) -> Result<GameProcess, ClientError> {
+    let args = args.iter().map(|arg| String::from(*arg)).collect();
+    self.start_game(Request::RunGame { release, args }).await
+  }
+
+  // Stops the build or run in progress, answered once it has exited. None if nothing was ever started.
+  pub async fn stop_game(&self) -> Result<Option<GameProcess>, ClientError> {
+    self.game(Request::StopGame).await
+  }
+
+  // Stops the build or run in progress, if any, and starts the last one again.
+  pub async fn restart_game(&self) -> Result<GameProcess, ClientError> {
+    self.start_game(Request::RestartGame).await
+  }
+
+  // The build or run in progress, or else the last one. None if nothing was ever started.
+  pub async fn game_status(&self) -> Result<Option<GameProcess>, ClientError> {
+    self.game(Request::GameStatus).await
+  }
+
+  // Every game event from now on, until the watcher is dropped.
+  pub async fn watch_game(&self) -> Result<GameEvents, ClientError> {
+    let (events, events_rx) = mpsc::unbounded_channel();
+    let (respond, response) = oneshot::channel();
+    self.commands.send(Command::WatchGame { events, respond }).map_err(|_| ClientError::Closed)?;
+    match self.answer(response).await? {
+      Reply::Done => Ok(GameEvents { events: events_rx }),
+      reply => Err(unexpected(reply)),
+    }
+  }
+
+  async fn game(&self, request: Request) -> Result<Option<GameProcess>, ClientError> {
+    match self.request(request).await? {
+      Reply::Game { process } => Ok(process),
+      reply => Err(unexpected(reply)),
+    }
+  }
+
+  async fn start_game(&self, request: Request) -> Result<GameProcess, ClientError> {
+    match self.request(request).await? {
+      Reply::Game { process: Some(process) } => Ok(process),
+      reply => Err(unexpected(reply)),
+    }
+  }
+
+  async fn done(&self, request: Request) -> Result<(), ClientError> {
+    match self.request(request).await? {
+      Reply::Done => Ok(()),
+      reply => Err(unexpected(reply)),
+    }
+  }
+
+  async fn move_request(&self, request: Request) -> Result<Moved, ClientError> {
+    match self.request(request).await? {
+      Reply::Moved { asset, edits } => Ok(Moved { asset, edits }),
+      reply => Err(unexpected(reply)),
+    }
+  }
+
+  async fn request(&self, request: Request) -> Result<Reply, ClientError> {
+    let (respond, response) = oneshot::channel();
+    self.commands.send(Command::Request { request, respond }).map_err(|_| ClientError::Closed)?;
+    self.answer(response).await
+  }
+
+  async fn answer<T>(&self, response: oneshot::Receiver<Result<T, ClientError>>) -> Result<T, ClientError> {
+    match time::timeout(self.request_timeout, response).await {
+      Ok(Ok(result)) => result,
+      Ok(Err(_)) => Err(ClientError::Closed),
+      Err(_) => Err(ClientError::Timeout),
+    }
+  }
+}
+
+fn unexpected(reply: Reply) -> ClientError {
+  ClientError::UnexpectedReply(format!("{:?}", reply))
+}
+
+// Subscriptions
+// -------------
+//
+// A subscription lasts until it's dropped, across reconnects and even when the server can't keep up with it: the
+// client starts it again and tells the subscriber it missed changes.
+//
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubscriptionEvent {
+  Changed(AssetChange),
+  // Some changes were missed, because the subscriber fell too far behind or the client was disconnected for longer
+  // than the server remembers changes. Anything built from earlier changes should be listed again.
+  Missed,
+}
+
+pub struct Subscription {
+  id: u64,
+  events: mpsc::UnboundedReceiver<SubscriptionEvent>,
+  commands: mpsc::UnboundedSender<Command>,
+}
+
+impl Subscription {
+  // The next event, or None if the subscription ended: the server refused to start it again after a reconnect, or the
+  // client is gone.
+  pub async fn next(&mut self) -> Option<SubscriptionEvent> {
+    self.events.recv().await
+  }
+}
+
+impl Drop for Subscription {
+  fn drop(&mut self) {
+    let _ = self.commands.send(Command::Unsubscribe { subscription: self.id });
+  }
+}
+
+// Presence
+// --------
+//
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PresenceInfo {
+  // This client, as the server knows it.
+  pub you: Peer,
+  pub peers: Vec<PeerPresence>,
+  pub locks: Vec<AssetLock>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PresenceEvent {
+  Opened { peer: Peer, path: String },
+  Closed { peer: Peer, path: String },
+  // An asset was locked, or its lease renewed.
+  Locked(AssetLock),
+  // The holder unlocked the asset, disconnected, or let the lease run out.
+  Unlocked { path: String, holder: Peer },
+  // The client reconnected. Its own open assets and locks were let go when the connection dropped, and other changes
+  // may have been missed in between, so presence should be listed again.
+  Reconnected,
+}
+
+pub struct PresenceEvents {
+  events: mpsc::UnboundedReceiver<PresenceEvent>,
+}
+
+impl PresenceEvents {
+  // The next event, or None once the client is gone.
+  pub async fn next(&mut self) -> Option<PresenceEvent> {
+    self.events.recv().await
+  }
+}
+
+// Game
+// ----
+//
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GameEvent {
+  Started { process: u64, command: String },
+  // A line the game or cargo wrote.
+  Output { process: u64, stream: OutputStream, line: String },
+  // An error, warning or note from the compiler.
+  Diagnostic { process: u64, diagnostic: Diagnostic },
+  // Cargo finished building; for a run, the game starts next if the build succeeded.
+  Built { process: u64, success: bool },
+  // `code` is None when the process was killed; `stopped` says whether a client stopped it.
+  Exited { process: u64, code: Option<i32>, stopped: bool },
+  // The client reconnected and is watching again. Anything in between was missed, so ask for the game's status again.
+  Reconnected,
+}
+
+pub struct GameEvents {
+  events: mpsc::UnboundedReceiver<GameEvent>,
+}
+
+impl GameEvents {
+  // The next event, or None once the client is gone.
+  pub async fn next(&mut self) -> Option<GameEvent> {
+    self.events.recv().await
+  }
+}
+
+// Errors
+// ------
+//
+
+#[derive(Debug)]
+pub enum ClientError {
+  // Connecting or the handshake failed.
+  Connect(ProtocolError),
+  // The server couldn't carry out the request.
+  Request(RequestError),
+  // The request wasn't answered within the request timeout.
+  Timeout,
+  // The connection dropped before the request was answered, so it may or may not have been carried out.
+  Disconnected,
+  // The task managing the connection is gone.
+  Closed,
+  // The server answered with the wrong kind of reply for the request.
+  UnexpectedReply(String),
+}
+
+impl ClientError {
+  // The server's error code, for errors that came from the server.
+  pub fn code(&self) -> Option<ErrorCode> {
+    match self {
+      ClientError::Request(err) => Some(err.code),
+      _ => None,
+    }
+  }
+}
+
+impl fmt::Display for ClientError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ClientError::Connect(err) => write!(f, "couldn't connect to the project server: {}", err),
+      ClientError::Request(err) => write!(f, "{}", err),
+      ClientError::Timeout => write!(f, "the project server didn't answer in time"),
+      ClientError::Disconnected => write!(f, "disconnected from the project server before it answered"),
+      ClientError::Closed => write!(f, "the client is closed"),
+      ClientError::UnexpectedReply(reply) => write!(f, "unexpected reply from the project server: {}", reply),
+    }
+  }
+}
+
+impl std::error::Error for ClientError {}
@@ -0,0 +1,455 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::io;
+use std::mem;
+use std::path::PathBuf;
+use proj_server_async::protocol::{
+  self, ClientMessage, ErrorCode, Event, ProtocolError, Reply, Request, RequestError, ServerMessage, Welcome, GAME,
+  LOCKS, SUBSCRIPTIONS,
+};
+use project_server::database::LIBRARY_FOLDER;
+use project_server::lock::ProjectLock;
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time;
+use crate::{ClientConfig, ClientError, GameEvent, PresenceEvent, SubscriptionEvent};
+
+// Connection Manager
+// ------------------
+//
+// The manager task is the only one that touches the connection. It gives every request the next id and keeps what it
+// needs to answer the caller until the server's answer with that id comes back, in whatever order the answers come.
+// Subscriptions have ids of their own on the client, since the server numbers them again whenever they're started
+// again. A task reading the socket hands the manager whole messages, so the manager can wait on those and on commands
+// at once without ever stopping halfway through a frame.
+//
+
+pub type Responder<T> = oneshot::Sender<Result<T, ClientError>>;
+
+pub enum Command {
+  Request { request: Request, respond: Responder<Reply> },
+  // Answered with the client's id for the subscription.
+  Subscribe { filter: Filter, events: mpsc::UnboundedSender<SubscriptionEvent>, respond: Responder<u64> },
+  Unsubscribe { subscription: u64 },
+  WatchPresence { events: mpsc::UnboundedSender<PresenceEvent> },
+  // Answered once the server is sending game events.
+  WatchGame { events: mpsc::UnboundedSender<GameEvent>, respond: Responder<Reply> },
+}
+
+#[derive(Debug, Clone)]
+pub struct Filter {
+  pub folder: Option<String>,
+  pub types: Vec<String>,
+}
+
+// Where the server is.
+#[derive(Debug, Clone)]
+pub enum Target {
+  Address(String),
+  // The server working on a project folder, at the address in the project's lock file. It's read again on every
+  // reconnect, since a restarted server usually listens on another port.
+  Project(PathBuf),
+}
+
+impl Target {
+  fn address(&self) -> io::Result<String> {
+    match self {
+      Target::Address(address) => Ok(address.clone()),
+      Target::Project(path) => match ProjectLock::server_address(&path.join(LIBRARY_FOLDER)) {
+        Some(address) => Ok(address.to_string()),
+        None => {
+          let message = format!("no server is running on the project {:?}", path);
+          Err(io::Error::new(io::ErrorKind::NotFound, message))
+        }
+      },
+    }
+  }
+}
+
+// How many messages from the server can wait for the manager before the reader stops reading.
+const INCOMING_LEN: usize = 256;
+
+pub struct Connection {
+  writer: OwnedWriteHalf,
+  incoming: mpsc::Receiver<ServerMessage>,
+}
+
+// Connects and shakes hands, within the request timeout.
+pub async fn connect(target: &Target, config: &ClientConfig) -> Result<(Connection, Welcome), ClientError> {
+  let connecting = async {
+    let mut stream = TcpStream::connect(target.address()?).await?;
+    let welcome = protocol::client_handshake(&mut stream, &config.name, &[SUBSCRIPTIONS, LOCKS, GAME]).await?;
+    Ok::<_, ProtocolError>((stream, welcome))
+  };
+  let (stream, welcome) = match time::timeout(config.request_timeout, connecting).await {
+    Ok(connected) => connected.map_err(ClientError::Connect)?,
+    Err(_) => return Err(ClientError::Timeout),
+  };
+  let (mut reader, writer) = stream.into_split();
+  let (mut incoming_tx, incoming) = mpsc::channel(INCOMING_LEN);
+  // Ends when the connection does, which closes `incoming`.
+  tokio::spawn(async move {
+    while let Ok(Some(message)) = protocol::read_message(&mut reader).await {
+      if incoming_tx.send(message).await.is_err() {
+        break;
+      }
+    }
+  });
+  Ok((Connection { writer, incoming }, welcome))
+}
+
+// Serves commands until every handle to the client is gone, reconnecting whenever the connection drops.
+pub async fn run(
+  target: Target,
+  config: ClientConfig,
+  connection: Connection,
+  commands: mpsc::UnboundedReceiver<Command>,
+) {
+  let mut manager = Manager {
+    commands,
+    next_id: 1,
+    next_subscription: 1,
+    pending: HashMap::new(),
+    subscriptions: HashMap::new(),
+    watchers: Vec::new(),
+    game_watchers: Vec::new(),
+    queued: Vec::new(),
+  };
+  let mut connection = connection;
+  while manager.serve(&mut connection).await {
+    manager.disconnected();
+    connection = match manager.reconnect(&target, &config).await {
+      Some(connection) => connection,
+      None => break,
+    };
+    manager.tell_watchers(PresenceEvent::Reconnected);
+    manager.tell_game_watchers(GameEvent::Reconnected);
+  }
+}
+
+struct Subscription {
+  filter: Filter,
+  events: mpsc::UnboundedSender<SubscriptionEvent>,
+  // The last change delivered, or where the server started the subscription if none has been. Starting again from
+  // here after a reconnect delivers exactly the changes missed in between.
+  cursor: Option<u64>,
+  // The server's id for the subscription, while it's running.
+  server_id: Option<u64>,
+}
+
+enum Pending {
+  Request(Responder<Reply>),
+  // `respond` is None when a subscription is being started again rather than for the first time.
+  Subscribe { subscription: u64, respond: Option<Responder<u64>> },
+  // Nobody's waiting for the answer, as when unsubscribing.
+  Ignored,
+}
+
+struct Manager {
+  commands: mpsc::UnboundedReceiver<Command>,
+  next_id: u64,
+  next_subscription: u64,
+  // Requests sent on the current connection that haven't been answered yet, by id.
+  pending: HashMap<u64, Pending>,
+  subscriptions: HashMap<u64, Subscription>,
+  // Where presence events go.
+  watchers: Vec<mpsc::UnboundedSender<PresenceEvent>>,
+  // Where game events go. The server sends them while there are any.
+  game_watchers: Vec<mpsc::UnboundedSender<GameEvent>>,
+  // Commands that came in while disconnected.
+  queued: Vec<Command>,
+}
+
+impl Manager {
+  // Returns false once every handle to the client is gone, and true when the connection drops.
+  async fn serve(&mut self, connection: &mut Connection) -> bool {
+    let subscriptions: Vec<u64> = self.subscriptions.keys().cloned().collect();
+    for subscription in subscriptions {
+      if self.subscribe(connection, subscription, None).await.is_err() {
+        return true;
+      }
+    }
+    if !self.game_watchers.is_empty() {
+      let id = self.pend(Pending::Ignored);
+      if send(connection, id, Request::WatchGame).await.is_err() {
+        return true;
+      }
+    }
+    for command in mem::take(&mut self.queued) {
+      if self.command(connection, command).await.is_err() {
+        return true;
+      }
+    }
+    loop {
+      let sent = tokio::select! {
+        command = self.commands.recv() => match command {
+          Some(command) => self.command(connection, command).await,
+          None => return false,
+        },
+        message = connection.incoming.recv() => match message {
+          Some(message) => self.message(connection, message).await,
+          None => return true,
+        },
+      };
+      if sent.is_err() {
+        return true;
+      }
+    }
+  }
+
+  async fn command(&mut self, connection: &mut Connection, command: Command) -> Result<(), ProtocolError> {
+    match command {
+      Command::Request { request, respond } => {
+        // The caller gave up waiting, most likely while the client was reconnecting. Nobody would hear whether it
+        // worked, so it's better not to move or delete anything.
+        if respond.is_closed() {
+          return Ok(());
+        }
+        let id = self.pend(Pending::Request(respond));
+        send(connection, id, request).await
+      }
+      Command::Subscribe { filter, events, respond } => {
+        let subscription = self.next_subscription;
+        self.next_subscription += 1;
+        self.subscriptions.insert(subscription, Subscription { filter, events, cursor: None, server_id: None });
+        self.subscribe(connection, subscription, Some(respond)).await
+      }
+      Command::Unsubscribe { subscription } => match self.subscriptions.remove(&subscription) {
+        Some(Subscription { server_id: Some(server_id), .. }) => self.unsubscribe(connection, server_id).await,
+        // Not running on the server (yet): if it's being started, it's unsubscribed once it is.
+        _ => Ok(()),
+      },
+      Command::WatchPresence { events } => {
+        self.watchers.push(events);
+        Ok(())
+      }
+      Command::WatchGame { events, respond } => {
+        let watching = !self.game_watchers.is_empty();
+        self.game_watchers.push(events);
+        if watching {
+          let _ = respond.send(Ok(Reply::Done));
+          return Ok(());
+        }
+        let id = self.pend(Pending::Request(respond));
+        send(connection, id, Request::WatchGame).await
+      }
+    }
+  }
+
+  async fn message(&mut self, connection: &mut Connection, message: ServerMessage) -> Result<(), ProtocolError> {
+    match message {
+      ServerMessage::Response { id, result } => self.answer(connection, id, Ok(result)).await,
+      ServerMessage::Error { id, error } => self.answer(connection, id, Err(error)).await,
+      ServerMessage::Event { event } => self.event(connection, event).await,
+      // Only part of the handshake.
+      ServerMessage::Welcome(_) | ServerMessage::Rejected { .. } => Ok(()),
+    }
+  }
+
+  async fn answer(
+    &mut self,
+    connection: &mut Connection,
+    id: u64,
+    result: Result<Reply, RequestError>,
+  ) -> Result<(), ProtocolError> {
+    let (subscription, respond) = match self.pending.remove(&id) {
+      Some(Pending::Request(respond)) => {
+        let _ = respond.send(result.map_err(ClientError::Request));
+        return Ok(());
+      }
+      Some(Pending::Subscribe { subscription, respond }) => (subscription, respond),
+      Some(Pending::Ignored) | None => return Ok(()),
+    };
+    match result {
+      Ok(Reply::Subscribed { subscription: server_id, cursor }) => match self.subscriptions.get_mut(&subscription) {
+        Some(state) => {
+          state.server_id = Some(server_id);
+          state.cursor = state.cursor.or(Some(cursor));
+          if let Some(respond) = respond {
+            let _ = respond.send(Ok(subscription));
+          }
+          Ok(())
+        }
+        // Dropped while it was being started.
+        None => self.unsubscribe(connection, server_id).await,
+      },
+      // The server no longer has the changes since the cursor; start from now instead.
+      Err(ref error) if error.code == ErrorCode::CursorExpired && self.subscriptions.contains_key(&subscription) => {
+        let state = self.subscriptions.get_mut(&subscription).expect("subscription exists");
+        state.cursor = None;
+        let _ = state.events.send(SubscriptionEvent::Missed);
+        self.subscribe(connection, subscription, respond).await
+      }
+      result => {
+        // Dropping the events sender ends the subscription for the subscriber.
+        self.subscriptions.remove(&subscription);
+        if let Some(respond) = respond {
+          let _ = respond.send(Err(match result {
+            Err(error) => ClientError::Request(error),
+            Ok(reply) => ClientError::UnexpectedReply(format!("{:?}", reply)),
+          }));
+        }
+        Ok(())
+      }
+    }
+  }
+
+  async fn event(&mut self, connection: &mut Connection, event: Event) -> Result<(), ProtocolError> {
+    match event {
+      Event::AssetChanged { subscription: server_id, change } => {
+        if let Some((_, state)) = self.running(server_id) {
+          state.cursor = Some(change.seq);
+          // A subscriber that's gone has already asked to unsubscribe.
+          let _ = state.events.send(SubscriptionEvent::Changed(change));
+        }
+        Ok(())
+      }
+      // The subscriber fell behind the server's buffer, but the server may still have the changes in its log.
+      Event::SubscriptionExpired { subscription: server_id } => match self.running(server_id) {
+        Some((subscription, state)) => {
+          state.server_id = None;
+          self.subscribe(connection, subscription, None).await
+        }
+        None => Ok(()),
+      },
+      // The connection closes next, and the client reconnects once the server is back.
+      Event::ServerShutdown => Ok(()),
+      Event::AssetOpened { peer, path } => {
+        self.tell_watchers(PresenceEvent::Opened { peer, path });
+        Ok(())
+      }
+      Event::AssetClosed { peer, path } => {
+        self.tell_watchers(PresenceEvent::Closed { peer, path });
+        Ok(())
+      }
+      Event::AssetLocked { lock } => {
+        self.tell_watchers(PresenceEvent::Locked(lock));
+        Ok(())
+      }
+      Event::AssetUnlocked { path, holder } => {
+        self.tell_watchers(PresenceEvent::Unlocked { path, holder });
+        Ok(())
+      }
+      Event::GameStarted { process, command } => {
+        self.game_event(connection, GameEvent::Started { process, command }).await
+      }
+      Event::GameOutput { process, stream, line } => {
+        self.game_event(connection, GameEvent::Output { process, stream, line }).await
+      }
+      Event::GameDiagnostic { process, diagnostic } => {
+        self.game_event(connection, GameEvent::Diagnostic { process, diagnostic }).await
+      }
+      Event::GameBuilt { process, success } => {
+        self.game_event(connection, GameEvent::Built { process, success }).await
+      }
+      Event::GameExited { process, code, stopped } => {
+        self.game_event(connection, GameEvent::Exited { process, code, stopped }).await
+      }
+    }
+  }
+
+  // Stops the game events once the last watcher is gone.
+  async fn game_event(&mut self, connection: &mut Connection, event: GameEvent) -> Result<(), ProtocolError> {
+    if self.game_watchers.is_empty() {
+      return Ok(());
+    }
+    self.tell_game_watchers(event);
+    if !self.game_watchers.is_empty() {
+      return Ok(());
+    }
+    let id = self.pend(Pending::Ignored);
+    send(connection, id, Request::UnwatchGame).await
+  }
+
+  fn tell_game_watchers(&mut self, event: GameEvent) {
+    self.game_watchers.retain(|watcher| watcher.send(event.clone()).is_ok());
+  }
+
+  // Drops the watchers that are gone.
+  fn tell_watchers(&mut self, event: PresenceEvent) {
+    self.watchers.retain(|watcher| watcher.send(event.clone()).is_ok());
+  }
+
+  // The subscription with the server's id `server_id`.
+  fn running(&mut self, server_id: u64) -> Option<(u64, &mut Subscription)> {
+    let found = self.subscriptions.iter_mut().find(|(_, state)| state.server_id == Some(server_id));
+    found.map(|(subscription, state)| (*subscription, state))
+  }
+
+  // Starts a subscription on the server, from its cursor if it has one.
+  async fn subscribe(
+    &mut self,
+    connection: &mut Connection,
+    subscription: u64,
+    respond: Option<Responder<u64>>,
+  ) -> Result<(), ProtocolError> {
+    let state = &self.subscriptions[&subscription];
+    let Filter { folder, types } = state.filter.clone();
+    let request = Request::Subscribe { folder, types, since: state.cursor };
+    let id = self.pend(Pending::Subscribe { subscription, respond });
+    send(connection, id, request).await
+  }
+
+  async fn unsubscribe(&mut self, connection: &mut Connection, server_id: u64) -> Result<(), ProtocolError> {
+    let id = self.pend(Pending::Ignored);
+    send(connection, id, Request::Unsubscribe { subscription: server_id }).await
+  }
+
+  fn pend(&mut self, pending: Pending) -> u64 {
+    let id = self.next_id;
+    self.next_id += 1;
+    self.pending.insert(id, pending);
+    id
+  }
+
+  // Fails the requests the connection took with it. Subscriptions carry on once reconnected, except for those that
+  // were never started, whose callers are still waiting to hear whether they were.
+  fn disconnected(&mut self) {
+    for (_, pending) in self.pending.drain() {
+      match pending {
+        Pending::Request(respond) => {
+          let _ = respond.send(Err(ClientError::Disconnected));
+        }
+        Pending::Subscribe { subscription, respond: Some(respond) } => {
+          self.subscriptions.remove(&subscription);
+          let _ = respond.send(Err(ClientError::Disconnected));
+        }
+        Pending::Subscribe { respond: None, .. } | Pending::Ignored => {}
+      }
+    }
+    for state in self.subscriptions.values_mut() {
+      state.server_id = None;
+    }
+  }
+
+  // Tries to connect again, backing off, until it works or every handle to the client is gone. Commands that come in
+  // meanwhile wait for the connection.
+  async fn reconnect(&mut self, target: &Target, config: &ClientConfig) -> Option<Connection> {
+    let mut delay = config.reconnect_delay;
+    loop {
+      let mut wait = time::delay_for(delay);
+      loop {
+        tokio::select! {
+          _ = &mut wait => break,
+          command = self.commands.recv() => match command {
+            // Nothing's running on the server to unsubscribe from.
+            Some(Command::Unsubscribe { subscription }) => {
+              self.subscriptions.remove(&subscription);
+            }
+            Some(command) => self.queued.push(command),
+            None => return None,
+          },
+        }
+      }
+      match connect(target, config).await {
+        Ok((connection, _)) => return Some(connection),
+        Err(_) => delay = cmp::min(delay * 2, config.max_reconnect_delay),
+      }
+    }
+  }
+}
+
+async fn send(connection: &mut Connection, id: u64, request: Request) -> Result<(), ProtocolError> {
+  protocol::write_message(&mut connection.writer, &ClientMessage::Request { id, request }).await
+}
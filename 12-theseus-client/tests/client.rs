@@ -0,0 +1,341 @@
+use std::time::Duration;
+use proj_server_async::protocol::{
+  self, ClientMessage, ErrorCode, Event, Reply, Request, RequestError, ServerMessage, GAME, LOCKS, SUBSCRIPTIONS,
+};
+use project_server::database::LIBRARY_FOLDER;
+use project_server::lock::ProjectLock;
+use theseus_client::{
+  AssetChange, AssetInfo, AssetLock, ChangeKind, Client, ClientConfig, ClientError, GameEvent, GameProcess,
+  OutputStream, Peer, PresenceEvent, SubscriptionEvent,
+};
+use tokio::net::{TcpListener, TcpStream};
+use uuid::Uuid;
+
+// Client Tests
+// ------------
+//
+// The client against a stand-in server that answers exactly as each test scripts it.
+//
+
+async fn listen() -> (TcpListener, String) {
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let address = listener.local_addr().unwrap().to_string();
+  (listener, address)
+}
+
+async fn accept(listener: &mut TcpListener) -> TcpStream {
+  let (mut stream, _) = listener.accept().await.unwrap();
+  let (hello, _) = protocol::server_handshake(&mut stream, "test", &[SUBSCRIPTIONS, LOCKS, GAME]).await.unwrap();
+  assert!(hello.has_capability(LOCKS) && hello.has_capability(GAME));
+  stream
+}
+
+fn config() -> ClientConfig {
+  ClientConfig {
+    name: String::from("test"),
+    request_timeout: Duration::from_secs(5),
+    reconnect_delay: Duration::from_millis(10),
+    max_reconnect_delay: Duration::from_millis(50),
+  }
+}
+
+async fn next_request(stream: &mut TcpStream) -> (u64, Request) {
+  match protocol::read_message(stream).await.unwrap() {
+    Some(ClientMessage::Request { id, request }) => (id, request),
+    other => panic!("expected a request, got {:?}", other),
+  }
+}
+
+async fn send(stream: &mut TcpStream, message: ServerMessage) {
+  protocol::write_message(stream, &message).await.unwrap();
+}
+
+async fn respond(stream: &mut TcpStream, id: u64, result: Reply) {
+  send(stream, ServerMessage::Response { id, result }).await;
+}
+
+fn asset(path: &str) -> AssetInfo {
+  AssetInfo {
+    guid: Uuid::from_u128(0x1234),
+    path: String::from(path),
+    asset_type: String::from("texture"),
+    is_folder: false,
+    size: 10,
+    modified: 0,
+    hash: String::new(),
+  }
+}
+
+fn change(seq: u64) -> AssetChange {
+  AssetChange {
+    seq,
+    kind: ChangeKind::Modified,
+    guid: Uuid::from_u128(0x1234),
+    path: String::from("logo.png"),
+    from: None,
+    asset_type: String::from("texture"),
+  }
+}
+
+async fn changed(stream: &mut TcpStream, subscription: u64, seq: u64) {
+  send(stream, ServerMessage::Event { event: Event::AssetChanged { subscription, change: change(seq) } }).await;
+}
+
+#[tokio::test]
+async fn answers_reach_their_requests_in_any_order() {
+  let (mut listener, address) = listen().await;
+  let server = tokio::spawn(async move {
+    let mut stream = accept(&mut listener).await;
+    let first = next_request(&mut stream).await;
+    let second = next_request(&mut stream).await;
+    for (id, request) in [second, first].iter().cloned() {
+      match request {
+        Request::ResolveGuid { .. } => respond(&mut stream, id, Reply::Path { path: String::from("logo.png") }).await,
+        Request::GetAsset { path } => respond(&mut stream, id, Reply::Asset { asset: asset(&path) }).await,
+        other => panic!("unexpected request {:?}", other),
+      }
+    }
+    stream
+  });
+  let client = Client::connect_with(&address, config()).await.unwrap();
+  assert_eq!(client.server().server, "test");
+  let (path, asset) = tokio::join!(client.resolve_guid(Uuid::from_u128(0x1234)), client.get_asset("other.png"));
+  assert_eq!(path.unwrap(), "logo.png");
+  assert_eq!(asset.unwrap().path, "other.png");
+  server.await.unwrap();
+}
+
+#[tokio::test]
+async fn server_errors_are_request_errors() {
+  let (mut listener, address) = listen().await;
+  let server = tokio::spawn(async move {
+    let mut stream = accept(&mut listener).await;
+    let (id, _) = next_request(&mut stream).await;
+    let error = RequestError::new(ErrorCode::AssetExists, "\"b.png\" already exists");
+    send(&mut stream, ServerMessage::Error { id, error }).await;
+    stream
+  });
+  let client = Client::connect_with(&address, config()).await.unwrap();
+  let err = client.move_asset("a.png", "b.png").await.unwrap_err();
+  assert_eq!(err.code(), Some(ErrorCode::AssetExists));
+  server.await.unwrap();
+}
+
+#[tokio::test]
+async fn unanswered_requests_time_out() {
+  let (mut listener, address) = listen().await;
+  let server = tokio::spawn(async move {
+    let mut stream = accept(&mut listener).await;
+    next_request(&mut stream).await;
+    stream
+  });
+  let client = Client::connect_with(&address, ClientConfig { request_timeout: Duration::from_millis(100), ..config() })
+    .await
+    .unwrap();
+  match client.ping().await {
+    Err(ClientError::Timeout) => {}
+    other => panic!("{:?}", other),
+  }
+  server.await.unwrap();
+}
+
+#[tokio::test]
+async fn requests_in_flight_fail_when_disconnected() {
+  let (mut listener, address) = listen().await;
+  let server = tokio::spawn(async move {
+    let mut stream = accept(&mut listener).await;
+    next_request(&mut stream).await;
+    drop(stream);
+    // The client comes back for the next request.
+    let mut stream = accept(&mut listener).await;
+    let (id, _) = next_request(&mut stream).await;
+    respond(&mut stream, id, Reply::Pong).await;
+    stream
+  });
+  let client = Client::connect_with(&address, config()).await.unwrap();
+  match client.delete_asset("a.png").await {
+    Err(ClientError::Disconnected) => {}
+    other => panic!("{:?}", other),
+  }
+  client.ping().await.unwrap();
+  server.await.unwrap();
+}
+
+#[tokio::test]
+async fn project_clients_follow_the_server_through_its_lock_file() {
+  let project = tempfile::tempdir().unwrap();
+  let library_path = project.path().join(LIBRARY_FOLDER);
+  match Client::connect_to_project_with(project.path(), config()).await {
+    Err(ClientError::Connect(_)) => {}
+    other => panic!("{:?}", other.map(|_| ())),
+  }
+
+  let (mut listener, address) = listen().await;
+  let mut lock = ProjectLock::acquire(&library_path, "serve").unwrap();
+  lock.set_address(address.parse().unwrap()).unwrap();
+  let server = tokio::spawn(async move {
+    let mut stream = accept(&mut listener).await;
+    let (id, _) = next_request(&mut stream).await;
+    respond(&mut stream, id, Reply::Pong).await;
+    next_request(&mut stream).await;
+  });
+  let client = Client::connect_to_project_with(project.path(), config()).await.unwrap();
+  client.ping().await.unwrap();
+
+  // The server restarts on another port, and the client finds it there.
+  drop(lock);
+  let (mut listener, address) = listen().await;
+  let mut lock = ProjectLock::acquire(&library_path, "serve").unwrap();
+  lock.set_address(address.parse().unwrap()).unwrap();
+  match client.ping().await {
+    Err(ClientError::Disconnected) => {}
+    other => panic!("{:?}", other),
+  }
+  server.await.unwrap();
+  let server = tokio::spawn(async move {
+    let mut stream = accept(&mut listener).await;
+    let (id, _) = next_request(&mut stream).await;
+    respond(&mut stream, id, Reply::Pong).await;
+    stream
+  });
+  client.ping().await.unwrap();
+  server.await.unwrap();
+}
+
+#[tokio::test]
+async fn subscriptions_resume_after_reconnecting() {
+  let (mut listener, address) = listen().await;
+  let server = tokio::spawn(async move {
+    let mut stream = accept(&mut listener).await;
+    let (id, request) = next_request(&mut stream).await;
+    assert_eq!(request, Request::Subscribe { folder: Some(String::from("ui")), types: Vec::new(), since: None });
+    respond(&mut stream, id, Reply::Subscribed { subscription: 1, cursor: 10 }).await;
+    changed(&mut stream, 1, 11).await;
+    drop(stream);
+
+    // Picks up after the last change it saw, under the new server id.
+    let mut stream = accept(&mut listener).await;
+    let (id, request) = next_request(&mut stream).await;
+    assert_eq!(request, Request::Subscribe { folder: Some(String::from("ui")), types: Vec::new(), since: Some(11) });
+    respond(&mut stream, id, Reply::Subscribed { subscription: 4, cursor: 13 }).await;
+    changed(&mut stream, 4, 12).await;
+    changed(&mut stream, 4, 13).await;
+    stream
+  });
+  let client = Client::connect_with(&address, config()).await.unwrap();
+  let mut changes = client.subscribe_changes(Some("ui"), &[]).await.unwrap();
+  for seq in 11..=13 {
+    assert_eq!(changes.next().await, Some(SubscriptionEvent::Changed(change(seq))));
+  }
+  server.await.unwrap();
+}
+
+#[tokio::test]
+async fn expired_subscriptions_start_again_and_report_missed_changes() {
+  let (mut listener, address) = listen().await;
+  let server = tokio::spawn(async move {
+    let mut stream = accept(&mut listener).await;
+    let (id, _) = next_request(&mut stream).await;
+    respond(&mut stream, id, Reply::Subscribed { subscription: 1, cursor: 10 }).await;
+    send(&mut stream, ServerMessage::Event { event: Event::SubscriptionExpired { subscription: 1 } }).await;
+
+    // The client first asks for what it missed, which the server no longer has, then starts from now.
+    let (id, request) = next_request(&mut stream).await;
+    assert_eq!(request, Request::Subscribe { folder: None, types: Vec::new(), since: Some(10) });
+    let error = RequestError::new(ErrorCode::CursorExpired, "changes after 10 are no longer known");
+    send(&mut stream, ServerMessage::Error { id, error }).await;
+    let (id, request) = next_request(&mut stream).await;
+    assert_eq!(request, Request::Subscribe { folder: None, types: Vec::new(), since: None });
+    respond(&mut stream, id, Reply::Subscribed { subscription: 2, cursor: 5000 }).await;
+    changed(&mut stream, 2, 5001).await;
+    stream
+  });
+  let client = Client::connect_with(&address, config()).await.unwrap();
+  let mut changes = client.subscribe_changes(None, &[]).await.unwrap();
+  assert_eq!(changes.next().await, Some(SubscriptionEvent::Missed));
+  assert_eq!(changes.next().await, Some(SubscriptionEvent::Changed(change(5001))));
+  server.await.unwrap();
+}
+
+#[tokio::test]
+async fn dropped_subscriptions_unsubscribe() {
+  let (mut listener, address) = listen().await;
+  let server = tokio::spawn(async move {
+    let mut stream = accept(&mut listener).await;
+    let (id, _) = next_request(&mut stream).await;
+    respond(&mut stream, id, Reply::Subscribed { subscription: 3, cursor: 0 }).await;
+    let (id, request) = next_request(&mut stream).await;
+    assert_eq!(request, Request::Unsubscribe { subscription: 3 });
+    respond(&mut stream, id, Reply::Done).await;
+    stream
+  });
+  let client = Client::connect_with(&address, config()).await.unwrap();
+  drop(client.subscribe_changes(None, &["texture"]).await.unwrap());
+  server.await.unwrap();
+}
+
+#[tokio::test]
+async fn presence_events_reach_watchers() {
+  let (mut listener, address) = listen().await;
+  let peer = Peer { id: 2, name: String::from("editor") };
+  let path = String::from("level.ron");
+  let lock = AssetLock { guid: Uuid::from_u128(0x1234), path, holder: peer, expires_in_secs: 30 };
+  let sent = lock.clone();
+  let server = tokio::spawn(async move {
+    let mut stream = accept(&mut listener).await;
+    // The watcher is registered before this is answered.
+    let (id, _) = next_request(&mut stream).await;
+    respond(&mut stream, id, Reply::Pong).await;
+    send(&mut stream, ServerMessage::Event { event: Event::AssetLocked { lock: sent } }).await;
+    drop(stream);
+    accept(&mut listener).await
+  });
+  let client = Client::connect_with(&address, config()).await.unwrap();
+  let mut presence = client.watch_presence();
+  client.ping().await.unwrap();
+  assert_eq!(presence.next().await, Some(PresenceEvent::Locked(lock)));
+  assert_eq!(presence.next().await, Some(PresenceEvent::Reconnected));
+  server.await.unwrap();
+}
+
+#[tokio::test]
+async fn game_watchers_watch_again_after_reconnecting() {
+  let (mut listener, address) = listen().await;
+  let process = GameProcess {
+    process: 1,
+    command: String::from("cargo run"),
+    running: true,
+    built: None,
+    exit_code: None,
+    diagnostics: Vec::new(),
+  };
+  let started = process.clone();
+  let server = tokio::spawn(async move {
+    let mut stream = accept(&mut listener).await;
+    let (id, request) = next_request(&mut stream).await;
+    assert_eq!(request, Request::WatchGame);
+    respond(&mut stream, id, Reply::Done).await;
+    let (id, request) = next_request(&mut stream).await;
+    assert_eq!(request, Request::RunGame { release: true, args: vec![String::from("--level"), String::from("2")] });
+    respond(&mut stream, id, Reply::Game { process: Some(started) }).await;
+    let event = Event::GameOutput { process: 1, stream: OutputStream::Stdout, line: String::from("frame 0") };
+    send(&mut stream, ServerMessage::Event { event }).await;
+    drop(stream);
+
+    let mut stream = accept(&mut listener).await;
+    let (id, request) = next_request(&mut stream).await;
+    assert_eq!(request, Request::WatchGame);
+    respond(&mut stream, id, Reply::Done).await;
+    send(&mut stream, ServerMessage::Event { event: Event::GameExited { process: 1, code: Some(0), stopped: false } })
+      .await;
+    stream
+  });
+  let client = Client::connect_with(&address, config()).await.unwrap();
+  let mut game = client.watch_game().await.unwrap();
+  assert_eq!(client.run_game(true, &["--level", "2"]).await.unwrap(), process);
+  let output = GameEvent::Output { process: 1, stream: OutputStream::Stdout, line: String::from("frame 0") };
+  assert_eq!(game.next().await, Some(output));
+  assert_eq!(game.next().await, Some(GameEvent::Reconnected));
+  assert_eq!(game.next().await, Some(GameEvent::Exited { process: 1, code: Some(0), stopped: false }));
+  server.await.unwrap();
+}